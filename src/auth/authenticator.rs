@@ -10,19 +10,70 @@ use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 
+const DEFAULT_CAPACITY: usize = 1000;
+const BLOOM_FILE: &str = "bloom.bin";
+
+// Result of checking a file on disk against what was registered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileCheck {
+    Unchanged,
+    Modified,
+    MovedFrom(String), // not registered at this path, but its content is known under this key
+}
+
 pub struct FileAuthenticator {
-    known_files: HashMap<PathBuf, HashValue>,
+    known_files: HashMap<String, HashValue>, // key (path or owner/filename) -> content hash
+    by_content: HashMap<HashValue, Vec<String>>, // content hash -> keys registered with it
     pub watch_dir: PathBuf,  // Made public
     pub bloom: BloomFilter,
 }
 
 impl FileAuthenticator {
     pub fn new(watch_dir: &Path) -> Self {
+        // Reuse a previously persisted filter if one exists
+        let bloom = std::fs::read(watch_dir.join(BLOOM_FILE)).ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok())
+            .unwrap_or_else(|| BloomFilter::new(DEFAULT_CAPACITY, 0.01));
+        
         Self {
             known_files: HashMap::new(),
+            by_content: HashMap::new(),
             watch_dir: watch_dir.to_path_buf(),
-            bloom: BloomFilter::new(1000, 0.01),
+            bloom,
+        }
+    }
+
+    pub fn unregister(&mut self, key: &str) -> Option<HashValue> {
+        // Bloom bits can't be cleared; call rebuild() to shed stale entries
+        let hash = self.known_files.remove(key)?;
+        self.forget_content(&hash, key);
+        Some(hash)
+    }
+
+    fn forget_content(&mut self, hash: &HashValue, key: &str) {
+        if let Some(keys) = self.by_content.get_mut(hash) {
+            keys.retain(|k| k != key);
+            if keys.is_empty() {
+                self.by_content.remove(hash);
+            }
+        }
+    }
+
+    // Replace the filter with a fresh one holding only the currently known keys
+    pub fn rebuild(&mut self) -> Result<()> {
+        let capacity = self.known_files.len().max(DEFAULT_CAPACITY);
+        let mut bloom = BloomFilter::new(capacity, 0.01);
+        for key in self.known_files.keys() {
+            bloom.add(key.as_bytes());
         }
+        
+        let old_rate = self.bloom.false_positive_rate();
+        self.bloom = bloom;
+        std::fs::write(self.watch_dir.join(BLOOM_FILE), bincode::serialize(&self.bloom)?)?;
+        
+        println!("🧹 bloom filter rebuilt: {} keys, fp rate {:.4} -> {:.4}",
+            self.known_files.len(), old_rate, self.bloom.false_positive_rate());
+        Ok(())
     }
 
     pub fn register(&mut self, path: &Path) -> Result<()> {
@@ -30,27 +81,96 @@ impl FileAuthenticator {
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
         
-        let hash = HashValue::compute(&data, HashAlgo::Sha256);
-        self.known_files.insert(path.to_path_buf(), hash.clone());
-        self.bloom.add(path.to_string_lossy().as_bytes());
-        
-        println!("📋 registered: {} -> {}", path.display(), hash.prefix(8));
+        self.register_bytes(&path.to_string_lossy(), &data);
         Ok(())
     }
 
-    pub fn verify(&self, path: &Path) -> Result<bool> {
-        let old_hash = self.known_files.get(path)
-            .context("file not registered")?;
+    // Register in-memory content under an arbitrary key, without touching disk
+    pub fn register_bytes(&mut self, key: &str, data: &[u8]) -> HashValue {
+        let hash = HashValue::compute(data, HashAlgo::Sha256);
+        if let Some(previous) = self.known_files.insert(key.to_string(), hash.clone()) {
+            self.forget_content(&previous, key);
+        }
+        self.by_content.entry(hash.clone()).or_default().push(key.to_string());
+        self.bloom.add(key.as_bytes());
         
+        println!("📋 registered: {} -> {}", key, hash.prefix(8));
+        hash
+    }
+
+    pub fn verify_bytes(&self, key: &str, data: &[u8]) -> Result<bool> {
+        let old_hash = self.known_files.get(key)
+            .context("file not registered")?;
+        Ok(old_hash == &HashValue::compute(data, HashAlgo::Sha256))
+    }
+
+    // A path that was never registered is still recognised if its content was,
+    // e.g. after the file was moved or renamed
+    pub fn verify(&self, path: &Path) -> Result<FileCheck> {
+        let key = path.to_string_lossy();
         let mut file = File::open(path)?;
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
-        
         let new_hash = HashValue::compute(&data, HashAlgo::Sha256);
-        Ok(old_hash == &new_hash)
+        
+        if let Some(old_hash) = self.known_files.get(key.as_ref()) {
+            return Ok(if old_hash == &new_hash { FileCheck::Unchanged } else { FileCheck::Modified });
+        }
+        
+        // Prefer a registration whose path is gone, which is what a move leaves behind
+        let keys = self.by_content.get(&new_hash).context("file not registered")?;
+        let old_key = keys.iter()
+            .find(|k| !Path::new(k.as_str()).exists())
+            .or_else(|| keys.first())
+            .context("file not registered")?;
+        Ok(FileCheck::MovedFrom(old_key.clone()))
     }
 
     pub fn quick_check(&self, path: &Path) -> bool {
         self.bloom.contains(path.to_string_lossy().as_bytes())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn rebuild_sheds_removed_keys() {
+        let dir = TempDir::new().unwrap();
+        let mut auth = FileAuthenticator::new(dir.path());
+        for i in 0..5000u32 {
+            auth.register_bytes(&format!("user/file{}", i), &i.to_le_bytes());
+        }
+        for i in 100..5000 {
+            auth.unregister(&format!("user/file{}", i));
+        }
+        let before = auth.bloom.false_positive_rate();
+        assert!(before > 0.3, "overfilled filter should be saturated, got {}", before);
+
+        auth.rebuild().unwrap();
+        let after = auth.bloom.false_positive_rate();
+        assert!(after <= 0.011, "rebuilt filter rate {}", after);
+        assert!((0..100).all(|i| auth.bloom.contains(format!("user/file{}", i).as_bytes())));
+
+        let reloaded = FileAuthenticator::new(dir.path());
+        assert_eq!(reloaded.bloom.len(), 100);
+    }
+
+    #[test]
+    fn moved_file_is_recognised_by_content() {
+        let dir = TempDir::new().unwrap();
+        let mut auth = FileAuthenticator::new(dir.path());
+        let original = dir.path().join("report.txt");
+        std::fs::write(&original, b"quarterly numbers").unwrap();
+        auth.register(&original).unwrap();
+        assert_eq!(auth.verify(&original).unwrap(), FileCheck::Unchanged);
+
+        let moved = dir.path().join("archive.txt");
+        std::fs::rename(&original, &moved).unwrap();
+        assert_eq!(auth.verify(&moved).unwrap(), FileCheck::MovedFrom(original.to_string_lossy().into_owned()));
+
+        std::fs::write(&moved, b"edited after the move").unwrap();
+        assert!(auth.verify(&moved).is_err());
+    }
+}
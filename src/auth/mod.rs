@@ -2,4 +2,6 @@
 // Authentication Module
 // ============================================================================
 
-pub mod authenticator;
\ No newline at end of file
+pub mod authenticator;
+pub mod password;
+pub mod provider;
\ No newline at end of file
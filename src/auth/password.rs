@@ -0,0 +1,102 @@
+// ============================================================================
+// Password Hashing (Argon2 with legacy SHA-256 support)
+// ============================================================================
+
+use anyhow::{anyhow, Result};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use sha2::{Digest, Sha256};
+
+use crate::error::FileSharingError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFormat {
+    LegacySha256, // 64 hex chars, unsalted
+    Argon2,       // PHC string, e.g. $argon2id$v=19$...
+}
+
+impl HashFormat {
+    // Sniff the format from the stored value
+    pub fn detect(stored: &str) -> Option<Self> {
+        if stored.starts_with("$argon2") {
+            Some(HashFormat::Argon2)
+        } else if stored.len() == 64 && stored.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Some(HashFormat::LegacySha256)
+        } else {
+            None
+        }
+    }
+}
+
+// Baseline for any new password, whatever the caller's own policy
+pub fn check_password(password: &str) -> Result<(), FileSharingError> {
+    if password.trim().is_empty() {
+        return Err(FileSharingError::WeakPassword("must not be empty or whitespace only".to_string()));
+    }
+    Ok(())
+}
+
+// New hashes are always Argon2
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("password hashing failed: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    match HashFormat::detect(stored) {
+        Some(HashFormat::Argon2) => PasswordHash::new(stored)
+            .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+            .unwrap_or(false),
+        Some(HashFormat::LegacySha256) => {
+            hex::encode(Sha256::digest(password.as_bytes())).eq_ignore_ascii_case(stored)
+        }
+        None => false,
+    }
+}
+
+// Stored hashes that should be rewritten after the next successful login
+pub fn needs_upgrade(stored: &str) -> bool {
+    HashFormat::detect(stored) != Some(HashFormat::Argon2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY: &str = "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8"; // "password"
+
+    #[test]
+    fn legacy_sha256_hash_verifies() {
+        assert_eq!(HashFormat::detect(LEGACY), Some(HashFormat::LegacySha256));
+        assert!(verify_password("password", LEGACY));
+        assert!(verify_password("password", &LEGACY.to_uppercase()));
+        assert!(!verify_password("Password", LEGACY));
+        assert!(needs_upgrade(LEGACY));
+    }
+
+    #[test]
+    fn argon2_hash_verifies_and_needs_no_upgrade() {
+        let stored = hash_password("password").unwrap();
+        assert_eq!(HashFormat::detect(&stored), Some(HashFormat::Argon2));
+        assert!(verify_password("password", &stored));
+        assert!(!verify_password("wrong", &stored));
+        assert!(!needs_upgrade(&stored));
+    }
+
+    #[test]
+    fn unknown_format_never_verifies() {
+        assert_eq!(HashFormat::detect("plaintext"), None);
+        assert!(!verify_password("plaintext", "plaintext"));
+    }
+
+    #[test]
+    fn blank_passwords_fail_the_baseline_check() {
+        assert!(matches!(check_password(""), Err(FileSharingError::WeakPassword(_))));
+        assert!(matches!(check_password(" \t\n"), Err(FileSharingError::WeakPassword(_))));
+        assert!(check_password(" padded secret ").is_ok());
+    }
+}
@@ -0,0 +1,88 @@
+// ============================================================================
+// Auth Provider (pluggable identity store: local DB, LDAP, OIDC, ...)
+// ============================================================================
+
+use crate::auth::password;
+use crate::db::{Database, User};
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    // Some(user) only for valid credentials. Checking them isn't a login, so
+    // last_login is the caller's to update.
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>>;
+    async fn get_user(&self, username: &str) -> Result<Option<User>>;
+    async fn register(&self, username: &str, password: &str, email: Option<&str>) -> Result<User>;
+}
+
+// Default provider backed by the local users table
+pub struct DatabaseAuthProvider {
+    database: Database,
+}
+
+impl DatabaseAuthProvider {
+    pub fn new(database: Database) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for DatabaseAuthProvider {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>> {
+        let mut user = match self.database.get_user_by_username(username).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        if !password::verify_password(password, &user.password_hash) {
+            return Ok(None);
+        }
+        
+        // Move legacy SHA-256 hashes to Argon2 while the plaintext is at hand
+        if password::needs_upgrade(&user.password_hash) {
+            user.password_hash = password::hash_password(password)?;
+            self.database.update_password(user.id, &user.password_hash).await?;
+            println!("🔐 Password hash upgraded to Argon2: {}", username);
+        }
+        
+        Ok(Some(user))
+    }
+    
+    async fn get_user(&self, username: &str) -> Result<Option<User>> {
+        self.database.get_user_by_username(username).await
+    }
+    
+    async fn register(&self, username: &str, password: &str, email: Option<&str>) -> Result<User> {
+        let password_hash = password::hash_password(password)?;
+        self.database.create_user(username, &password_hash, email).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::password::HashFormat;
+    use crate::db::DatabaseConfig;
+    use tempfile::TempDir;
+    
+    #[tokio::test]
+    async fn legacy_hash_is_upgraded_on_login() {
+        let dir = TempDir::new().unwrap();
+        let database = Database::with_config(DatabaseConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..DatabaseConfig::default()
+        }).await.unwrap();
+        // SHA-256 of "password", as the old scheme stored it
+        database.create_user("alice", "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8", None)
+            .await.unwrap();
+        let provider = DatabaseAuthProvider::new(database.clone());
+        
+        assert!(provider.authenticate("alice", "wrong").await.unwrap().is_none());
+        provider.authenticate("alice", "password").await.unwrap().unwrap();
+        
+        let stored = database.get_user_by_username("alice").await.unwrap().unwrap().password_hash;
+        assert_eq!(HashFormat::detect(&stored), Some(HashFormat::Argon2));
+        assert!(provider.authenticate("alice", "password").await.unwrap().is_some());
+        assert!(provider.authenticate("alice", "wrong").await.unwrap().is_none());
+    }
+}
@@ -2,10 +2,16 @@
 // File Metadata Structures
 // ============================================================================
 
-use crate::crypto::hash::HashValue;
+use crate::core::merkle_tree::DEFAULT_ARITY;
+use crate::crypto::hash::{HashAlgo, HashValue};
+use crate::error::FileSharingError;
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+// Bumped whenever the on-disk layout of FileMetadata changes. Files written
+// before the field existed read as version 1.
+pub const METADATA_FORMAT_VERSION: u32 = 2;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileMetadata {
@@ -13,10 +19,71 @@ pub struct FileMetadata {
     pub size: u64,
     pub hash: HashValue,
     pub chunks: Vec<HashValue>,
+    #[serde(default)]
+    pub chunk_sizes: Vec<u64>, // empty in metadata written before sizes were recorded
     pub merkle_root: HashValue,
+    #[serde(default = "default_arity")]
+    pub merkle_arity: usize, // tree arity the root was built with
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
     pub owner: String,
+    #[serde(default = "legacy_format_version")]
+    pub format_version: u32, // kept last: bincode is positional, see FileMetadataV1
+}
+
+fn default_arity() -> usize {
+    DEFAULT_ARITY
+}
+
+fn legacy_format_version() -> u32 {
+    1
+}
+
+// Version 1 layout. JSON fills missing fields from serde defaults, but bincode
+// has no field names, so old .metab files are decoded through this instead.
+#[derive(Deserialize)]
+struct FileMetadataV1 {
+    path: PathBuf,
+    size: u64,
+    hash: HashValue,
+    chunks: Vec<HashValue>,
+    chunk_sizes: Vec<u64>,
+    merkle_root: HashValue,
+    merkle_arity: usize,
+    created_at: DateTime<Utc>,
+    modified_at: DateTime<Utc>,
+    owner: String,
+}
+
+impl From<FileMetadataV1> for FileMetadata {
+    fn from(v1: FileMetadataV1) -> Self {
+        Self {
+            path: v1.path,
+            size: v1.size,
+            hash: v1.hash,
+            chunks: v1.chunks,
+            chunk_sizes: v1.chunk_sizes,
+            merkle_root: v1.merkle_root,
+            merkle_arity: v1.merkle_arity,
+            created_at: v1.created_at,
+            modified_at: v1.modified_at,
+            owner: v1.owner,
+            format_version: 1,
+        }
+    }
+}
+
+// Name a local file is uploaded under. Names are stored as text, so a non-UTF-8
+// name is refused rather than mangled by a lossy conversion, which could also
+// make two different files look identical.
+pub fn upload_name(path: &Path) -> Result<String, FileSharingError> {
+    let name = path.file_name()
+        .ok_or_else(|| FileSharingError::InvalidFilename(format!("{} has no file name", path.display())))?;
+    name.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| FileSharingError::InvalidFilename(
+            format!("{} is not valid UTF-8", name.to_string_lossy())
+        ))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,4 +91,116 @@ pub struct FileChunk {
     pub index: usize,
     pub hash: HashValue,
     pub data: Vec<u8>,
-}
\ No newline at end of file
+}
+
+impl FileMetadata {
+    // Current layout first: a newer file's bytes would also decode as a V1 prefix
+    pub fn from_bincode(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize::<FileMetadata>(bytes)
+            .or_else(|_| bincode::deserialize::<FileMetadataV1>(bytes).map(FileMetadata::from))
+    }
+
+    // Chunks carry their own algorithm, which may differ from the whole-file hash
+    pub fn chunk_algo(&self) -> HashAlgo {
+        self.chunks.first().map(|c| c.algo).unwrap_or(HashAlgo::Sha256)
+    }
+
+    // Split data the same way it was chunked when stored
+    pub fn split<'a>(&self, data: &'a [u8], default_chunk_size: usize) -> Vec<&'a [u8]> {
+        if self.chunk_sizes.is_empty() {
+            return data.chunks(default_chunk_size).collect();
+        }
+
+        let mut parts = Vec::with_capacity(self.chunk_sizes.len());
+        let mut offset = 0usize;
+        for &size in &self.chunk_sizes {
+            let end = (offset + size as usize).min(data.len());
+            parts.push(&data[offset..end]);
+            offset = end;
+        }
+        if offset < data.len() {
+            parts.push(&data[offset..]);
+        }
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current() -> FileMetadata {
+        let chunk = HashValue::compute(b"chunk", HashAlgo::Sha256);
+        let now = Utc::now();
+        FileMetadata {
+            path: PathBuf::from("f.txt"),
+            size: 5,
+            hash: HashValue::compute(b"chunk", HashAlgo::Sha256),
+            chunks: vec![chunk.clone()],
+            chunk_sizes: vec![5],
+            merkle_root: chunk,
+            merkle_arity: 4,
+            created_at: now,
+            modified_at: now,
+            owner: "alice".to_string(),
+            format_version: METADATA_FORMAT_VERSION,
+        }
+    }
+
+    #[test]
+    fn v1_json_without_newer_fields_still_loads() {
+        let meta = current();
+        let v1 = serde_json::json!({
+            "path": "f.txt",
+            "size": 5,
+            "hash": meta.hash,
+            "chunks": meta.chunks,
+            "merkle_root": meta.merkle_root,
+            "created_at": meta.created_at,
+            "modified_at": meta.modified_at,
+            "owner": "alice",
+        });
+        let loaded: FileMetadata = serde_json::from_value(v1).unwrap();
+        assert_eq!(loaded.format_version, 1);
+        assert_eq!(loaded.merkle_arity, DEFAULT_ARITY);
+        assert!(loaded.chunk_sizes.is_empty());
+        assert_eq!(loaded.hash, meta.hash);
+    }
+
+    #[test]
+    fn v1_bincode_decodes_through_the_legacy_layout() {
+        let meta = current();
+        // bincode has no field names, so a tuple in field order has the V1 layout
+        let v1 = bincode::serialize(&(
+            &meta.path, meta.size, &meta.hash, &meta.chunks, &meta.chunk_sizes, &meta.merkle_root,
+            meta.merkle_arity, meta.created_at, meta.modified_at, &meta.owner,
+        )).unwrap();
+        let loaded = FileMetadata::from_bincode(&v1).unwrap();
+        assert_eq!(loaded.format_version, 1);
+        assert_eq!(loaded.merkle_arity, 4);
+        assert_eq!(loaded.owner, "alice");
+    }
+
+    #[test]
+    fn current_format_round_trips() {
+        let meta = current();
+        let json: FileMetadata = serde_json::from_slice(&serde_json::to_vec(&meta).unwrap()).unwrap();
+        let binary = FileMetadata::from_bincode(&bincode::serialize(&meta).unwrap()).unwrap();
+        for loaded in [json, binary] {
+            assert_eq!(loaded.format_version, METADATA_FORMAT_VERSION);
+            assert_eq!(loaded.chunk_sizes, meta.chunk_sizes);
+            assert_eq!(loaded.merkle_arity, 4);
+            assert_eq!(loaded.modified_at, meta.modified_at);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_rejected_not_mangled() {
+        use std::os::unix::ffi::OsStrExt;
+        let bad = Path::new(std::ffi::OsStr::from_bytes(b"/tmp/report\xff.txt"));
+        assert!(matches!(upload_name(bad), Err(FileSharingError::InvalidFilename(_))));
+        assert_eq!(upload_name(Path::new("/tmp/résumé.txt")).unwrap(), "résumé.txt");
+        assert!(upload_name(Path::new("/")).is_err());
+    }
+}
@@ -4,27 +4,47 @@
 
 use crate::crypto::hash::{HashAlgo, HashValue};
 
+pub const DEFAULT_ARITY: usize = 2;
+
 #[derive(Debug, Clone)]
 pub struct MerkleTree {
     root: HashValue,
     leaves: Vec<HashValue>,
     levels: Vec<Vec<HashValue>>,
+    arity: usize,
 }
 
-#[derive(Debug, Clone)]
+// The other children of one internal node, and where the proven hash sits among them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofStep {
+    pub position: usize,
+    pub siblings: Vec<HashValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MerkleProof {
     leaf_hash: HashValue,
-    siblings: Vec<(HashValue, bool)>, // (hash, is_right)
+    steps: Vec<ProofStep>,
     root_hash: HashValue,
+    arity: usize,
 }
 
 impl MerkleTree {
     pub fn new(leaves: &[HashValue]) -> Self {
+        Self::with_arity(leaves, DEFAULT_ARITY)
+    }
+
+    // Each internal node hashes up to `arity` children with the leaves' algorithm.
+    // A short last group is padded by repeating its last child, which for arity 2
+    // is the classic "duplicate the odd node" rule.
+    pub fn with_arity(leaves: &[HashValue], arity: usize) -> Self {
+        let arity = arity.max(2);
         if leaves.is_empty() {
             return Self {
                 root: HashValue::compute(b"", HashAlgo::Sha256),
                 leaves: vec![],
                 levels: vec![],
+                arity,
             };
         }
 
@@ -32,19 +52,9 @@ impl MerkleTree {
         let mut current = leaves.to_vec();
 
         while current.len() > 1 {
-            let mut next = Vec::new();
-            for pair in current.chunks(2) {
-                let combined = if pair.len() == 2 {
-                    let mut bytes = pair[0].bytes.clone();
-                    bytes.extend(&pair[1].bytes);
-                    HashValue::compute(&bytes, HashAlgo::Sha256)
-                } else {
-                    let mut bytes = pair[0].bytes.clone();
-                    bytes.extend(&pair[0].bytes);
-                    HashValue::compute(&bytes, HashAlgo::Sha256)
-                };
-                next.push(combined);
-            }
+            let next: Vec<HashValue> = current.chunks(arity)
+                .map(|group| Self::combine(group, arity))
+                .collect();
             levels.push(next.clone());
             current = next;
         }
@@ -53,51 +63,193 @@ impl MerkleTree {
             root: current[0].clone(),
             leaves: leaves.to_vec(),
             levels,
+            arity,
+        }
+    }
+
+    fn combine(group: &[HashValue], arity: usize) -> HashValue {
+        let mut bytes = Vec::new();
+        for child in group {
+            bytes.extend(&child.bytes);
+        }
+        let last = &group[group.len() - 1];
+        for _ in group.len()..arity {
+            bytes.extend(&last.bytes);
         }
+        HashValue::compute(&bytes, last.algo)
+    }
+
+    pub fn root(&self) -> HashValue {
+        self.root.clone()
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
     }
 
-    pub fn root(&self) -> HashValue { 
-        self.root.clone() 
+    // Number of levels above the leaves (0 for a single leaf)
+    pub fn height(&self) -> usize {
+        self.levels.len().saturating_sub(1)
     }
 
     pub fn generate_proof(&self, leaf_idx: usize) -> Option<MerkleProof> {
-        if leaf_idx >= self.leaves.len() { 
-            return None; 
+        if leaf_idx >= self.leaves.len() {
+            return None;
         }
-        
-        let mut siblings = Vec::new();
+
+        let mut steps = Vec::new();
         let mut idx = leaf_idx;
-        
-        for level in 0..self.levels.len()-1 {
-            let sibling_idx = if idx % 2 == 0 { idx + 1 } else { idx - 1 };
-            if sibling_idx < self.levels[level].len() {
-                let is_right = idx % 2 == 0;
-                siblings.push((self.levels[level][sibling_idx].clone(), is_right));
-            }
-            idx /= 2;
+
+        for level in &self.levels[..self.levels.len() - 1] {
+            let start = idx - idx % self.arity;
+            let end = (start + self.arity).min(level.len());
+            steps.push(ProofStep {
+                position: idx - start,
+                siblings: (start..end).filter(|&i| i != idx).map(|i| level[i].clone()).collect(),
+            });
+            idx /= self.arity;
         }
-        
+
         Some(MerkleProof {
             leaf_hash: self.leaves[leaf_idx].clone(),
-            siblings,
+            steps,
             root_hash: self.root.clone(),
+            arity: self.arity,
         })
     }
 
+    // Proofs for every leaf, in leaf order. Each node's step is built once per
+    // level and shared by all leaves under it, instead of walking the tree per leaf.
+    pub fn generate_all_proofs(&self) -> Vec<MerkleProof> {
+        let inner = &self.levels[..self.levels.len().saturating_sub(1)];
+        let level_steps: Vec<Vec<ProofStep>> = inner.iter()
+            .map(|level| {
+                (0..level.len()).map(|idx| {
+                    let start = idx - idx % self.arity;
+                    let end = (start + self.arity).min(level.len());
+                    ProofStep {
+                        position: idx - start,
+                        siblings: (start..end).filter(|&i| i != idx).map(|i| level[i].clone()).collect(),
+                    }
+                }).collect()
+            })
+            .collect();
+
+        self.leaves.iter().enumerate()
+            .map(|(leaf_idx, leaf)| {
+                let mut idx = leaf_idx;
+                let steps = level_steps.iter()
+                    .map(|steps| {
+                        let step = steps[idx].clone();
+                        idx /= self.arity;
+                        step
+                    })
+                    .collect();
+                MerkleProof {
+                    leaf_hash: leaf.clone(),
+                    steps,
+                    root_hash: self.root.clone(),
+                    arity: self.arity,
+                }
+            })
+            .collect()
+    }
+
+    // Only proves the leaf is under the root the proof itself carries; use
+    // verify_proof_against when the root comes from somewhere trusted
     pub fn verify_proof(proof: &MerkleProof) -> bool {
+        Self::verify_proof_against(proof, &proof.root_hash)
+    }
+
+    // Recompute from the leaf up and compare with the caller's root, ignoring the embedded one
+    pub fn verify_proof_against(proof: &MerkleProof, trusted_root: &HashValue) -> bool {
         let mut current = proof.leaf_hash.clone();
-        for (sibling, is_right) in &proof.siblings {
-            let combined = if *is_right {
-                let mut bytes = current.bytes.clone();
-                bytes.extend(&sibling.bytes);
-                bytes
-            } else {
-                let mut bytes = sibling.bytes.clone();
-                bytes.extend(&current.bytes);
-                bytes
-            };
-            current = HashValue::compute(&combined, HashAlgo::Sha256);
+        for step in &proof.steps {
+            if step.position > step.siblings.len() || step.siblings.len() >= proof.arity {
+                return false;
+            }
+            let mut group = step.siblings.clone();
+            group.insert(step.position, current);
+            current = Self::combine(&group, proof.arity);
+        }
+        current == *trusted_root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<HashValue> {
+        (0..count as u32).map(|i| HashValue::compute(&i.to_le_bytes(), HashAlgo::Sha256)).collect()
+    }
+
+    #[test]
+    fn every_proof_verifies_under_arity_2_and_4() {
+        let leaves = leaves(21);
+        for arity in [2, 4] {
+            let tree = MerkleTree::with_arity(&leaves, arity);
+            assert_eq!(tree.arity(), arity);
+            for (i, proof) in tree.generate_all_proofs().iter().enumerate() {
+                assert!(MerkleTree::verify_proof_against(proof, &tree.root()), "arity {} leaf {}", arity, i);
+                let single = tree.generate_proof(i).unwrap();
+                assert!(MerkleTree::verify_proof_against(&single, &tree.root()), "arity {} leaf {}", arity, i);
+                assert_eq!(single.steps.len(), tree.height());
+            }
+        }
+    }
+
+    #[test]
+    fn arity_4_tree_is_shallower_with_a_different_root() {
+        let leaves = leaves(64);
+        let binary = MerkleTree::new(&leaves);
+        let quad = MerkleTree::with_arity(&leaves, 4);
+        assert_eq!(binary.height(), 6);
+        assert_eq!(quad.height(), 3);
+        assert_ne!(binary.root(), quad.root());
+    }
+
+    #[test]
+    fn proof_fails_for_a_wrong_leaf_or_root() {
+        let leaves = leaves(9);
+        let tree = MerkleTree::with_arity(&leaves, 4);
+        let mut proof = tree.generate_proof(5).unwrap();
+        let other = MerkleTree::with_arity(&leaves, 2);
+        assert!(!MerkleTree::verify_proof_against(&proof, &other.root()));
+
+        proof.leaf_hash = leaves[6].clone();
+        assert!(!MerkleTree::verify_proof_against(&proof, &tree.root()));
+    }
+
+    #[test]
+    fn trusted_root_ignores_a_tampered_embedded_root() {
+        let leaves = leaves(8);
+        let tree = MerkleTree::new(&leaves);
+        let mut proof = tree.generate_proof(3).unwrap();
+
+        // A forged leaf with a root rebuilt to match passes the self-contained check
+        let forged = HashValue::compute(b"forged", HashAlgo::Sha256);
+        let mut forged_leaves = leaves.clone();
+        forged_leaves[3] = forged.clone();
+        proof.leaf_hash = forged;
+        proof.root_hash = MerkleTree::new(&forged_leaves).root();
+        assert!(MerkleTree::verify_proof(&proof));
+        assert!(!MerkleTree::verify_proof_against(&proof, &tree.root()));
+
+        let honest = tree.generate_proof(3).unwrap();
+        assert!(MerkleTree::verify_proof_against(&honest, &tree.root()));
+    }
+
+    #[test]
+    fn all_proofs_match_individual_proofs() {
+        for (count, arity) in [(16, 2), (16, 4), (7, 3), (1, 2)] {
+            let tree = MerkleTree::with_arity(&leaves(count), arity);
+            let all = tree.generate_all_proofs();
+            assert_eq!(all.len(), count);
+            for (i, proof) in all.iter().enumerate() {
+                assert_eq!(Some(proof), tree.generate_proof(i).as_ref(), "leaf {} of {} (arity {})", i, count, arity);
+                assert!(MerkleTree::verify_proof(proof));
+            }
         }
-        current == proof.root_hash
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,60 @@
+// ============================================================================
+// MIME Type Detection
+// ============================================================================
+
+use std::path::Path;
+
+pub const DEFAULT_MIME: &str = "application/octet-stream";
+
+// Magic bytes win over the extension, so a mislabelled file is still typed correctly
+pub fn detect_mime(filename: &str, data: &[u8]) -> String {
+    if let Some(kind) = infer::get(data) {
+        return kind.mime_type().to_string();
+    }
+
+    let ext = Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    let mime = match ext.as_deref() {
+        Some("txt") | Some("log") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("csv") => "text/csv",
+        Some("html") | Some("htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("yaml") | Some("yml") => "application/yaml",
+        Some("toml") => "application/toml",
+        Some("svg") => "image/svg+xml",
+        _ => DEFAULT_MIME,
+    };
+    mime.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn detects_png_and_text() {
+        assert_eq!(detect_mime("photo.png", PNG_HEADER), "image/png");
+        assert_eq!(detect_mime("notes.txt", b"plain words"), "text/plain");
+        assert_eq!(detect_mime("NOTES.TXT", b"plain words"), "text/plain");
+    }
+
+    #[test]
+    fn content_beats_a_wrong_extension() {
+        assert_eq!(detect_mime("not-really.txt", PNG_HEADER), "image/png");
+    }
+
+    #[test]
+    fn unknown_content_defaults_to_octet_stream() {
+        assert_eq!(detect_mime("blob", b"\x01\x02\x03"), DEFAULT_MIME);
+        assert_eq!(detect_mime("data.weird", b"\x01\x02\x03"), DEFAULT_MIME);
+    }
+}
@@ -3,4 +3,5 @@
 // ============================================================================
 
 pub mod file_metadata;
-pub mod merkle_tree;
\ No newline at end of file
+pub mod merkle_tree;
+pub mod mime;
\ No newline at end of file
@@ -3,8 +3,49 @@
 // ============================================================================
 
 use super::hash::{HashAlgo, HashValue};
+use super::pedersen::PedersenCommitment;
+use anyhow::Result;
 use serde::{Serialize, Deserialize};
 
+pub const DEFAULT_NONCE_LEN: usize = 32;
+pub const MIN_NONCE_LEN: usize = 16;
+
+// Which scheme produced a stored commitment, so verification can dispatch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(rename_all = "lowercase")]
+pub enum CommitmentKind {
+    #[default]
+    Hash,     // SHA3-256(secret || nonce)
+    Pedersen, // Ristretto, additively homomorphic
+}
+
+pub trait CommitmentScheme: Sized + Serialize + for<'de> Deserialize<'de> {
+    const KIND: CommitmentKind;
+
+    fn commit(secret: &[u8]) -> Self;
+    fn verify(&self, secret: &[u8]) -> bool;
+}
+
+// Commit with the chosen scheme, returning the bytes to store alongside `kind`
+pub fn commit_bytes(kind: CommitmentKind, secret: &[u8]) -> Result<Vec<u8>> {
+    Ok(match kind {
+        CommitmentKind::Hash => bincode::serialize(&Commitment::commit(secret))?,
+        CommitmentKind::Pedersen => bincode::serialize(&PedersenCommitment::commit(secret))?,
+    })
+}
+
+// Verify stored bytes under the scheme they were recorded with; bytes that don't
+// decode as that scheme never verify
+pub fn verify_bytes(kind: CommitmentKind, bytes: &[u8], secret: &[u8]) -> bool {
+    fn check<C: CommitmentScheme>(bytes: &[u8], secret: &[u8]) -> bool {
+        bincode::deserialize::<C>(bytes).is_ok_and(|c| c.verify(secret))
+    }
+    match kind {
+        CommitmentKind::Hash => check::<Commitment>(bytes, secret),
+        CommitmentKind::Pedersen => check::<PedersenCommitment>(bytes, secret),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Commitment {
     hash: HashValue,
@@ -13,15 +54,24 @@ pub struct Commitment {
 
 impl Commitment {
     pub fn commit(secret: &[u8]) -> Self {
+        Self::commit_with_nonce_len(secret, DEFAULT_NONCE_LEN)
+            .expect("default nonce length is valid")
+    }
+
+    pub fn commit_with_nonce_len(secret: &[u8], len: usize) -> Result<Self> {
         use rand::RngCore;
         
-        let mut nonce = vec![0u8; 32];
+        if len < MIN_NONCE_LEN {
+            anyhow::bail!("nonce length {} is below the minimum of {} bytes", len, MIN_NONCE_LEN);
+        }
+        
+        let mut nonce = vec![0u8; len];
         rand::thread_rng().fill_bytes(&mut nonce);
         
         let mut combined = secret.to_vec();
         combined.extend(&nonce);
         let hash = HashValue::compute(&combined, HashAlgo::Sha3_256);
-        Self { hash, nonce }
+        Ok(Self { hash, nonce })
     }
 
     pub fn verify(&self, secret: &[u8]) -> bool {
@@ -34,4 +84,54 @@ impl Commitment {
     pub fn hash(&self) -> &HashValue { 
         &self.hash 
     }
-}
\ No newline at end of file
+
+    pub fn nonce_len(&self) -> usize {
+        self.nonce.len()
+    }
+}
+
+impl CommitmentScheme for Commitment {
+    const KIND: CommitmentKind = CommitmentKind::Hash;
+
+    fn commit(secret: &[u8]) -> Self {
+        Commitment::commit(secret)
+    }
+
+    fn verify(&self, secret: &[u8]) -> bool {
+        Commitment::verify(self, secret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_nonce_lengths_verify() {
+        for len in [16, 48] {
+            let commitment = Commitment::commit_with_nonce_len(b"secret", len).unwrap();
+            assert_eq!(commitment.nonce_len(), len);
+            assert!(commitment.verify(b"secret"));
+            assert!(!commitment.verify(b"other"));
+
+            let stored = bincode::serialize(&commitment).unwrap();
+            assert!(verify_bytes(CommitmentKind::Hash, &stored, b"secret"));
+        }
+        assert_eq!(Commitment::commit(b"secret").nonce_len(), DEFAULT_NONCE_LEN);
+    }
+
+    #[test]
+    fn short_nonce_is_rejected() {
+        assert!(Commitment::commit_with_nonce_len(b"secret", MIN_NONCE_LEN - 1).is_err());
+        assert!(Commitment::commit_with_nonce_len(b"secret", 0).is_err());
+    }
+
+    #[test]
+    fn schemes_never_verify_each_others_bytes() {
+        let hash = commit_bytes(CommitmentKind::Hash, b"secret").unwrap();
+        let pedersen = commit_bytes(CommitmentKind::Pedersen, b"secret").unwrap();
+        assert!(verify_bytes(CommitmentKind::Hash, &hash, b"secret"));
+        assert!(!verify_bytes(CommitmentKind::Pedersen, &hash, b"secret"));
+        assert!(!verify_bytes(CommitmentKind::Hash, &pedersen, b"secret"));
+    }
+}
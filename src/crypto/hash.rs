@@ -2,7 +2,10 @@
 // Hash Functions Core Module
 // ============================================================================
 
+use crate::error::FileSharingError;
 use serde::{Serialize, Deserialize};
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum HashAlgo {
@@ -10,15 +13,107 @@ pub enum HashAlgo {
     Sha512,    // 64 bytes - Fast on 64-bit
     Sha3_256,  // 32 bytes - Length extension attack resistant
     Sha3_512,  // 64 bytes - High security
+    Blake3,    // 32 bytes - Very fast, parallel-friendly
 }
 
+impl HashAlgo {
+    pub const ALL: [HashAlgo; 5] = [
+        HashAlgo::Sha256, HashAlgo::Sha512, HashAlgo::Sha3_256, HashAlgo::Sha3_512, HashAlgo::Blake3,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Sha512 => "sha512",
+            HashAlgo::Sha3_256 => "sha3-256",
+            HashAlgo::Sha3_512 => "sha3-512",
+            HashAlgo::Blake3 => "blake3",
+        }
+    }
+
+    pub fn digest_len(&self) -> usize {
+        match self {
+            HashAlgo::Sha256 | HashAlgo::Sha3_256 | HashAlgo::Blake3 => 32,
+            HashAlgo::Sha512 | HashAlgo::Sha3_512 => 64,
+        }
+    }
+
+    // Collision resistance in bits (half the digest size)
+    pub fn strength_bits(&self) -> usize {
+        self.digest_len() * 4
+    }
+
+    // Algorithm named by SFS_DEFAULT_HASH, or SHA-256 when unset
+    pub fn from_env() -> Result<Self, FileSharingError> {
+        match std::env::var(DEFAULT_HASH_ENV) {
+            Ok(name) => name.parse(),
+            Err(_) => Ok(HashAlgo::Sha256),
+        }
+    }
+}
+
+pub const DEFAULT_HASH_ENV: &str = "SFS_DEFAULT_HASH";
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Accepts the `as_str` names, case-insensitively
+impl FromStr for HashAlgo {
+    type Err = FileSharingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        HashAlgo::ALL.into_iter()
+            .find(|a| a.as_str().eq_ignore_ascii_case(name))
+            .ok_or_else(|| FileSharingError::UnknownHashAlgo(name.to_string()))
+    }
+}
+
+impl TryFrom<&str> for HashAlgo {
+    type Error = FileSharingError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+// Deserialization goes through `from_bytes`, so a stored or received hash
+// with the wrong digest length is rejected instead of flowing into the system
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "RawHashValue")]
 pub struct HashValue {
     pub algo: HashAlgo,
     pub bytes: Vec<u8>,
 }
 
+#[derive(Deserialize)]
+struct RawHashValue {
+    algo: HashAlgo,
+    bytes: Vec<u8>,
+}
+
+impl TryFrom<RawHashValue> for HashValue {
+    type Error = FileSharingError;
+
+    fn try_from(raw: RawHashValue) -> Result<Self, Self::Error> {
+        HashValue::from_bytes(raw.algo, raw.bytes)
+    }
+}
+
 impl HashValue {
+    // Build from a raw digest, checking its length against the algorithm
+    pub fn from_bytes(algo: HashAlgo, bytes: Vec<u8>) -> Result<Self, FileSharingError> {
+        if bytes.len() != algo.digest_len() {
+            return Err(FileSharingError::InvalidHash(format!(
+                "{} digest must be {} bytes, got {}", algo.as_str(), algo.digest_len(), bytes.len()
+            )));
+        }
+        Ok(Self { algo, bytes })
+    }
+
     pub fn compute(data: &[u8], algo: HashAlgo) -> Self {
         match algo {
             HashAlgo::Sha256 => {
@@ -37,6 +132,9 @@ impl HashValue {
                 use sha3::Digest;
                 Self { algo, bytes: sha3::Sha3_512::digest(data).to_vec() }
             },
+            HashAlgo::Blake3 => {
+                Self { algo, bytes: blake3::hash(data).as_bytes().to_vec() }
+            },
         }
     }
 
@@ -51,4 +149,104 @@ impl HashValue {
     pub fn size(&self) -> usize { 
         self.bytes.len() 
     }
-}
\ No newline at end of file
+}
+
+// Rendered as `algo:hex`, e.g. `sha256:ab12...`
+impl fmt::Display for HashValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algo.as_str(), self.to_hex())
+    }
+}
+
+impl FromStr for HashValue {
+    type Err = FileSharingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |msg: &str| FileSharingError::InvalidHash(format!("{}: {}", msg, s));
+
+        let (name, hex_part) = s.trim().split_once(':')
+            .ok_or_else(|| invalid("expected algo:hex"))?;
+        let algo: HashAlgo = name.parse()
+            .map_err(|_| invalid("unknown hash algorithm"))?;
+        let bytes = hex::decode(hex_part)
+            .map_err(|_| invalid("invalid hex digest"))?;
+
+        Self::from_bytes(algo, bytes).map_err(|_| invalid("wrong digest length"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_and_parse_round_trip() {
+        for algo in HashAlgo::ALL {
+            let hash = HashValue::compute(b"round trip", algo);
+            let text = hash.to_string();
+            assert!(text.starts_with(&format!("{}:", algo.as_str())));
+            assert_eq!(text.parse::<HashValue>().unwrap(), hash);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_malformed_input() {
+        let sha256_hex = HashValue::compute(b"x", HashAlgo::Sha256).to_hex();
+        for input in [
+            sha256_hex.clone(),                       // no algorithm prefix
+            format!("md5:{}", sha256_hex),            // unknown algorithm
+            format!("sha256:{}zz", &sha256_hex[2..]), // not hex
+            format!("sha512:{}", sha256_hex),         // wrong digest length
+            "sha256:".to_string(),
+        ] {
+            assert!(matches!(input.parse::<HashValue>(), Err(FileSharingError::InvalidHash(_))), "{}", input);
+        }
+    }
+
+    #[test]
+    fn algo_names_parse_case_insensitively() {
+        for (name, algo) in [
+            ("sha256", HashAlgo::Sha256),
+            ("sha512", HashAlgo::Sha512),
+            ("sha3-256", HashAlgo::Sha3_256),
+            ("sha3-512", HashAlgo::Sha3_512),
+            ("blake3", HashAlgo::Blake3),
+        ] {
+            assert_eq!(name.parse::<HashAlgo>().unwrap(), algo);
+            assert_eq!(name.to_uppercase().parse::<HashAlgo>().unwrap(), algo);
+            assert_eq!(HashAlgo::try_from(format!(" {} ", name).as_str()).unwrap(), algo);
+            assert_eq!(algo.to_string(), name);
+        }
+    }
+
+    #[test]
+    fn unknown_algo_name_is_an_error() {
+        for name in ["md5", "sha3_256", ""] {
+            assert!(matches!(name.parse::<HashAlgo>(), Err(FileSharingError::UnknownHashAlgo(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn default_algo_comes_from_the_environment() {
+        std::env::set_var(DEFAULT_HASH_ENV, "SHA3-512");
+        assert_eq!(HashAlgo::from_env().unwrap(), HashAlgo::Sha3_512);
+        std::env::set_var(DEFAULT_HASH_ENV, "whirlpool");
+        assert!(HashAlgo::from_env().is_err());
+        std::env::remove_var(DEFAULT_HASH_ENV);
+        assert_eq!(HashAlgo::from_env().unwrap(), HashAlgo::Sha256);
+    }
+
+    #[test]
+    fn short_digest_is_rejected_at_construction_and_deserialization() {
+        assert!(HashValue::from_bytes(HashAlgo::Sha256, vec![0u8; 10]).is_err());
+        assert!(HashValue::from_bytes(HashAlgo::Sha256, vec![0u8; 32]).is_ok());
+
+        let short = serde_json::json!({ "algo": "Sha256", "bytes": [1, 2, 3] });
+        assert!(serde_json::from_value::<HashValue>(short).is_err());
+        let good = HashValue::compute(b"data", HashAlgo::Sha512);
+        let encoded = bincode::serialize(&good).unwrap();
+        assert_eq!(bincode::deserialize::<HashValue>(&encoded).unwrap(), good);
+        let forged = bincode::serialize(&HashValue { algo: HashAlgo::Sha512, bytes: vec![0u8; 10] }).unwrap();
+        assert!(bincode::deserialize::<HashValue>(&forged).is_err());
+    }
+}
@@ -3,4 +3,5 @@
 // ============================================================================
 
 pub mod hash;
-pub mod commitment;
\ No newline at end of file
+pub mod commitment;
+pub mod pedersen;
\ No newline at end of file
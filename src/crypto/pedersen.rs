@@ -0,0 +1,79 @@
+// ============================================================================
+// Pedersen Commitments over Ristretto
+// ============================================================================
+
+use super::commitment::{CommitmentKind, CommitmentScheme};
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use serde::{Serialize, Deserialize};
+use sha2::Sha512;
+
+// Second generator with no known discrete log relative to the base point
+fn blinding_generator() -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(b"secure-file-sharing/pedersen/H")
+}
+
+// C = m*G + r*H, where m is the secret hashed to a scalar and r is the blinding factor.
+// Commitments add homomorphically: C(m1, r1) + C(m2, r2) = C(m1 + m2, r1 + r2).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PedersenCommitment {
+    point: [u8; 32],    // compressed Ristretto point
+    blinding: [u8; 32], // canonical scalar bytes
+}
+
+impl PedersenCommitment {
+    fn compute(secret: &[u8], blinding: &Scalar) -> RistrettoPoint {
+        let m = Scalar::hash_from_bytes::<Sha512>(secret);
+        m * RISTRETTO_BASEPOINT_POINT + blinding * blinding_generator()
+    }
+
+    pub fn point(&self) -> Option<RistrettoPoint> {
+        CompressedRistretto(self.point).decompress()
+    }
+}
+
+impl CommitmentScheme for PedersenCommitment {
+    const KIND: CommitmentKind = CommitmentKind::Pedersen;
+
+    fn commit(secret: &[u8]) -> Self {
+        let blinding = Scalar::random(&mut rand::thread_rng());
+        Self {
+            point: Self::compute(secret, &blinding).compress().to_bytes(),
+            blinding: blinding.to_bytes(),
+        }
+    }
+
+    fn verify(&self, secret: &[u8]) -> bool {
+        let blinding: Option<Scalar> = Scalar::from_canonical_bytes(self.blinding).into();
+        match (blinding, self.point()) {
+            (Some(blinding), Some(point)) => Self::compute(secret, &blinding) == point,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::commitment::{commit_bytes, verify_bytes};
+
+    #[test]
+    fn commit_and_verify_round_trip() {
+        let commitment = PedersenCommitment::commit(b"secret");
+        assert!(commitment.verify(b"secret"));
+        assert!(!commitment.verify(b"Secret"));
+
+        let stored = commit_bytes(CommitmentKind::Pedersen, b"secret").unwrap();
+        assert!(verify_bytes(CommitmentKind::Pedersen, &stored, b"secret"));
+        assert!(!verify_bytes(CommitmentKind::Pedersen, &stored, b"other"));
+    }
+
+    #[test]
+    fn commitments_add_homomorphically() {
+        let (r1, r2) = (Scalar::random(&mut rand::thread_rng()), Scalar::random(&mut rand::thread_rng()));
+        let sum = PedersenCommitment::compute(b"a", &r1) + PedersenCommitment::compute(b"b", &r2);
+        let m = Scalar::hash_from_bytes::<Sha512>(b"a") + Scalar::hash_from_bytes::<Sha512>(b"b");
+        assert_eq!(sum, m * RISTRETTO_BASEPOINT_POINT + (r1 + r2) * blinding_generator());
+    }
+}
@@ -4,26 +4,144 @@
 
 use anyhow::{Result, Context, anyhow};
 use chrono::{DateTime, Utc};
-use sqlx::{SqlitePool, sqlite::SqlitePoolOptions, Row};
+use sqlx::{QueryBuilder, Row, Sqlite, SqlitePool};
+use sqlx::types::Json;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous};
 use dotenv::dotenv;
-use std::env;
+use rand::RngCore;
+use std::collections::HashMap;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
-use super::models::{User, FileRecord, SharedFile, SystemStats};
-use crate::crypto::hash::HashValue;
+use super::models::{User, UserSummary, UserActivity, FileRecord, SharedFile, SharePermission, SystemStats, QuarantinedFile, DownloadLink, Group, OutgoingShare, ShareStatus, AuditEntry, AuditFilter, FileQuery, SecurityEvent, ShareProof};
+use crate::crypto::commitment::CommitmentKind;
+use crate::crypto::hash::{HashAlgo, HashValue};
+use crate::error::FileSharingError;
+
+// Column lists matching `User` and `FileRecord`
+const USER_COLUMNS: &str = "id, username, password_hash, email, public_key, is_admin, created_at, last_login";
+const FILE_COLUMNS: &str = "id, hash, hash_algo, filename, size, owner_id, description, chunks, merkle_root, mime_type, created_at, modified_at, previous_version_id, deleted_at";
+
+// Files a user currently has: not in the recycle bin and not superseded by a newer version
+const CURRENT_FILES: &str = r#"(
+    SELECT * FROM files
+    WHERE deleted_at IS NULL
+      AND id NOT IN (SELECT previous_version_id FROM files WHERE previous_version_id IS NOT NULL)
+)"#;
+
+// Row shape of `SharedFile`; callers append the WHERE clause
+const SHARED_FILE_SELECT: &str = r#"
+    SELECT
+        s.id,
+        s.public_id,
+        f.id as file_id,
+        f.filename,
+        u_sender.username as shared_by,
+        s.shared_with_id,
+        u_receiver.username as shared_with_username,
+        s.commitment,
+        s.commitment_scheme,
+        s.permission,
+        s.shared_at,
+        s.expires_at,
+        s.proof
+    FROM shares s
+    JOIN files f ON s.file_id = f.id
+    JOIN users u_sender ON s.shared_by_id = u_sender.id
+    JOIN users u_receiver ON s.shared_with_id = u_receiver.id
+"#;
+
+// Shortest hash prefix accepted for lookups, like `git` short hashes
+pub const MIN_HASH_PREFIX: usize = 8;
+
+// Free-form text is trimmed before these byte limits are applied
+fn bounded<'a>(field: &str, value: &'a str, max: usize) -> Result<&'a str> {
+    let value = value.trim();
+    if value.len() > max {
+        return Err(FileSharingError::FieldTooLong { field: field.to_string(), max }.into());
+    }
+    Ok(value)
+}
+
+// Bearer tokens are stored only as this hash, so a leaked database holds none usable
+fn hash_token(token: &str) -> String {
+    HashValue::compute(token.as_bytes(), HashAlgo::Sha256).to_hex()
+}
+
+// Most audit rows one query may return
+pub const MAX_AUDIT_LIMIT: i64 = 500;
+
+// Externally visible share reference: 16 random bytes, so it reveals neither
+// how many shares exist nor the ids of anyone else's
+fn new_public_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
 
 #[derive(Debug, Clone)]
 pub struct Database {
     pool: SqlitePool,
+    in_memory: bool, // fell back to :memory:, nothing survives a restart
+    limits: FieldLimits,
+}
+
+// Longest free-form values accepted, in bytes
+#[derive(Debug, Clone)]
+pub struct FieldLimits {
+    pub description: usize,
+    pub attribute_key: usize,
+    pub attribute_value: usize,
+}
+
+impl Default for FieldLimits {
+    fn default() -> Self {
+        Self {
+            description: 4096,
+            attribute_key: 64,
+            attribute_value: 1024,
+        }
+    }
+}
+
+// Connection settings applied to every pooled connection
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub data_dir: PathBuf,
+    pub max_connections: u32,
+    pub journal_mode: SqliteJournalMode,
+    pub synchronous: SqliteSynchronous,
+    pub busy_timeout: Duration,
+    pub foreign_keys: bool, // the schema declares FKs; SQLite only enforces them when this is on
+    pub limits: FieldLimits,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("./data"),
+            max_connections: 1,
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Normal,
+            busy_timeout: Duration::from_secs(5),
+            foreign_keys: true,
+            limits: FieldLimits::default(),
+        }
+    }
 }
 
 impl Database {
     pub async fn new() -> Result<Self> {
+        Self::with_config(DatabaseConfig::default()).await
+    }
+    
+    pub async fn with_config(config: DatabaseConfig) -> Result<Self> {
     dotenv().ok();
     
     // ساخت پوشه data تو مسیر جاری
-    let data_dir = Path::new("./data");
+    let data_dir = config.data_dir.as_path();
     println!("Creating data directory: {:?}", data_dir);
     
     if !data_dir.exists() {
@@ -55,13 +173,19 @@ impl Database {
     }
     
 
-    let database_url = format!("sqlite:{}", db_path_str);
-    println!("Connection URL: {}", database_url);
+    let options = SqliteConnectOptions::new()
+        .filename(&db_path)
+        .create_if_missing(true)
+        .journal_mode(config.journal_mode)
+        .synchronous(config.synchronous)
+        .busy_timeout(config.busy_timeout)
+        .foreign_keys(config.foreign_keys);
+    println!("Connection URL: sqlite:{}", db_path_str);
     
 
     match SqlitePoolOptions::new()
-        .max_connections(1)
-        .connect(&database_url)
+        .max_connections(config.max_connections)
+        .connect_with(options)
         .await 
     {
         Ok(pool) => {
@@ -73,7 +197,7 @@ impl Database {
                 Err(e) => println!("⚠️ Schema initialization warning: {}", e),
             }
             
-            Ok(Self { pool })
+            Ok(Self { pool, in_memory: false, limits: config.limits })
         },
         Err(e) => {
             println!("❌ Database connection failed!");
@@ -83,9 +207,12 @@ impl Database {
     
             println!("🔄 Trying in-memory database as fallback...");
             
+            // A single connection, since each in-memory connection is its own database
+            let memory_options = SqliteConnectOptions::from_str("sqlite::memory:")?
+                .foreign_keys(config.foreign_keys);
             let memory_pool = SqlitePoolOptions::new()
                 .max_connections(1)
-                .connect("sqlite::memory:")
+                .connect_with(memory_options)
                 .await
                 .context("Failed to connect to in-memory database")?;
             
@@ -93,11 +220,34 @@ impl Database {
             Self::init_schema(&memory_pool).await?;
             println!("✅ In-memory schema initialized");
             
-            Ok(Self { pool: memory_pool })
+            Ok(Self { pool: memory_pool, in_memory: true, limits: config.limits })
         }
     }
 }
     
+    // Checkpoint the WAL into the main file and close every connection. Dropping
+    // a Database without this may leave a -wal file behind, which SQLite replays
+    // on the next open. Closing an already closed pool (via a clone) is a no-op.
+    pub async fn close(self) {
+        if self.pool.is_closed() {
+            return;
+        }
+        if !self.in_memory {
+            if let Err(e) = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)").execute(&self.pool).await {
+                println!("⚠️  WAL checkpoint failed: {}", e);
+            }
+        }
+        self.pool.close().await;
+    }
+    
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory
+    }
+    
+    pub async fn ping(&self) -> bool {
+        sqlx::query("SELECT 1").execute(&self.pool).await.is_ok()
+    }
+    
     async fn init_schema(pool: &SqlitePool) -> Result<()> {
         // Create users table
         sqlx::query(
@@ -108,6 +258,7 @@ impl Database {
                 password_hash TEXT NOT NULL,
                 email TEXT,
                 public_key BLOB,
+                is_admin INTEGER NOT NULL DEFAULT 0,
                 created_at DATETIME NOT NULL,
                 last_login DATETIME
             )
@@ -123,14 +274,20 @@ impl Database {
             CREATE TABLE IF NOT EXISTS files (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 hash TEXT NOT NULL,
+                hash_algo TEXT NOT NULL DEFAULT 'sha256',
                 filename TEXT NOT NULL,
                 size INTEGER NOT NULL,
                 owner_id INTEGER NOT NULL,
                 description TEXT,
                 chunks INTEGER NOT NULL,
                 merkle_root TEXT NOT NULL,
+                mime_type TEXT NOT NULL DEFAULT 'application/octet-stream',
                 created_at DATETIME NOT NULL,
+                modified_at DATETIME,
+                previous_version_id INTEGER,
+                deleted_at DATETIME,
                 FOREIGN KEY (owner_id) REFERENCES users(id),
+                FOREIGN KEY (previous_version_id) REFERENCES files(id),
                 UNIQUE(hash, owner_id)
             )
             "#,
@@ -148,6 +305,8 @@ impl Database {
                 shared_by_id INTEGER NOT NULL,
                 shared_with_id INTEGER NOT NULL,
                 commitment BLOB,
+                commitment_scheme TEXT NOT NULL DEFAULT 'hash',
+                permission TEXT NOT NULL DEFAULT 'read',
                 shared_at DATETIME NOT NULL,
                 expires_at DATETIME,
                 FOREIGN KEY (file_id) REFERENCES files(id),
@@ -161,6 +320,193 @@ impl Database {
         .await
         .context("Failed to create shares table")?;
         
+        // Create quarantine table (files that failed integrity checks)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS quarantine (
+                hash TEXT PRIMARY KEY,
+                hash_algo TEXT NOT NULL DEFAULT 'sha256',
+                reason TEXT NOT NULL,
+                quarantined_at DATETIME NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create quarantine table")?;
+        
+        // Create idempotency keys table (client retry keys for uploads)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS idempotency_keys (
+                owner_id INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                file_id INTEGER NOT NULL,
+                created_at DATETIME NOT NULL,
+                PRIMARY KEY (owner_id, key),
+                FOREIGN KEY (owner_id) REFERENCES users(id),
+                FOREIGN KEY (file_id) REFERENCES files(id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create idempotency_keys table")?;
+        
+        // Create download links table (bearer tokens for a single file, kept hashed)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS download_links (
+                token_hash TEXT PRIMARY KEY,
+                file_id INTEGER NOT NULL,
+                created_by_id INTEGER NOT NULL,
+                bound_user_id INTEGER,
+                created_at DATETIME NOT NULL,
+                expires_at DATETIME,
+                FOREIGN KEY (file_id) REFERENCES files(id),
+                FOREIGN KEY (created_by_id) REFERENCES users(id),
+                FOREIGN KEY (bound_user_id) REFERENCES users(id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create download_links table")?;
+        
+        // Password reset tokens; only a hash of each token is kept, so a leaked
+        // database cannot be used to reset anyone's password
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS password_reset_tokens (
+                token_hash TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                created_at DATETIME NOT NULL,
+                expires_at DATETIME NOT NULL,
+                used_at DATETIME,
+                FOREIGN KEY (user_id) REFERENCES users(id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create password_reset_tokens table")?;
+        
+        // Keys generated once per installation, e.g. for signing share strings
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS server_secrets (
+                name TEXT PRIMARY KEY,
+                value BLOB NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create server_secrets table")?;
+        
+        // Create groups tables (named sets of users to share with)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS groups (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                owner_id INTEGER NOT NULL,
+                created_at DATETIME NOT NULL,
+                FOREIGN KEY (owner_id) REFERENCES users(id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create groups table")?;
+        
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS group_members (
+                group_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                added_at DATETIME NOT NULL,
+                PRIMARY KEY (group_id, user_id),
+                FOREIGN KEY (group_id) REFERENCES groups(id),
+                FOREIGN KEY (user_id) REFERENCES users(id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create group_members table")?;
+        
+        // Create file attributes table (free-form key/value metadata per file)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS file_attributes (
+                file_id INTEGER NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                PRIMARY KEY (file_id, key),
+                FOREIGN KEY (file_id) REFERENCES files(id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create file_attributes table")?;
+        
+        // Create audit log table (who did what, append-only)
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER,
+                action TEXT NOT NULL,
+                target TEXT,
+                timestamp DATETIME NOT NULL,
+                FOREIGN KEY (user_id) REFERENCES users(id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create audit_log table")?;
+        
+        // Failed logins and lockouts, kept apart from the audit log so they can
+        // be shown to the account's owner
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS security_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                detail TEXT,
+                created_at DATETIME NOT NULL,
+                FOREIGN KEY (user_id) REFERENCES users(id)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to create security_events table")?;
+        
+        // Columns added after the initial schema
+        Self::add_column_if_missing(pool, "shares", "permission", "TEXT NOT NULL DEFAULT 'read'").await?;
+        Self::add_column_if_missing(pool, "shares", "commitment_scheme", "TEXT NOT NULL DEFAULT 'hash'").await?;
+        Self::add_column_if_missing(pool, "shares", "proof", "TEXT").await?;
+        Self::add_column_if_missing(pool, "users", "is_admin", "INTEGER NOT NULL DEFAULT 0").await?;
+        Self::add_column_if_missing(pool, "quarantine", "hash_algo", "TEXT NOT NULL DEFAULT 'sha256'").await?;
+        Self::add_column_if_missing(pool, "files", "hash_algo", "TEXT NOT NULL DEFAULT 'sha256'").await?;
+        Self::add_column_if_missing(pool, "quarantine", "copy_dir", "TEXT").await?;
+        Self::add_column_if_missing(pool, "files", "mime_type", "TEXT NOT NULL DEFAULT 'application/octet-stream'").await?;
+        Self::add_column_if_missing(pool, "files", "modified_at", "DATETIME").await?;
+        Self::add_column_if_missing(pool, "files", "previous_version_id", "INTEGER REFERENCES files(id)").await?;
+        Self::add_column_if_missing(pool, "files", "deleted_at", "DATETIME").await?;
+        Self::add_column_if_missing(pool, "shares", "public_id", "TEXT").await?;
+        sqlx::query("UPDATE shares SET public_id = lower(hex(randomblob(16))) WHERE public_id IS NULL")
+            .execute(pool)
+            .await?;
+        sqlx::query("UPDATE files SET modified_at = created_at WHERE modified_at IS NULL")
+            .execute(pool)
+            .await?;
+        
         // Create indexes
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_files_hash ON files(hash)")
             .execute(pool)
@@ -174,6 +520,72 @@ impl Database {
             .execute(pool)
             .await?;
         
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_shares_public_id ON shares(public_id)")
+            .execute(pool)
+            .await?;
+        
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_user_time ON audit_log(user_id, timestamp)")
+            .execute(pool)
+            .await?;
+        
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_action_time ON audit_log(action, timestamp)")
+            .execute(pool)
+            .await?;
+        
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_security_events_user ON security_events(user_id, kind, created_at)")
+            .execute(pool)
+            .await?;
+        
+        Self::ensure_nocase_usernames(pool).await?;
+        
+        Ok(())
+    }
+    
+    // Usernames are unique regardless of case. Databases created before this rule
+    // may already hold "Alice" and "alice"; those accounts keep working (exact-case
+    // matches win on login) and the index is retried on the next start once an
+    // admin has renamed one of them. create_user enforces the rule either way.
+    async fn ensure_nocase_usernames(pool: &SqlitePool) -> Result<()> {
+        let collisions: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT GROUP_CONCAT(username, ', ')
+            FROM users
+            GROUP BY username COLLATE NOCASE
+            HAVING COUNT(*) > 1
+            "#,
+        )
+        .fetch_all(pool)
+        .await?;
+        
+        if !collisions.is_empty() {
+            for names in &collisions {
+                println!("⚠️  Usernames differing only in case: {}", names);
+            }
+            println!("⚠️  Case-insensitive username index not created until these are resolved");
+            return Ok(());
+        }
+        
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_users_username_nocase ON users(username COLLATE NOCASE)")
+            .execute(pool)
+            .await
+            .context("Failed to create case-insensitive username index")?;
+        Ok(())
+    }
+    
+    async fn add_column_if_missing(pool: &SqlitePool, table: &str, column: &str, decl: &str) -> Result<()> {
+        let exists: i64 = sqlx::query("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+            .bind(table)
+            .bind(column)
+            .fetch_one(pool)
+            .await?
+            .get(0);
+        
+        if exists == 0 {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl))
+                .execute(pool)
+                .await
+                .with_context(|| format!("Failed to add {}.{} column", table, column))?;
+        }
         Ok(())
     }
     
@@ -181,10 +593,13 @@ impl Database {
     pub async fn create_user(&self, username: &str, password_hash: &str, email: Option<&str>) -> Result<User> {
         let now = Utc::now();
         
-        let id = sqlx::query(
+        // New accounts are never admins; see `set_admin`. The NOT EXISTS guard
+        // covers legacy databases where the NOCASE index could not be built.
+        let row = sqlx::query(
             r#"
-            INSERT INTO users (username, password_hash, email, created_at)
-            VALUES (?, ?, ?, ?)
+            INSERT INTO users (username, password_hash, email, is_admin, created_at)
+            SELECT ?, ?, ?, 0, ?
+            WHERE NOT EXISTS (SELECT 1 FROM users WHERE username = ? COLLATE NOCASE)
             RETURNING id
             "#,
         )
@@ -192,29 +607,42 @@ impl Database {
         .bind(password_hash)
         .bind(email)
         .bind(now)
-        .fetch_one(&self.pool)
-        .await?
-        .get(0);
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| match e {
+            // Lost a race with a concurrent registration of the same name
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                FileSharingError::UsernameTaken(username.to_string()).into()
+            }
+            e => anyhow::Error::from(e),
+        })?
+        .ok_or_else(|| FileSharingError::UsernameTaken(username.to_string()))?;
         
         Ok(User {
-            id,
+            id: row.get(0),
             username: username.to_string(),
             password_hash: password_hash.to_string(),
             email: email.map(|s| s.to_string()),
             public_key: None,
+            is_admin: false,
             created_at: now,
             last_login: None,
         })
     }
     
     pub async fn get_user_by_username(&self, username: &str) -> Result<Option<User>> {
-        let user = sqlx::query_as::<_, User>(
+        let user = sqlx::query_as::<_, User>(&format!(
             r#"
-            SELECT id, username, password_hash, email, public_key, created_at, last_login
+            SELECT {}
             FROM users
-            WHERE username = ?
-            "#
-        )
+            WHERE username = ? COLLATE NOCASE
+            ORDER BY username = ? DESC
+            LIMIT 1
+            "#,
+            USER_COLUMNS
+        ))
+        .bind(username)
         .bind(username)
         .fetch_optional(&self.pool)
         .await?;
@@ -222,151 +650,1311 @@ impl Database {
         Ok(user)
     }
     
-    pub async fn update_last_login(&self, user_id: i64) -> Result<()> {
-        sqlx::query(
+    pub async fn get_user_by_id(&self, user_id: i64) -> Result<Option<User>> {
+        let user = sqlx::query_as::<_, User>(&format!(
             r#"
-            UPDATE users
-            SET last_login = ?
+            SELECT {}
+            FROM users
             WHERE id = ?
-            "#
-        )
-        .bind(Utc::now())
+            "#,
+            USER_COLUMNS
+        ))
         .bind(user_id)
-        .execute(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
         
+        Ok(user)
+    }
+    
+    pub async fn set_admin(&self, user_id: i64, is_admin: bool) -> Result<()> {
+        sqlx::query("UPDATE users SET is_admin = ? WHERE id = ?")
+            .bind(is_admin)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        
         Ok(())
     }
     
-    pub async fn save_file(
-        &self, 
-        hash: &HashValue, 
-        filename: &str, 
-        size: u64,
-        owner_id: i64,
-        description: Option<&str>,
-        chunks: usize,
-        merkle_root: &HashValue,
-    ) -> Result<FileRecord> {
-        let now = Utc::now();
+    // Account overview with storage usage; deliberately has no password hash
+    pub async fn list_users(&self, limit: i64, offset: i64) -> Result<Vec<UserSummary>> {
+        let users = sqlx::query_as::<_, UserSummary>(&format!(
+            r#"
+            SELECT 
+                u.id,
+                u.username,
+                u.email,
+                u.is_admin,
+                u.created_at,
+                u.last_login,
+                COUNT(f.id) as file_count,
+                COALESCE(SUM(f.size), 0) as total_bytes
+            FROM users u
+            LEFT JOIN {} f ON f.owner_id = u.id
+            GROUP BY u.id
+            ORDER BY u.username
+            LIMIT ? OFFSET ?
+            "#,
+            CURRENT_FILES
+        ))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
         
-        let id = sqlx::query(
+        Ok(users)
+    }
+    
+    pub async fn user_activity(&self, username: &str) -> Result<UserActivity> {
+        let user = self.get_user_by_username(username).await?
+            .context("User not found")?;
+        let activity = sqlx::query_as::<_, UserActivity>(&format!(
             r#"
-            INSERT INTO files (hash, filename, size, owner_id, description, chunks, merkle_root, created_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
-            RETURNING id
+            SELECT 
+                u.last_login,
+                (SELECT COUNT(*) FROM {current} f WHERE f.owner_id = u.id) as file_count,
+                (SELECT COALESCE(SUM(f.size), 0) FROM {current} f WHERE f.owner_id = u.id) as total_bytes,
+                (SELECT COUNT(*) FROM shares s JOIN {current} f ON s.file_id = f.id WHERE s.shared_by_id = u.id) as share_count,
+                (SELECT MAX(created_at) FROM files WHERE owner_id = u.id) as last_upload
+            FROM users u
+            WHERE u.id = ?
             "#,
-        )
-        .bind(hash.to_hex())
-        .bind(filename)
-        .bind(size as i64)
-        .bind(owner_id)
-        .bind(description)
-        .bind(chunks as i32)
-        .bind(merkle_root.to_hex())
-        .bind(now)
+            current = CURRENT_FILES
+        ))
+        .bind(user.id)
         .fetch_one(&self.pool)
-        .await?
-        .get(0);
+        .await?;
         
-        Ok(FileRecord {
-            id,
-            hash: hash.to_hex(),
-            filename: filename.to_string(),
-            size: size as i64,
-            owner_id,
-            description: description.map(|s| s.to_string()),
-            chunks: chunks as i32,
-            merkle_root: merkle_root.to_hex(),
-            created_at: now,
-        })
+        Ok(activity)
     }
     
-    pub async fn get_user_files(&self, username: &str) -> Result<Vec<FileRecord>> {
-        let files = sqlx::query_as::<_, FileRecord>(
-            r#"
-            SELECT f.id, f.hash, f.filename, f.size, f.owner_id, 
-                f.description, f.chunks, f.merkle_root, f.created_at
-            FROM files f
-            JOIN users u ON f.owner_id = u.id
-            WHERE u.username = ?
-            ORDER BY f.created_at DESC
-            "#
-        )
-        .bind(username)
-        .fetch_all(&self.pool)
-        .await?;
+    // 32 random bytes stored under `name`, created on first use. Concurrent first
+    // calls agree on one value because only the first insert wins.
+    pub async fn get_or_create_secret(&self, name: &str) -> Result<[u8; 32]> {
+        let mut fresh = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut fresh);
+        sqlx::query("INSERT OR IGNORE INTO server_secrets (name, value) VALUES (?, ?)")
+            .bind(name)
+            .bind(fresh.as_slice())
+            .execute(&self.pool)
+            .await?;
         
-        Ok(files)
+        let value: Vec<u8> = sqlx::query_scalar("SELECT value FROM server_secrets WHERE name = ?")
+            .bind(name)
+            .fetch_one(&self.pool)
+            .await?;
+        value.try_into().map_err(|_| anyhow!("secret {} has the wrong length", name))
+    }
+    
+    // A fresh single-use token for `user_id`; the caller gets the only plaintext copy
+    pub async fn create_reset_token(&self, user_id: i64, ttl: chrono::Duration) -> Result<String> {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+        let now = Utc::now();
+        
+        sqlx::query(
+            "INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at) VALUES (?, ?, ?, ?)"
+        )
+        .bind(hash_token(&token))
+        .bind(user_id)
+        .bind(now)
+        .bind(now + ttl)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(token)
+    }
+    
+    // Mark an unused, unexpired token as used and return its user. Every other
+    // outstanding token of that user is retired with it.
+    pub async fn consume_reset_token(&self, token: &str) -> Result<Option<i64>> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        
+        let user_id: Option<i64> = sqlx::query_scalar(
+            r#"
+            UPDATE password_reset_tokens
+            SET used_at = ?
+            WHERE token_hash = ? AND used_at IS NULL AND expires_at > ?
+            RETURNING user_id
+            "#,
+        )
+        .bind(now)
+        .bind(hash_token(token))
+        .bind(now)
+        .fetch_optional(&mut *tx)
+        .await?;
+        
+        if let Some(user_id) = user_id {
+            sqlx::query("UPDATE password_reset_tokens SET used_at = ? WHERE user_id = ? AND used_at IS NULL")
+                .bind(now)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        
+        tx.commit().await?;
+        Ok(user_id)
+    }
+    
+    pub async fn update_last_login(&self, user_id: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE users
+            SET last_login = ?
+            WHERE id = ?
+            "#
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn update_password(&self, user_id: i64, new_hash: &str) -> Result<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE users
+            SET password_hash = ?
+            WHERE id = ?
+            "#
+        )
+        .bind(new_hash)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        
+        if result.rows_affected() == 0 {
+            return Err(anyhow!("User {} not found", user_id));
+        }
+        Ok(())
+    }
+    
+    // Trimmed and length-checked; a blank description is stored as none
+    pub fn checked_description<'a>(&self, description: Option<&'a str>) -> Result<Option<&'a str>> {
+        Ok(match description {
+            Some(d) => Some(bounded("description", d, self.limits.description)?).filter(|d| !d.is_empty()),
+            None => None,
+        })
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    pub async fn save_file(
+        &self, 
+        hash: &HashValue, 
+        filename: &str, 
+        size: u64,
+        owner_id: i64,
+        description: Option<&str>,
+        chunks: usize,
+        merkle_root: &HashValue,
+        mime_type: &str,
+        created_at: Option<DateTime<Utc>>,
+        modified_at: Option<DateTime<Utc>>,
+    ) -> Result<FileRecord> {
+        let description = self.checked_description(description)?;
+        let created_at = created_at.unwrap_or_else(Utc::now);
+        let modified_at = modified_at.unwrap_or(created_at);
+        
+        let id = sqlx::query(
+            r#"
+            INSERT INTO files (hash, hash_algo, filename, size, owner_id, description, chunks, merkle_root, mime_type, created_at, modified_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING id
+            "#,
+        )
+        .bind(hash.to_hex())
+        .bind(hash.algo.as_str())
+        .bind(filename)
+        .bind(size as i64)
+        .bind(owner_id)
+        .bind(description)
+        .bind(chunks as i32)
+        .bind(merkle_root.to_hex())
+        .bind(mime_type)
+        .bind(created_at)
+        .bind(modified_at)
+        .fetch_one(&self.pool)
+        .await?
+        .get(0);
+        
+        Ok(FileRecord {
+            id,
+            hash: hash.to_hex(),
+            hash_algo: hash.algo.as_str().to_string(),
+            filename: filename.to_string(),
+            size: size as i64,
+            owner_id,
+            description: description.map(|s| s.to_string()),
+            chunks: chunks as i32,
+            merkle_root: merkle_root.to_hex(),
+            mime_type: mime_type.to_string(),
+            created_at,
+            modified_at,
+            previous_version_id: None,
+            deleted_at: None,
+        })
+    }
+    
+    // Record new content for an existing file: the new row keeps the original
+    // created_at and links back to the version it supersedes
+    pub async fn save_file_version(
+        &self,
+        previous: &FileRecord,
+        hash: &HashValue,
+        size: u64,
+        chunks: usize,
+        merkle_root: &HashValue,
+        mime_type: &str,
+    ) -> Result<FileRecord> {
+        let id: i64 = sqlx::query(
+            r#"
+            INSERT INTO files (hash, hash_algo, filename, size, owner_id, description, chunks, merkle_root, mime_type, 
+                created_at, modified_at, previous_version_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING id
+            "#,
+        )
+        .bind(hash.to_hex())
+        .bind(hash.algo.as_str())
+        .bind(&previous.filename)
+        .bind(size as i64)
+        .bind(previous.owner_id)
+        .bind(&previous.description)
+        .bind(chunks as i32)
+        .bind(merkle_root.to_hex())
+        .bind(mime_type)
+        .bind(previous.created_at)
+        .bind(Utc::now())
+        .bind(previous.id)
+        .fetch_one(&self.pool)
+        .await?
+        .get(0);
+        
+        // Attributes describe the file, not one version of its content
+        sqlx::query("INSERT INTO file_attributes (file_id, key, value) SELECT ?, key, value FROM file_attributes WHERE file_id = ?")
+            .bind(id)
+            .bind(previous.id)
+            .execute(&self.pool)
+            .await?;
+        
+        self.get_file_by_id(id).await?
+            .context("Inserted file version not found")
+    }
+    
+    // Only files `user_id` can see count: their own (not in the recycle bin) and
+    // ones shared with them that have not expired. Anyone else's hashes never
+    // match, so neither a match nor an ambiguity error reveals them.
+    pub async fn resolve_short_hash(&self, prefix: &str, user_id: i64) -> Result<FileRecord> {
+        let prefix = prefix.trim().to_ascii_lowercase();
+        if prefix.len() < MIN_HASH_PREFIX {
+            return Err(FileSharingError::InvalidHash(
+                format!("prefix must be at least {} hex characters", MIN_HASH_PREFIX)
+            ).into());
+        }
+        // Also keeps LIKE wildcards out of the pattern
+        if !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(FileSharingError::InvalidHash(format!("not a hex prefix: {}", prefix)).into());
+        }
+        
+        const VISIBLE: &str = r#"
+            f.deleted_at IS NULL
+            AND (f.owner_id = ? OR EXISTS (
+                SELECT 1 FROM shares s
+                WHERE s.file_id = f.id AND s.shared_with_id = ?
+                  AND (s.expires_at IS NULL OR s.expires_at > ?)
+            ))
+        "#;
+        let now = Utc::now();
+        let candidates: Vec<String> = sqlx::query(&format!(
+            "SELECT DISTINCT f.hash FROM files f WHERE f.hash LIKE ? AND {} LIMIT 10",
+            VISIBLE
+        ))
+        .bind(format!("{}%", prefix))
+        .bind(user_id)
+        .bind(user_id)
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+        
+        match candidates.len() {
+            0 => Err(anyhow!("No file matches hash prefix {}", prefix)),
+            1 => {
+                // The user's own row first, then any shared with them
+                let file = sqlx::query_as::<_, FileRecord>(&format!(
+                    "SELECT {} FROM files f WHERE f.hash = ? AND {} ORDER BY f.owner_id = ? DESC LIMIT 1",
+                    FILE_COLUMNS, VISIBLE
+                ))
+                .bind(&candidates[0])
+                .bind(user_id)
+                .bind(user_id)
+                .bind(now)
+                .bind(user_id)
+                .fetch_one(&self.pool)
+                .await?;
+                Ok(file)
+            }
+            _ => Err(FileSharingError::AmbiguousHash { candidates }.into()),
+        }
+    }
+    
+    // Every distinct content hash referenced by a file row
+    pub async fn file_hashes(&self) -> Result<Vec<HashValue>> {
+        let rows = sqlx::query("SELECT DISTINCT hash, hash_algo FROM files ORDER BY hash")
+            .fetch_all(&self.pool)
+            .await?;
+        
+        rows.iter()
+            .map(|row| Ok(HashValue::from_bytes(
+                row.get::<String, _>("hash_algo").parse()?,
+                hex::decode(row.get::<String, _>("hash"))?,
+            )?))
+            .collect()
+    }
+    
+    // How many files use each hash algorithm, most common first
+    pub async fn files_by_hash_algo(&self) -> Result<Vec<(HashAlgo, i64)>> {
+        let rows = sqlx::query(
+            "SELECT hash_algo, COUNT(*) AS n FROM files GROUP BY hash_algo ORDER BY n DESC, hash_algo"
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        
+        rows.iter()
+            .map(|row| Ok((row.get::<String, _>("hash_algo").parse()?, row.get("n"))))
+            .collect()
+    }
+    
+    pub async fn get_files_with_hash_algo(&self, algos: &[HashAlgo]) -> Result<Vec<FileRecord>> {
+        if algos.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; algos.len()].join(", ");
+        let sql = format!(
+            "SELECT {} FROM files WHERE hash_algo IN ({}) ORDER BY created_at",
+            FILE_COLUMNS, placeholders
+        );
+        
+        let mut query = sqlx::query_as::<_, FileRecord>(&sql);
+        for algo in algos {
+            query = query.bind(algo.as_str());
+        }
+        Ok(query.fetch_all(&self.pool).await?)
+    }
+    
+    // Remove every row for a content hash, along with the shares and keys pointing at them
+    pub async fn delete_files_by_hash(&self, hash: &HashValue) -> Result<u64> {
+        let ids: Vec<i64> = sqlx::query("SELECT id FROM files WHERE hash = ?")
+            .bind(hash.to_hex())
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        
+        self.delete_file_rows(&ids).await
+    }
+    
+    async fn delete_file_rows(&self, ids: &[i64]) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let mut deleted = 0;
+        
+        for &id in ids {
+            for statement in [
+                "DELETE FROM shares WHERE file_id = ?",
+                "DELETE FROM idempotency_keys WHERE file_id = ?",
+                "DELETE FROM download_links WHERE file_id = ?",
+                "DELETE FROM file_attributes WHERE file_id = ?",
+                "UPDATE files SET previous_version_id = NULL WHERE previous_version_id = ?",
+            ] {
+                sqlx::query(statement)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            
+            deleted += sqlx::query("DELETE FROM files WHERE id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        
+        tx.commit().await?;
+        Ok(deleted)
+    }
+    
+    // Versions that superseded this file, oldest first (empty if it is the latest)
+    pub async fn get_newer_versions(&self, file_id: i64) -> Result<Vec<FileRecord>> {
+        let files = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            WITH RECURSIVE newer(id, depth) AS (
+                SELECT id, 1 FROM files WHERE previous_version_id = ?
+                UNION
+                SELECT f.id, n.depth + 1 FROM files f JOIN newer n ON f.previous_version_id = n.id
+            )
+            SELECT {}
+            FROM files JOIN newer USING (id)
+            ORDER BY newer.depth
+            "#,
+            FILE_COLUMNS
+        ))
+        .bind(file_id)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(files)
+    }
+    
+    // Mark a file and the versions it replaced as deleted (or, with None, restore them)
+    pub async fn set_file_deleted(&self, file_id: i64, deleted_at: Option<DateTime<Utc>>) -> Result<()> {
+        sqlx::query(
+            r#"
+            WITH RECURSIVE chain(id) AS (
+                SELECT ?
+                UNION
+                SELECT f.previous_version_id FROM files f JOIN chain c ON f.id = c.id
+                WHERE f.previous_version_id IS NOT NULL
+            )
+            UPDATE files SET deleted_at = ? WHERE id IN chain
+            "#,
+        )
+        .bind(file_id)
+        .bind(deleted_at)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn get_deleted_file(&self, hash: &HashValue, owner_id: i64) -> Result<Option<FileRecord>> {
+        let file = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE hash = ? AND owner_id = ? AND deleted_at IS NOT NULL
+            "#,
+            FILE_COLUMNS
+        ))
+        .bind(hash.to_hex())
+        .bind(owner_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(file)
+    }
+    
+    // The recycle bin: a user's deleted files, newest deletion first
+    pub async fn get_deleted_files(&self, owner_id: i64) -> Result<Vec<FileRecord>> {
+        let files = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE owner_id = ? AND deleted_at IS NOT NULL
+              AND id NOT IN (SELECT previous_version_id FROM files WHERE previous_version_id IS NOT NULL)
+            ORDER BY deleted_at DESC
+            "#,
+            FILE_COLUMNS
+        ))
+        .bind(owner_id)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(files)
+    }
+    
+    // Hard-delete rows soft-deleted at or before `cutoff`; returns the removed rows
+    pub async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<Vec<FileRecord>> {
+        let files = sqlx::query_as::<_, FileRecord>(&format!(
+            "SELECT {} FROM files WHERE deleted_at IS NOT NULL AND deleted_at <= ?",
+            FILE_COLUMNS
+        ))
+        .bind(cutoff)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let ids: Vec<i64> = files.iter().map(|f| f.id).collect();
+        self.delete_file_rows(&ids).await?;
+        Ok(files)
+    }
+    
+    pub async fn count_files_with_hash(&self, hash: &HashValue) -> Result<i64> {
+        let count: i64 = sqlx::query("SELECT COUNT(*) FROM files WHERE hash = ?")
+            .bind(hash.to_hex())
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+        
+        Ok(count)
+    }
+    
+    pub async fn get_file_by_id(&self, file_id: i64) -> Result<Option<FileRecord>> {
+        let file = sqlx::query_as::<_, FileRecord>(&format!(
+            "SELECT {} FROM files WHERE id = ?",
+            FILE_COLUMNS
+        ))
+        .bind(file_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(file)
+    }
+    
+    // Usernames match as in get_user_by_username; an unknown one owns nothing
+    pub async fn get_user_files(&self, username: &str) -> Result<Vec<FileRecord>> {
+        let user = match self.get_user_by_username(username).await? {
+            Some(user) => user,
+            None => return Ok(Vec::new()),
+        };
+        let files = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE owner_id = ?
+              AND deleted_at IS NULL
+              AND id NOT IN (SELECT previous_version_id FROM files WHERE previous_version_id IS NOT NULL)
+            ORDER BY created_at DESC
+            "#,
+            FILE_COLUMNS
+        ))
+        .bind(user.id)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(files)
+    }
+    
+    // Same files as get_user_files, one page at a time in upload order
+    pub async fn get_user_files_page(&self, owner_id: i64, limit: i64, offset: i64) -> Result<Vec<FileRecord>> {
+        let files = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE owner_id = ?
+              AND deleted_at IS NULL
+              AND id NOT IN (SELECT previous_version_id FROM files WHERE previous_version_id IS NOT NULL)
+            ORDER BY id
+            LIMIT ? OFFSET ?
+            "#,
+            FILE_COLUMNS
+        ))
+        .bind(owner_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(files)
+    }
+    
+    // Like get_user_files, with the filters and ordering of `query`
+    pub async fn query_files(&self, owner_id: i64, query: &FileQuery) -> Result<Vec<FileRecord>> {
+        let mut builder = QueryBuilder::<Sqlite>::new(format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE deleted_at IS NULL
+              AND id NOT IN (SELECT previous_version_id FROM files WHERE previous_version_id IS NOT NULL)
+            "#,
+            FILE_COLUMNS
+        ));
+        builder.push(" AND owner_id = ").push_bind(owner_id);
+        if let Some(min_size) = query.min_size {
+            builder.push(" AND size >= ").push_bind(min_size);
+        }
+        if let Some(max_size) = query.max_size {
+            builder.push(" AND size <= ").push_bind(max_size);
+        }
+        if let Some(name) = &query.name_contains {
+            let pattern = format!("%{}%", name.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+            builder.push(" AND filename LIKE ").push_bind(pattern).push(" ESCAPE '\\'");
+        }
+        if let Some(after) = query.created_after {
+            builder.push(" AND created_at >= ").push_bind(after);
+        }
+        if let Some(before) = query.created_before {
+            builder.push(" AND created_at < ").push_bind(before);
+        }
+        
+        // sort_by is an enum, so the column name comes from a fixed list
+        let direction = if query.ascending { "ASC" } else { "DESC" };
+        builder.push(format!(" ORDER BY {} {}, id {}", query.sort_by.as_str(), direction, direction));
+        
+        let files = builder.build_query_as::<FileRecord>()
+            .fetch_all(&self.pool)
+            .await?;
+        
+        Ok(files)
     }
     
     pub async fn get_file_by_hash(&self, hash: &HashValue) -> Result<Option<FileRecord>> {
-        let file = sqlx::query_as::<_, FileRecord>(
+        let file = sqlx::query_as::<_, FileRecord>(&format!(
             r#"
-            SELECT id, hash, filename, size, owner_id, description, chunks, merkle_root, created_at
+            SELECT {}
             FROM files
             WHERE hash = ?
+            "#,
+            FILE_COLUMNS
+        ))
+        .bind(hash.to_hex())
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(file)
+    }
+    
+    // Mark a file as modified now, replacing its description when one is given
+    pub async fn refresh_file(&self, file_id: i64, description: Option<&str>) -> Result<FileRecord> {
+        let description = self.checked_description(description)?;
+        let file = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            UPDATE files
+            SET modified_at = ?, description = COALESCE(?, description)
+            WHERE id = ?
+            RETURNING {}
+            "#,
+            FILE_COLUMNS
+        ))
+        .bind(Utc::now())
+        .bind(description)
+        .bind(file_id)
+        .fetch_one(&self.pool)
+        .await?;
+        
+        Ok(file)
+    }
+    
+    // Overwrite the recorded Merkle root on every row for this content that
+    // disagrees with `merkle_root`; returns how many rows changed
+    pub async fn set_merkle_root(&self, hash: &HashValue, merkle_root: &HashValue) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE files SET merkle_root = ? WHERE hash = ? AND merkle_root != ?"
+        )
+        .bind(merkle_root.to_hex())
+        .bind(hash.to_hex())
+        .bind(merkle_root.to_hex())
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(result.rows_affected())
+    }
+    
+    pub async fn get_owned_file(&self, hash: &HashValue, owner_id: i64) -> Result<Option<FileRecord>> {
+        let file = sqlx::query_as::<_, FileRecord>(&format!(
+            r#"
+            SELECT {}
+            FROM files
+            WHERE hash = ? AND owner_id = ? AND deleted_at IS NULL
+            "#,
+            FILE_COLUMNS
+        ))
+        .bind(hash.to_hex())
+        .bind(owner_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(file)
+    }
+    
+    // Share of a file (by content hash) received by the given user, if any
+    pub async fn get_received_share(&self, hash: &HashValue, shared_with_id: i64) -> Result<Option<SharedFile>> {
+        let share = sqlx::query_as::<_, SharedFile>(&format!(
+            r#"
+            {}
+            WHERE f.hash = ? AND s.shared_with_id = ? AND f.deleted_at IS NULL
+              AND (s.expires_at IS NULL OR s.expires_at > ?)
+            "#,
+            SHARED_FILE_SELECT
+        ))
+        .bind(hash.to_hex())
+        .bind(shared_with_id)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(share)
+    }
+    
+    // Look up a share by the random identifier used outside the database
+    pub async fn get_share_by_public_id(&self, public_id: &str) -> Result<Option<SharedFile>> {
+        let share = sqlx::query_as::<_, SharedFile>(&format!(
+            r#"
+            {}
+            WHERE s.public_id = ?
+              AND (s.expires_at IS NULL OR s.expires_at > ?)
+            "#,
+            SHARED_FILE_SELECT
+        ))
+        .bind(public_id)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(share)
+    }
+    
+    // Sharing again with the same recipient is not an error, so retries are safe.
+    // If the same user made the existing share, it takes the new permission,
+    // expiry and commitment (keeping its public_id and shared_at); a share made
+    // by someone else is left alone.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_share(
+        &self,
+        file_id: i64,
+        shared_by_id: i64,
+        shared_with_id: i64,
+        commitment: Option<&[u8]>,
+        commitment_scheme: CommitmentKind,
+        proof: Option<&ShareProof>,
+        permission: SharePermission,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO shares (public_id, file_id, shared_by_id, shared_with_id, commitment, commitment_scheme, proof, permission, shared_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(file_id, shared_with_id) DO UPDATE SET
+                commitment = excluded.commitment,
+                commitment_scheme = excluded.commitment_scheme,
+                proof = excluded.proof,
+                permission = excluded.permission,
+                expires_at = excluded.expires_at
+            WHERE shares.shared_by_id = excluded.shared_by_id
+            "#,
+        )
+        .bind(new_public_id())
+        .bind(file_id)
+        .bind(shared_by_id)
+        .bind(shared_with_id)
+        .bind(commitment)
+        .bind(commitment_scheme)
+        .bind(proof.map(Json))
+        .bind(permission)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    // Give every active share of one file a counterpart on another (e.g. its new
+    // version), keeping sharer, recipient, permission and expiry
+    pub async fn copy_shares(
+        &self,
+        from_file_id: i64,
+        to_file_id: i64,
+        commitment: &[u8],
+        commitment_scheme: CommitmentKind,
+        proof: Option<&ShareProof>,
+    ) -> Result<u64> {
+        let now = Utc::now();
+        let copied = sqlx::query(
+            r#"
+            INSERT INTO shares (public_id, file_id, shared_by_id, shared_with_id, commitment, commitment_scheme, proof, permission, shared_at, expires_at)
+            SELECT lower(hex(randomblob(16))), ?, shared_by_id, shared_with_id, ?, ?, ?, permission, ?, expires_at
+            FROM shares
+            WHERE file_id = ? AND (expires_at IS NULL OR expires_at > ?)
+            ON CONFLICT(file_id, shared_with_id) DO NOTHING
+            "#,
+        )
+        .bind(to_file_id)
+        .bind(commitment)
+        .bind(commitment_scheme)
+        .bind(proof.map(Json))
+        .bind(now)
+        .bind(from_file_id)
+        .bind(now)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        
+        Ok(copied)
+    }
+    
+    // Shares a user has made, ordered so each file's recipients are adjacent
+    pub async fn get_outgoing_shares(&self, owner_id: i64) -> Result<Vec<OutgoingShare>> {
+        // A negative LIMIT is no limit in SQLite
+        self.get_outgoing_shares_page(owner_id, -1, 0).await
+    }
+    
+    pub async fn get_outgoing_shares_page(&self, owner_id: i64, limit: i64, offset: i64) -> Result<Vec<OutgoingShare>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT f.id, f.filename, u.username, s.permission, s.shared_at, s.expires_at
+            FROM shares s
+            JOIN files f ON s.file_id = f.id
+            JOIN users u ON s.shared_with_id = u.id
+            WHERE s.shared_by_id = ? AND f.deleted_at IS NULL
+            ORDER BY f.filename, f.id, s.shared_at, s.id
+            LIMIT ? OFFSET ?
+            "#
+        )
+        .bind(owner_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        let now = Utc::now();
+        Ok(rows.iter().map(|row| {
+            let expires_at: Option<DateTime<Utc>> = row.get(5);
+            OutgoingShare {
+                file_id: row.get(0),
+                filename: row.get(1),
+                shared_with_username: row.get(2),
+                permission: row.get(3),
+                shared_at: row.get(4),
+                expires_at,
+                status: if expires_at.is_some_and(|t| t <= now) { ShareStatus::Expired } else { ShareStatus::Active },
+            }
+        }).collect())
+    }
+    
+    pub async fn get_shared_files(&self, username: &str) -> Result<Vec<SharedFile>> {
+        let user = match self.get_user_by_username(username).await? {
+            Some(user) => user,
+            None => return Ok(Vec::new()),
+        };
+        let shares = sqlx::query_as::<_, SharedFile>(&format!(
+            r#"
+            {}
+            WHERE s.shared_with_id = ? AND f.deleted_at IS NULL
+              AND (s.expires_at IS NULL OR s.expires_at > ?)
+            ORDER BY s.shared_at DESC
+            "#,
+            SHARED_FILE_SELECT
+        ))
+        .bind(user.id)
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(shares)
+    }
+    
+    // `copy` names the stored copy that failed (StorageEngine::copy_id); None,
+    // or a second copy failing too, quarantines every copy of the hash
+    pub async fn quarantine_file(&self, hash: &HashValue, copy: Option<&str>, reason: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO quarantine (hash, hash_algo, copy_dir, reason, quarantined_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(hash) DO UPDATE SET
+                reason = excluded.reason,
+                copy_dir = CASE WHEN quarantine.copy_dir = excluded.copy_dir THEN quarantine.copy_dir ELSE NULL END
+            "#,
+        )
+        .bind(hash.to_hex())
+        .bind(hash.algo.as_str())
+        .bind(copy)
+        .bind(reason)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    // The quarantine covering `copy` of a hash; with None, one covering any copy
+    pub async fn get_quarantine(&self, hash: &HashValue, copy: Option<&str>) -> Result<Option<QuarantinedFile>> {
+        let entry = sqlx::query_as::<_, QuarantinedFile>(
+            r#"
+            SELECT hash, hash_algo, copy_dir, reason, quarantined_at FROM quarantine
+            WHERE hash = ? AND (copy_dir IS NULL OR ? IS NULL OR copy_dir = ?)
             "#
         )
         .bind(hash.to_hex())
+        .bind(copy)
+        .bind(copy)
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(entry)
+    }
+    
+    pub async fn is_quarantined(&self, hash: &HashValue) -> Result<bool> {
+        Ok(self.get_quarantine(hash, None).await?.is_some())
+    }
+    
+    pub async fn list_quarantined(&self) -> Result<Vec<QuarantinedFile>> {
+        let files = sqlx::query_as::<_, QuarantinedFile>(
+            "SELECT hash, hash_algo, copy_dir, reason, quarantined_at FROM quarantine ORDER BY quarantined_at DESC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(files)
+    }
+    
+    pub async fn clear_quarantine(&self, hash: &HashValue) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM quarantine WHERE hash = ?")
+            .bind(hash.to_hex())
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(result.rows_affected() > 0)
+    }
+    
+    // File row recorded for an upload key, if the key was used at or after `since`
+    pub async fn get_idempotent_upload(&self, owner_id: i64, key: &str, since: DateTime<Utc>) -> Result<Option<FileRecord>> {
+        let file = sqlx::query_as::<_, FileRecord>(&format!(
+            "SELECT {} FROM files WHERE id = (SELECT file_id FROM idempotency_keys WHERE owner_id = ? AND key = ? AND created_at >= ?)",
+            FILE_COLUMNS
+        ))
+        .bind(owner_id)
+        .bind(key)
+        .bind(since)
         .fetch_optional(&self.pool)
         .await?;
         
         Ok(file)
     }
     
-    pub async fn create_share(
+    pub async fn record_idempotency_key(&self, owner_id: i64, key: &str, file_id: i64) -> Result<()> {
+        // An expired key is reused for the new upload
+        sqlx::query(
+            r#"
+            INSERT INTO idempotency_keys (owner_id, key, file_id, created_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(owner_id, key) DO UPDATE SET file_id = excluded.file_id, created_at = excluded.created_at
+            "#,
+        )
+        .bind(owner_id)
+        .bind(key)
+        .bind(file_id)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn create_download_link(
         &self,
+        token: &str,
         file_id: i64,
-        shared_by_id: i64,
-        shared_with_id: i64,
-        commitment: Option<&[u8]>,
+        created_by_id: i64,
+        bound_user_id: Option<i64>,
         expires_at: Option<DateTime<Utc>>,
-    ) -> Result<()> {
+    ) -> Result<DownloadLink> {
+        let now = Utc::now();
+        
         sqlx::query(
             r#"
-            INSERT INTO shares (file_id, shared_by_id, shared_with_id, commitment, shared_at, expires_at)
+            INSERT INTO download_links (token_hash, file_id, created_by_id, bound_user_id, created_at, expires_at)
             VALUES (?, ?, ?, ?, ?, ?)
             "#,
         )
+        .bind(hash_token(token))
         .bind(file_id)
-        .bind(shared_by_id)
-        .bind(shared_with_id)
-        .bind(commitment)
-        .bind(Utc::now())
+        .bind(created_by_id)
+        .bind(bound_user_id)
+        .bind(now)
         .bind(expires_at)
         .execute(&self.pool)
         .await?;
         
+        Ok(DownloadLink {
+            token_hash: hash_token(token),
+            file_id,
+            created_by_id,
+            bound_user_id,
+            created_at: now,
+            expires_at,
+        })
+    }
+    
+    pub async fn get_download_link(&self, token: &str) -> Result<Option<DownloadLink>> {
+        let link = sqlx::query_as::<_, DownloadLink>(
+            "SELECT token_hash, file_id, created_by_id, bound_user_id, created_at, expires_at FROM download_links WHERE token_hash = ?"
+        )
+        .bind(hash_token(token))
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(link)
+    }
+    
+    // Insert or overwrite one attribute
+    pub async fn set_attribute(&self, file_id: i64, key: &str, value: &str) -> Result<()> {
+        let key = bounded("attribute key", key, self.limits.attribute_key)?;
+        let value = bounded("attribute value", value, self.limits.attribute_value)?;
+        sqlx::query(
+            r#"
+            INSERT INTO file_attributes (file_id, key, value) VALUES (?, ?, ?)
+            ON CONFLICT(file_id, key) DO UPDATE SET value = excluded.value
+            "#
+        )
+        .bind(file_id)
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        
         Ok(())
     }
     
-    pub async fn get_shared_files(&self, username: &str) -> Result<Vec<SharedFile>> {
-        let shares = sqlx::query_as::<_, SharedFile>(
+    pub async fn remove_attribute(&self, file_id: i64, key: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM file_attributes WHERE file_id = ? AND key = ?")
+            .bind(file_id)
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(result.rows_affected() > 0)
+    }
+    
+    pub async fn get_attributes(&self, file_id: i64) -> Result<HashMap<String, String>> {
+        let rows = sqlx::query("SELECT key, value FROM file_attributes WHERE file_id = ?")
+            .bind(file_id)
+            .fetch_all(&self.pool)
+            .await?;
+        
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+    
+    pub async fn record_audit(&self, user_id: Option<i64>, action: &str, target: Option<&str>) -> Result<()> {
+        sqlx::query("INSERT INTO audit_log (user_id, action, target, timestamp) VALUES (?, ?, ?, ?)")
+            .bind(user_id)
+            .bind(action)
+            .bind(target)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    // Login failures, lockouts and the like, shown to the user at their next login
+    pub async fn record_security_event(&self, user_id: i64, kind: &str, detail: Option<&str>) -> Result<()> {
+        sqlx::query("INSERT INTO security_events (user_id, kind, detail, created_at) VALUES (?, ?, ?, ?)")
+            .bind(user_id)
+            .bind(kind)
+            .bind(detail)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    // Oldest first; every event when `since` is None
+    pub async fn security_events_since(&self, user_id: i64, since: Option<DateTime<Utc>>) -> Result<Vec<SecurityEvent>> {
+        let events = sqlx::query_as::<_, SecurityEvent>(
             r#"
-            SELECT 
-                s.id,
-                f.id as file_id,
-                f.filename,
-                u_sender.username as shared_by,
-                s.shared_with_id,
-                u_receiver.username as shared_with_username,
-                s.commitment,
-                s.shared_at,
-                s.expires_at
-            FROM shares s
-            JOIN files f ON s.file_id = f.id
-            JOIN users u_sender ON s.shared_by_id = u_sender.id
-            JOIN users u_receiver ON s.shared_with_id = u_receiver.id
-            WHERE u_receiver.username = ?
-            ORDER BY s.shared_at DESC
+            SELECT id, user_id, kind, detail, created_at
+            FROM security_events
+            WHERE user_id = ? AND (? IS NULL OR created_at > ?)
+            ORDER BY created_at, id
+            "#,
+        )
+        .bind(user_id)
+        .bind(since)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(events)
+    }
+    
+    pub async fn count_security_events(&self, user_id: i64, kind: &str, since: DateTime<Utc>) -> Result<i64> {
+        let count = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM security_events WHERE user_id = ? AND kind = ? AND created_at > ?"
+        )
+        .bind(user_id)
+        .bind(kind)
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await?;
+        
+        Ok(count)
+    }
+    
+    pub async fn last_security_event(&self, user_id: i64, kind: &str) -> Result<Option<DateTime<Utc>>> {
+        let at = sqlx::query_scalar(
+            "SELECT MAX(created_at) FROM security_events WHERE user_id = ? AND kind = ?"
+        )
+        .bind(user_id)
+        .bind(kind)
+        .fetch_one(&self.pool)
+        .await?;
+        
+        Ok(at)
+    }
+    
+    // Newest first. A limit of 0 or above MAX_AUDIT_LIMIT is clamped to the cap.
+    pub async fn query_audit(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            r#"
+            SELECT a.id, a.user_id, u.username, a.action, a.target, a.timestamp
+            FROM audit_log a
+            LEFT JOIN users u ON a.user_id = u.id
+            WHERE 1 = 1
             "#
+        );
+        if let Some(username) = &filter.user {
+            match self.get_user_by_username(username).await? {
+                Some(user) => query.push(" AND a.user_id = ").push_bind(user.id),
+                None => return Ok(Vec::new()),
+            };
+        }
+        if let Some(action) = &filter.action {
+            query.push(" AND a.action = ").push_bind(action);
+        }
+        if let Some(since) = filter.since {
+            query.push(" AND a.timestamp >= ").push_bind(since);
+        }
+        if let Some(until) = filter.until {
+            query.push(" AND a.timestamp < ").push_bind(until);
+        }
+        
+        let limit = if filter.limit <= 0 { MAX_AUDIT_LIMIT } else { filter.limit.min(MAX_AUDIT_LIMIT) };
+        query.push(" ORDER BY a.timestamp DESC, a.id DESC LIMIT ").push_bind(limit)
+            .push(" OFFSET ").push_bind(filter.offset.max(0));
+        
+        let entries = query.build_query_as::<AuditEntry>()
+            .fetch_all(&self.pool)
+            .await?;
+        
+        Ok(entries)
+    }
+    
+    pub async fn create_group(&self, name: &str, owner_id: i64) -> Result<Group> {
+        let now = Utc::now();
+        
+        let id: i64 = sqlx::query(
+            r#"
+            INSERT INTO groups (name, owner_id, created_at)
+            VALUES (?, ?, ?)
+            RETURNING id
+            "#,
         )
-        .bind(username)
+        .bind(name)
+        .bind(owner_id)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                FileSharingError::GroupNameTaken(name.to_string()).into()
+            }
+            e => anyhow::Error::from(e),
+        })?
+        .get(0);
+        
+        Ok(Group { id, name: name.to_string(), owner_id, created_at: now })
+    }
+    
+    pub async fn get_group_by_name(&self, name: &str) -> Result<Option<Group>> {
+        let group = sqlx::query_as::<_, Group>(
+            "SELECT id, name, owner_id, created_at FROM groups WHERE name = ?"
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+        
+        Ok(group)
+    }
+    
+    // Groups a user owns or belongs to
+    pub async fn get_user_groups(&self, user_id: i64) -> Result<Vec<Group>> {
+        let groups = sqlx::query_as::<_, Group>(
+            r#"
+            SELECT id, name, owner_id, created_at
+            FROM groups
+            WHERE owner_id = ? OR id IN (SELECT group_id FROM group_members WHERE user_id = ?)
+            ORDER BY name
+            "#
+        )
+        .bind(user_id)
+        .bind(user_id)
         .fetch_all(&self.pool)
         .await?;
         
-        Ok(shares)
+        Ok(groups)
+    }
+    
+    // Adding an existing member is a no-op; returns whether the user was added
+    pub async fn add_member(&self, group_id: i64, user_id: i64) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT OR IGNORE INTO group_members (group_id, user_id, added_at) VALUES (?, ?, ?)"
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(result.rows_affected() > 0)
+    }
+    
+    pub async fn get_group_members(&self, group_id: i64) -> Result<Vec<User>> {
+        let users = sqlx::query_as::<_, User>(&format!(
+            r#"
+            SELECT {}
+            FROM users
+            WHERE id IN (SELECT user_id FROM group_members WHERE group_id = ?)
+            ORDER BY username
+            "#,
+            USER_COLUMNS
+        ))
+        .bind(group_id)
+        .fetch_all(&self.pool)
+        .await?;
+        
+        Ok(users)
+    }
+    
+    // Share one file with several users atomically. Users who already have a
+    // share of it keep theirs; returns how many new shares were created.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_shares(
+        &self,
+        file_id: i64,
+        shared_by_id: i64,
+        shared_with_ids: &[i64],
+        commitment: Option<&[u8]>,
+        commitment_scheme: CommitmentKind,
+        proof: Option<&ShareProof>,
+        permission: SharePermission,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let now = Utc::now();
+        let mut created = 0;
+        
+        for &shared_with_id in shared_with_ids {
+            created += sqlx::query(
+                r#"
+                INSERT INTO shares (public_id, file_id, shared_by_id, shared_with_id, commitment, commitment_scheme, proof, permission, shared_at, expires_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(file_id, shared_with_id) DO NOTHING
+                "#,
+            )
+            .bind(new_public_id())
+            .bind(file_id)
+            .bind(shared_by_id)
+            .bind(shared_with_id)
+            .bind(commitment)
+            .bind(commitment_scheme)
+            .bind(proof.map(Json))
+            .bind(permission)
+            .bind(now)
+            .bind(expires_at)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+        
+        tx.commit().await?;
+        Ok(created)
     }
     
     pub async fn get_system_stats(&self) -> Result<SystemStats> {
@@ -376,40 +1964,41 @@ impl Database {
             .await?
             .get(0);
         
-        // Get total files
-        let total_files: i64 = sqlx::query("SELECT COUNT(*) FROM files")
+        // Get total files (recycled and superseded ones aren't counted anywhere below)
+        let total_files: i64 = sqlx::query(&format!("SELECT COUNT(*) FROM {}", CURRENT_FILES))
             .fetch_one(&self.pool)
             .await?
             .get(0);
         
         // Get unique files (by hash)
-        let unique_files: i64 = sqlx::query("SELECT COUNT(DISTINCT hash) FROM files")
+        let unique_files: i64 = sqlx::query(&format!("SELECT COUNT(DISTINCT hash) FROM {}", CURRENT_FILES))
             .fetch_one(&self.pool)
             .await?
             .get(0);
         
         // Get total shares
-        let total_shares: i64 = sqlx::query("SELECT COUNT(*) FROM shares")
+        let total_shares: i64 = sqlx::query(&format!(
+            "SELECT COUNT(*) FROM shares s JOIN {} f ON s.file_id = f.id", CURRENT_FILES
+        ))
             .fetch_one(&self.pool)
             .await?
             .get(0);
         
         // Get total bytes
-        let total_bytes: i64 = sqlx::query("SELECT COALESCE(SUM(size), 0) FROM files")
+        let total_bytes: i64 = sqlx::query(&format!("SELECT COALESCE(SUM(size), 0) FROM {}", CURRENT_FILES))
             .fetch_one(&self.pool)
             .await?
             .get(0);
         
-        // Calculate saved bytes (deduplication)
-        let saved_bytes = if total_files > unique_files {
-            let avg_size: f64 = sqlx::query("SELECT COALESCE(AVG(size), 0) FROM files")
-                .fetch_one(&self.pool)
-                .await?
-                .get(0);
-            ((total_files - unique_files) as f64 * avg_size) as i64
-        } else {
-            0
-        };
+        // Calculate saved bytes (deduplication): everything beyond one copy per hash,
+        // the same quantity the storage engine counts as uploads come in
+        let stored_bytes: i64 = sqlx::query(&format!(
+            "SELECT COALESCE(SUM(size), 0) FROM (SELECT MAX(size) AS size FROM {} GROUP BY hash)", CURRENT_FILES
+        ))
+            .fetch_one(&self.pool)
+            .await?
+            .get(0);
+        let saved_bytes = total_bytes - stored_bytes;
         
         let dedup_rate = if total_bytes > 0 {
             (saved_bytes as f64 / total_bytes as f64) * 100.0
@@ -428,4 +2017,376 @@ impl Database {
             bloom_fp_rate: 0.01,
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::SortField;
+    use tempfile::TempDir;
+    
+    async fn open_db() -> (TempDir, Database) {
+        let dir = TempDir::new().unwrap();
+        let db = Database::with_config(DatabaseConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..DatabaseConfig::default()
+        }).await.unwrap();
+        (dir, db)
+    }
+    
+    #[tokio::test]
+    async fn foreign_keys_are_enforced() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let bob = db.create_user("bob", "hash", None).await.unwrap();
+        
+        let result = db.create_share(
+            9999, alice.id, bob.id, None, CommitmentKind::default(), None, SharePermission::Read, None,
+        ).await;
+        assert!(result.is_err());
+    }
+    
+    #[tokio::test]
+    async fn connection_pragmas_are_applied() {
+        let (_dir, db) = open_db().await;
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode").fetch_one(&db.pool).await.unwrap();
+        assert_eq!(journal_mode, "wal");
+        for (pragma, expected) in [("synchronous", 1), ("foreign_keys", 1), ("busy_timeout", 5000)] {
+            let value: i64 = sqlx::query_scalar(&format!("PRAGMA {}", pragma)).fetch_one(&db.pool).await.unwrap();
+            assert_eq!(value, expected, "{}", pragma);
+        }
+    }
+    
+    // A row for a made-up SHA-256 hash `prefix` followed by `last`, enough for lookups
+    async fn file_with_hash(db: &Database, owner_id: i64, prefix: &[u8], last: u8) -> FileRecord {
+        let mut bytes = prefix.to_vec();
+        bytes.resize(31, 0);
+        bytes.push(last);
+        let hash = HashValue::from_bytes(HashAlgo::Sha256, bytes).unwrap();
+        db.save_file(&hash, "f.txt", 1, owner_id, None, 1, &hash, "text/plain", None, None).await.unwrap()
+    }
+    
+    #[tokio::test]
+    async fn short_hash_resolves_a_unique_prefix() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let file = file_with_hash(&db, alice.id, &[0xab, 0xcd, 0xef, 0x01], 1).await;
+        file_with_hash(&db, alice.id, &[0x12, 0x34, 0x56, 0x78], 1).await;
+        
+        assert_eq!(db.resolve_short_hash("ABCDEF01", alice.id).await.unwrap().id, file.id);
+        assert_eq!(db.resolve_short_hash(&file.hash, alice.id).await.unwrap().id, file.id);
+    }
+    
+    #[tokio::test]
+    async fn short_hash_reports_ambiguity() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let a = file_with_hash(&db, alice.id, &[0xab, 0xcd, 0xef, 0x01], 1).await;
+        let b = file_with_hash(&db, alice.id, &[0xab, 0xcd, 0xef, 0x01], 2).await;
+        
+        let err = db.resolve_short_hash("abcdef01", alice.id).await.unwrap_err();
+        match err.downcast_ref::<FileSharingError>() {
+            Some(FileSharingError::AmbiguousHash { candidates }) => {
+                let mut candidates = candidates.clone();
+                candidates.sort();
+                assert_eq!(candidates, vec![a.hash, b.hash]);
+            }
+            other => panic!("expected AmbiguousHash, got {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn short_hash_rejects_short_or_non_hex_prefixes() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        file_with_hash(&db, alice.id, &[0xab, 0xcd, 0xef, 0x01], 1).await;
+        
+        for input in ["abcdef0", "abcdef0%", "abcdef_1"] {
+            let err = db.resolve_short_hash(input, alice.id).await.unwrap_err();
+            assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidHash(_))), "{}", input);
+        }
+    }
+    
+    #[tokio::test]
+    async fn short_hash_only_sees_own_and_shared_files() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let bob = db.create_user("bob", "hash", None).await.unwrap();
+        let own = file_with_hash(&db, alice.id, &[0xab, 0xcd, 0xef, 0x01], 1).await;
+        file_with_hash(&db, bob.id, &[0xab, 0xcd, 0xef, 0x01], 2).await;
+        let bobs = file_with_hash(&db, bob.id, &[0x11, 0x22, 0x33, 0x44], 1).await;
+        
+        // Bob's file with the same prefix is neither a match nor an ambiguity
+        assert_eq!(db.resolve_short_hash("abcdef01", alice.id).await.unwrap().id, own.id);
+        assert!(db.resolve_short_hash("11223344", alice.id).await.is_err());
+        
+        db.create_share(bobs.id, bob.id, alice.id, None, CommitmentKind::default(), None, SharePermission::Read, None)
+            .await.unwrap();
+        assert_eq!(db.resolve_short_hash("11223344", alice.id).await.unwrap().id, bobs.id);
+        
+        db.set_file_deleted(own.id, Some(Utc::now())).await.unwrap();
+        assert!(db.resolve_short_hash("abcdef01", alice.id).await.is_err());
+    }
+    
+    async fn audit_at(db: &Database, user_id: Option<i64>, action: &str, timestamp: DateTime<Utc>) {
+        sqlx::query("INSERT INTO audit_log (user_id, action, target, timestamp) VALUES (?, ?, NULL, ?)")
+            .bind(user_id)
+            .bind(action)
+            .bind(timestamp)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+    }
+    
+    #[tokio::test]
+    async fn audit_filters_each_narrow_the_results() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let bob = db.create_user("bob", "hash", None).await.unwrap();
+        let start = Utc::now() - chrono::Duration::days(10);
+        let day = |d: i64| start + chrono::Duration::days(d);
+        audit_at(&db, Some(alice.id), "login", day(1)).await;
+        audit_at(&db, Some(alice.id), "upload", day(2)).await;
+        audit_at(&db, Some(bob.id), "login", day(3)).await;
+        audit_at(&db, Some(bob.id), "share", day(4)).await;
+        audit_at(&db, None, "quarantine", day(5)).await;
+        
+        let actions = |entries: Vec<AuditEntry>| entries.into_iter().map(|e| e.action).collect::<Vec<_>>();
+        let all = db.query_audit(&AuditFilter::default()).await.unwrap();
+        assert_eq!(actions(all), ["quarantine", "share", "login", "upload", "login"]);
+        
+        let by_user = db.query_audit(&AuditFilter { user: Some("alice".to_string()), ..Default::default() }).await.unwrap();
+        assert_eq!(actions(by_user), ["upload", "login"]);
+        let by_action = db.query_audit(&AuditFilter { action: Some("login".to_string()), ..Default::default() }).await.unwrap();
+        assert_eq!(by_action.iter().map(|e| e.username.as_deref()).collect::<Vec<_>>(), [Some("bob"), Some("alice")]);
+        let window = db.query_audit(&AuditFilter { since: Some(day(2)), until: Some(day(4)), ..Default::default() }).await.unwrap();
+        assert_eq!(actions(window), ["login", "upload"]); // `until` is exclusive
+        let page = db.query_audit(&AuditFilter { limit: 2, offset: 1, ..Default::default() }).await.unwrap();
+        assert_eq!(actions(page), ["share", "login"]);
+    }
+    
+    #[tokio::test]
+    async fn audit_limit_is_capped() {
+        let (_dir, db) = open_db().await;
+        sqlx::query(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?) \
+             INSERT INTO audit_log (user_id, action, target, timestamp) SELECT NULL, 'bulk', NULL, ? FROM n"
+        )
+        .bind(MAX_AUDIT_LIMIT + 10)
+        .bind(Utc::now())
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        
+        for limit in [0, MAX_AUDIT_LIMIT + 1, i64::MAX] {
+            let entries = db.query_audit(&AuditFilter { limit, ..Default::default() }).await.unwrap();
+            assert_eq!(entries.len() as i64, MAX_AUDIT_LIMIT, "limit {}", limit);
+        }
+        assert_eq!(db.query_audit(&AuditFilter { limit: 3, ..Default::default() }).await.unwrap().len(), 3);
+    }
+    
+    #[tokio::test]
+    async fn share_public_ids_are_random_and_resolve_back() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let file = file_with_hash(&db, alice.id, &[0x42], 1).await;
+        let mut shares = Vec::new();
+        for name in ["bob", "carol", "dave"] {
+            let user = db.create_user(name, "hash", None).await.unwrap();
+            db.create_share(file.id, alice.id, user.id, None, CommitmentKind::default(), None, SharePermission::Read, None)
+                .await.unwrap();
+            shares.push(db.get_shared_files(name).await.unwrap().remove(0));
+        }
+        
+        for share in &shares {
+            assert_eq!(share.public_id.len(), 32);
+            assert!(share.public_id.bytes().all(|b| b.is_ascii_hexdigit()));
+            assert_ne!(share.public_id, share.id.to_string());
+            let found = db.get_share_by_public_id(&share.public_id).await.unwrap().unwrap();
+            assert_eq!((found.id, found.shared_with_username.as_str()), (share.id, share.shared_with_username.as_str()));
+        }
+        let mut ids: Vec<&str> = shares.iter().map(|s| s.public_id.as_str()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 3);
+        assert!(db.get_share_by_public_id(&"0".repeat(32)).await.unwrap().is_none());
+        
+        // Re-sharing updates the share in place and keeps its public reference
+        let bob = db.get_user_by_username("bob").await.unwrap().unwrap();
+        db.create_share(file.id, alice.id, bob.id, None, CommitmentKind::default(), None, SharePermission::Reshare, None)
+            .await.unwrap();
+        assert_eq!(db.get_shared_files("bob").await.unwrap()[0].public_id, shares[0].public_id);
+    }
+    
+    #[tokio::test]
+    async fn download_link_tokens_are_stored_hashed() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let file = file_with_hash(&db, alice.id, &[0x42], 1).await;
+        let token = "0123456789abcdef".repeat(4);
+        db.create_download_link(&token, file.id, alice.id, None, None).await.unwrap();
+        
+        let stored: Vec<String> = sqlx::query_scalar("SELECT token_hash FROM download_links")
+            .fetch_all(&db.pool).await.unwrap();
+        assert_eq!(stored, vec![hash_token(&token)]);
+        assert_ne!(stored[0], token);
+        assert_eq!(db.get_download_link(&token).await.unwrap().unwrap().file_id, file.id);
+        assert!(db.get_download_link(&stored[0]).await.unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn query_files_combines_filters_and_sorts() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let start = Utc::now() - chrono::Duration::days(30);
+        for (i, (name, size, day)) in [
+            ("report-q1.pdf", 300, 1),
+            ("report_q2.pdf", 100, 10),
+            ("notes.txt", 200, 12),
+            ("report-q3.pdf", 200, 20),
+        ].into_iter().enumerate() {
+            let hash = HashValue::compute(&[i as u8], HashAlgo::Sha256);
+            let at = start + chrono::Duration::days(day);
+            db.save_file(&hash, name, size, alice.id, None, 1, &hash, "text/plain", Some(at), Some(at)).await.unwrap();
+        }
+        let names = |files: Vec<FileRecord>| files.into_iter().map(|f| f.filename).collect::<Vec<_>>();
+        
+        let by_size = db.query_files(alice.id, &FileQuery { sort_by: SortField::Size, ascending: true, ..Default::default() }).await.unwrap();
+        assert_eq!(by_size.iter().map(|f| f.size).collect::<Vec<_>>(), [100, 200, 200, 300]);
+        
+        let reports = db.query_files(alice.id, &FileQuery {
+            sort_by: SortField::Size,
+            ascending: true,
+            name_contains: Some("report".to_string()),
+            created_after: Some(start + chrono::Duration::days(5)),
+            created_before: Some(start + chrono::Duration::days(20)),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(names(reports), ["report_q2.pdf"]);
+        
+        let underscore = db.query_files(alice.id, &FileQuery { name_contains: Some("_".to_string()), ..Default::default() }).await.unwrap();
+        assert_eq!(names(underscore), ["report_q2.pdf"]);
+        
+        let sized = db.query_files(alice.id, &FileQuery { min_size: Some(150), max_size: Some(250), ..Default::default() }).await.unwrap();
+        assert_eq!(names(sized), ["report-q3.pdf", "notes.txt"]);
+    }
+    
+    #[test]
+    fn sort_field_only_accepts_known_columns() {
+        assert_eq!("Size".parse::<SortField>().unwrap(), SortField::Size);
+        assert!(matches!("size; DROP TABLE files".parse::<SortField>(), Err(FileSharingError::UnknownSortField(_))));
+    }
+    
+    #[tokio::test]
+    async fn close_checkpoints_the_wal_and_data_survives_a_reopen() {
+        let (dir, db) = open_db().await;
+        db.create_user("alice", "hash", None).await.unwrap();
+        let clone = db.clone();
+        db.close().await;
+        clone.close().await;
+        
+        let wal = dir.path().join("secure_files.db-wal");
+        assert!(std::fs::metadata(&wal).map_or(true, |m| m.len() == 0), "WAL left behind");
+        
+        let reopened = Database::with_config(DatabaseConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..DatabaseConfig::default()
+        }).await.unwrap();
+        assert!(reopened.get_user_by_username("alice").await.unwrap().is_some());
+    }
+    
+    #[tokio::test]
+    async fn quarantine_of_one_copy_spreads_when_another_fails() {
+        let (_dir, db) = open_db().await;
+        let hash = HashValue::compute(b"two copies", HashAlgo::Sha256);
+        db.quarantine_file(&hash, Some("alice"), "bad chunk").await.unwrap();
+        assert!(db.get_quarantine(&hash, Some("alice")).await.unwrap().is_some());
+        assert!(db.get_quarantine(&hash, Some("bob")).await.unwrap().is_none());
+        assert!(db.is_quarantined(&hash).await.unwrap());
+        
+        db.quarantine_file(&hash, Some("bob"), "bad chunk").await.unwrap();
+        let entry = db.get_quarantine(&hash, Some("bob")).await.unwrap().unwrap();
+        assert_eq!(entry.copy_dir, None);
+        assert!(db.get_quarantine(&hash, Some("alice")).await.unwrap().is_some());
+    }
+    
+    #[tokio::test]
+    async fn sharing_twice_refreshes_the_sharers_own_share() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let bob = db.create_user("bob", "hash", None).await.unwrap();
+        let carol = db.create_user("carol", "hash", None).await.unwrap();
+        let file = file_with_hash(&db, alice.id, &[0x42], 1).await;
+        let soon = Utc::now() + chrono::Duration::days(1);
+        let later = Utc::now() + chrono::Duration::days(7);
+        
+        db.create_share(file.id, alice.id, bob.id, None, CommitmentKind::default(), None, SharePermission::Read, Some(soon))
+            .await.unwrap();
+        db.create_share(file.id, alice.id, bob.id, None, CommitmentKind::default(), None, SharePermission::Reshare, Some(later))
+            .await.unwrap();
+        let shares = db.get_shared_files("bob").await.unwrap();
+        assert_eq!(shares.len(), 1);
+        assert_eq!(shares[0].permission, SharePermission::Reshare);
+        assert_eq!(shares[0].expires_at.map(|t| t.timestamp()), Some(later.timestamp()));
+        
+        // Someone else sharing to the same recipient leaves alice's share as it was
+        db.create_share(file.id, carol.id, bob.id, None, CommitmentKind::default(), None, SharePermission::Read, None)
+            .await.unwrap();
+        let shares = db.get_shared_files("bob").await.unwrap();
+        assert_eq!(shares.len(), 1);
+        assert_eq!((shares[0].permission, shares[0].expires_at.map(|t| t.timestamp())), (SharePermission::Reshare, Some(later.timestamp())));
+    }
+    
+    #[tokio::test]
+    async fn free_form_fields_are_trimmed_and_length_limited() {
+        let dir = TempDir::new().unwrap();
+        let db = Database::with_config(DatabaseConfig {
+            data_dir: dir.path().to_path_buf(),
+            limits: FieldLimits { description: 8, attribute_key: 4, attribute_value: 8 },
+            ..DatabaseConfig::default()
+        }).await.unwrap();
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let hash = HashValue::compute(b"limited", HashAlgo::Sha256);
+        
+        let err = db.save_file(&hash, "f.txt", 1, alice.id, Some("123456789"), 1, &hash, "text/plain", None, None)
+            .await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::FieldTooLong { max: 8, .. })));
+        // Surrounding whitespace doesn't count against the limit and isn't stored
+        let file = db.save_file(&hash, "f.txt", 1, alice.id, Some("  12345678\n"), 1, &hash, "text/plain", None, None)
+            .await.unwrap();
+        assert_eq!(file.description.as_deref(), Some("12345678"));
+        let file = db.refresh_file(file.id, Some("   ")).await.unwrap();
+        assert_eq!(file.description.as_deref(), Some("12345678"));
+        assert!(db.refresh_file(file.id, Some("far too long")).await.is_err());
+        
+        db.set_attribute(file.id, " tag ", " draft ").await.unwrap();
+        assert_eq!(db.get_attributes(file.id).await.unwrap().get("tag").map(String::as_str), Some("draft"));
+        for (key, value) in [("toolong", "x"), ("tag", "123456789")] {
+            let err = db.set_attribute(file.id, key, value).await.unwrap_err();
+            assert!(matches!(err.downcast_ref(), Some(FileSharingError::FieldTooLong { .. })), "{key}");
+        }
+    }
+    
+    #[tokio::test]
+    async fn file_and_share_pages_cover_every_row_once() {
+        let (_dir, db) = open_db().await;
+        let alice = db.create_user("alice", "hash", None).await.unwrap();
+        let bob = db.create_user("bob", "hash", None).await.unwrap();
+        for last in 0..5 {
+            let file = file_with_hash(&db, alice.id, &[0x10], last).await;
+            db.create_share(file.id, alice.id, bob.id, None, CommitmentKind::default(), None, SharePermission::Read, None)
+                .await.unwrap();
+        }
+        
+        let mut files = Vec::new();
+        let mut shares = Vec::new();
+        for offset in (0..6).step_by(2) {
+            files.extend(db.get_user_files_page(alice.id, 2, offset).await.unwrap().into_iter().map(|f| f.id));
+            shares.extend(db.get_outgoing_shares_page(alice.id, 2, offset).await.unwrap().into_iter().map(|s| s.file_id));
+        }
+        let mut all: Vec<i64> = db.get_user_files("alice").await.unwrap().into_iter().map(|f| f.id).collect();
+        all.sort();
+        assert_eq!(files, all);
+        assert_eq!(shares, db.get_outgoing_shares(alice.id).await.unwrap().into_iter().map(|s| s.file_id).collect::<Vec<_>>());
+        assert_eq!(shares.len(), 5);
+    }
+}
@@ -5,5 +5,5 @@
 pub mod models;
 pub mod database;
 
-pub use database::Database;
-pub use models::{User, FileRecord, SharedFile, SystemStats};
\ No newline at end of file
+pub use database::{Database, DatabaseConfig, FieldLimits};
+pub use models::{User, UserSummary, UserActivity, FileRecord, SharedFile, SharePermission, SystemStats, QuarantinedFile, DownloadLink, Group, OutgoingShare, ShareStatus, AuditEntry, AuditFilter, FileQuery, SecurityEvent, ShareProof, SortField};
\ No newline at end of file
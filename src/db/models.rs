@@ -5,6 +5,14 @@
 use serde::{Serialize, Deserialize};
 use chrono::{DateTime, Utc};
 use sqlx::FromRow;
+use crate::crypto::commitment::CommitmentKind;
+use crate::core::file_metadata::FileMetadata;
+use crate::core::merkle_tree::MerkleTree;
+use crate::crypto::hash::{HashAlgo, HashValue};
+use crate::storage::engine::CHUNK_SIZE;
+use sqlx::types::Json;
+use crate::error::FileSharingError;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct User {
@@ -13,34 +21,150 @@ pub struct User {
     pub password_hash: String,
     pub email: Option<String>,
     pub public_key: Option<Vec<u8>>,
+    pub is_admin: bool,
     pub created_at: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct UserSummary {
+    pub id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub is_admin: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_login: Option<DateTime<Utc>>,
+    pub file_count: i64,
+    pub total_bytes: i64,
+}
+
+// One user's own activity; counts are zero and times None for a new account
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct UserActivity {
+    pub last_login: Option<DateTime<Utc>>,
+    pub file_count: i64,
+    pub total_bytes: i64,
+    pub share_count: i64, // shares this user has made
+    pub last_upload: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct FileRecord {
     pub id: i64,
     pub hash: String,
+    pub hash_algo: String,
     pub filename: String,
     pub size: i64,
     pub owner_id: i64,
     pub description: Option<String>,
     pub chunks: i32,
     pub merkle_root: String,
+    pub mime_type: String,
     pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+    pub previous_version_id: Option<i64>, // set when this row replaced older content
+    pub deleted_at: Option<DateTime<Utc>>, // in the recycle bin since then
+}
+
+impl FileRecord {
+    // "report (ab12cd34).pdf": the filename with a short hash before the extension
+    pub fn name_with_short_hash(&self) -> String {
+        let short = &self.hash[..8.min(self.hash.len())];
+        match self.filename.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => format!("{} ({}).{}", stem, short, ext),
+            _ => format!("{} ({})", self.filename, short),
+        }
+    }
+    
+    pub fn hash_value(&self) -> anyhow::Result<HashValue> {
+        Ok(HashValue::from_bytes(self.hash_algo.parse()?, hex::decode(&self.hash)?)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(rename_all = "lowercase")]
+pub enum SharePermission {
+    Read,    // recipient may download only
+    Reshare, // recipient may also share the file onward
 }
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct SharedFile {
     pub id: i64,
+    pub public_id: String, // random reference for links and URLs; `id` stays internal
     pub file_id: i64,
     pub filename: String,
     pub shared_by: String,
     pub shared_with_id: i64,
     pub shared_with_username: String,
     pub commitment: Option<Vec<u8>>,
+    pub commitment_scheme: CommitmentKind,
+    pub permission: SharePermission,
     pub shared_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    pub proof: Option<Json<ShareProof>>, // None for shares made before proofs were recorded
+}
+
+// Everything besides the commitment a recipient needs to check received bytes,
+// so verification never has to fetch the file's metadata
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareProof {
+    pub size: u64,
+    pub hash_algo: HashAlgo,    // whole-file hash the commitment is over
+    pub merkle_root: HashValue, // its algorithm is the chunk algorithm
+    pub merkle_arity: usize,
+    pub chunk_size: u64,        // every chunk but the last, which may be longer
+    pub chunk_count: usize,
+}
+
+impl ShareProof {
+    pub fn from_metadata(metadata: &FileMetadata) -> Self {
+        Self {
+            size: metadata.size,
+            hash_algo: metadata.hash.algo,
+            merkle_root: metadata.merkle_root.clone(),
+            merkle_arity: metadata.merkle_arity,
+            chunk_size: metadata.chunk_sizes.first().copied().unwrap_or(CHUNK_SIZE as u64),
+            chunk_count: metadata.chunks.len(),
+        }
+    }
+
+    // Size and Merkle root of `data`, chunked the way the shared copy was
+    pub fn matches(&self, data: &[u8]) -> bool {
+        if data.len() as u64 != self.size {
+            return false;
+        }
+        let full = self.chunk_count.saturating_sub(1) as u64 * self.chunk_size;
+        if self.chunk_count == 0 || self.chunk_size == 0 || full >= self.size {
+            return self.chunk_count == 0 && MerkleTree::new(&[]).root() == self.merkle_root;
+        }
+
+        let (head, last) = data.split_at(full as usize);
+        let algo = self.merkle_root.algo;
+        let leaves: Vec<HashValue> = head.chunks(self.chunk_size as usize)
+            .chain(std::iter::once(last))
+            .map(|chunk| HashValue::compute(chunk, algo))
+            .collect();
+        MerkleTree::with_arity(&leaves, self.merkle_arity).root() == self.merkle_root
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShareStatus {
+    Active,
+    Expired,
+}
+
+// A share as seen by the user who made it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutgoingShare {
+    pub file_id: i64,
+    pub filename: String,
+    pub shared_with_username: String,
+    pub permission: SharePermission,
+    pub shared_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub status: ShareStatus,
 }
 
 #[derive(Debug, Clone)]
@@ -53,4 +177,116 @@ pub struct SystemStats {
     pub saved_bytes: i64,
     pub dedup_rate: f64,
     pub bloom_fp_rate: f64,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct QuarantinedFile {
+    pub hash: String,
+    pub hash_algo: String,
+    pub copy_dir: Option<String>, // the one failing copy under per-user dedup; None = every copy
+    pub reason: String,
+    pub quarantined_at: DateTime<Utc>,
+}
+
+impl QuarantinedFile {
+    pub fn hash_value(&self) -> anyhow::Result<HashValue> {
+        Ok(HashValue::from_bytes(self.hash_algo.parse()?, hex::decode(&self.hash)?)?)
+    }
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub user_id: Option<i64>,
+    pub username: Option<String>,
+    pub action: String,         // e.g. login, upload, share, delete
+    pub target: Option<String>, // what the action was applied to, usually a file hash or username
+    pub timestamp: DateTime<Utc>,
+}
+
+// Something a user should hear about on their next login
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct SecurityEvent {
+    pub id: i64,
+    pub user_id: i64,
+    pub kind: String,           // login_failed or account_locked
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Every field narrows the query; the limit is capped at MAX_AUDIT_LIMIT
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub user: Option<String>,
+    pub action: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// Columns a file listing may be ordered by. User input goes through FromStr,
+// so only these names ever reach the ORDER BY clause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    ModifiedAt,
+    Filename,
+    Size,
+}
+
+impl SortField {
+    pub const ALL: [SortField; 4] = [SortField::CreatedAt, SortField::ModifiedAt, SortField::Filename, SortField::Size];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortField::CreatedAt => "created_at",
+            SortField::ModifiedAt => "modified_at",
+            SortField::Filename => "filename",
+            SortField::Size => "size",
+        }
+    }
+}
+
+impl FromStr for SortField {
+    type Err = FileSharingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        SortField::ALL.into_iter()
+            .find(|f| f.as_str().eq_ignore_ascii_case(name))
+            .ok_or_else(|| FileSharingError::UnknownSortField(name.to_string()))
+    }
+}
+
+// Every set field narrows the listing; bounds are inclusive for size,
+// `created_after` inclusive and `created_before` exclusive for dates
+#[derive(Debug, Clone, Default)]
+pub struct FileQuery {
+    pub sort_by: SortField,
+    pub ascending: bool,
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    pub name_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct Group {
+    pub id: i64,
+    pub name: String,
+    pub owner_id: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct DownloadLink {
+    pub token_hash: String, // the token itself is only ever held by its recipient
+    pub file_id: i64,
+    pub created_by_id: i64,
+    pub bound_user_id: Option<i64>, // only this user may redeem; None = anyone with the token
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
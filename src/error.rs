@@ -0,0 +1,89 @@
+// ============================================================================
+// Error Types
+// ============================================================================
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FileSharingError {
+    #[error("content rejected: {reason}")]
+    ContentRejected { reason: String },
+
+    #[error("username already taken: {0}")]
+    UsernameTaken(String),
+
+    #[error("password rejected: {0}")]
+    WeakPassword(String),
+
+    #[error("account locked after repeated failed logins; try again after {}", until.format("%Y-%m-%d %H:%M:%S UTC"))]
+    AccountLocked { until: chrono::DateTime<chrono::Utc> },
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("invalid hash: {0}")]
+    InvalidHash(String),
+
+    #[error("unknown hash algorithm: {0}")]
+    UnknownHashAlgo(String),
+
+    #[error("cannot sort files by {0}")]
+    UnknownSortField(String),
+
+    #[error("cannot share a file with yourself")]
+    CannotShareWithSelf,
+
+    #[error("{target} already owns this file")]
+    TargetAlreadyOwns { target: String },
+
+    #[error("too many recipients: {count} (max {max})")]
+    TooManyRecipients { count: usize, max: usize },
+
+    #[error("hash prefix matches {} files: {}", candidates.len(), candidates.join(", "))]
+    AmbiguousHash { candidates: Vec<String> },
+
+    #[error("merkle root mismatch for {hash}: database has {expected}, content gives {actual}")]
+    MerkleRootMismatch { hash: String, expected: String, actual: String },
+
+    #[error("insufficient disk space: need {needed} bytes, {available} available")]
+    InsufficientSpace { needed: u64, available: u64 },
+
+    #[error("hash algorithm {0} is not allowed by the upload policy")]
+    HashAlgoNotAllowed(String),
+
+    #[error("password reset token is invalid, expired or already used")]
+    InvalidResetToken,
+
+    #[error("invalid share string: {0}")]
+    InvalidShareString(String),
+
+    #[error("invalid download link: {0}")]
+    InvalidDownloadLink(String),
+
+    #[error("filename rejected: {0}")]
+    InvalidFilename(String),
+
+    #[error("attribute rejected: {0}")]
+    AttributeRejected(String),
+
+    #[error("{field} is longer than {max} bytes")]
+    FieldTooLong { field: String, max: usize },
+
+    #[error("group name already taken: {0}")]
+    GroupNameTaken(String),
+
+    #[error("invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+
+    #[error("file would need {count} chunks (max {max}); use a larger chunk size")]
+    TooManyChunks { count: usize, max: usize },
+
+    #[error("chunk {index} integrity check failed")]
+    ChunkCorrupt { index: usize },
+
+    #[error("chunk {index} does not match its recorded hash {expected}")]
+    ChunkMismatch { index: usize, expected: String },
+
+    #[error("file {hash} is quarantined: {reason}")]
+    Quarantined { hash: String, reason: String },
+}
@@ -3,7 +3,9 @@
 // ============================================================================
 
 use crate::crypto::hash::{HashAlgo, HashValue};
+use serde::{Serialize, Deserialize};
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BloomFilter {
     bits: Vec<bool>,
     hashers: Vec<HashAlgo>,
@@ -50,18 +52,41 @@ impl BloomFilter {
         true
     }
 
+    // Uses the first 8 digest bytes, or fewer if a (malformed) hash is shorter
     fn hash_to_index(&self, hash: &HashValue) -> usize {
         let mut val = 0u64;
-        for &b in &hash.bytes[..8] {
+        for &b in hash.bytes.iter().take(8) {
             val = (val << 8) | b as u64;
         }
         (val % self.size as u64) as usize
     }
 
+    pub fn len(&self) -> usize {
+        self.num_items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_items == 0
+    }
+
     pub fn false_positive_rate(&self) -> f64 {
         let k = self.hashers.len() as f64;
         let m = self.size as f64;
         let n = self.num_items as f64;
         (1.0 - (-k * n / m).exp()).powf(k)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_hash_does_not_panic_the_index() {
+        let filter = BloomFilter::new(100, 0.01);
+        let short = HashValue { algo: HashAlgo::Sha256, bytes: vec![0xab, 0xcd] };
+        assert_eq!(filter.hash_to_index(&short), 0xabcd % filter.size);
+        let empty = HashValue { algo: HashAlgo::Sha256, bytes: Vec::new() };
+        assert_eq!(filter.hash_to_index(&empty), 0);
+    }
+}
@@ -9,10 +9,15 @@ pub mod filter;
 pub mod auth;
 pub mod service;
 pub mod db;
+pub mod error;
+pub mod metrics;
+#[cfg(feature = "server")]
+pub mod server;
 
 // Re-export commonly used types
 pub use crypto::hash::{HashAlgo, HashValue};
 pub use core::file_metadata::FileMetadata;
 pub use service::file_sharing::FileSharingService;
 pub use db::database::Database;
-pub use db::models::{User, SharedFile};
\ No newline at end of file
+pub use db::models::{User, SharedFile};
+pub use error::FileSharingError;
\ No newline at end of file
@@ -2,22 +2,24 @@
 // Main CLI Application with Interactive Menu
 // ============================================================================
 
-// ============================================================================
-// Main CLI Application with Interactive Menu
-// ============================================================================
-
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use colored::*;
 use dialoguer::{Input, Password, Select};
 use secure_file_sharing::{
     FileSharingService, 
     Database, 
     HashValue,
-    HashAlgo,
+    FileSharingError,
 };
+use secure_file_sharing::core::file_metadata::upload_name;
+use secure_file_sharing::crypto::hash::{HashAlgo, DEFAULT_HASH_ENV};
+use secure_file_sharing::db::{AuditFilter, FileRecord, SharePermission, ShareStatus, SharedFile};
+use secure_file_sharing::service::file_sharing::{CollisionPolicy, ADMIN_USER_ENV};
 use std::path::Path;
+use tokio_util::sync::CancellationToken;
 use std::fs;
-use tokio;
+use std::io::Write;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -42,7 +44,22 @@ async fn main() -> Result<()> {
     fs::create_dir_all(storage_path)?;
     fs::create_dir_all(watch_path)?;
     
+    // Upload hash algorithm, chosen by SFS_DEFAULT_HASH (default sha256)
+    if let Err(e) = HashAlgo::from_env() {
+        println!("{} Invalid {}: {}", "❌".bright_red(), DEFAULT_HASH_ENV, e);
+        return Ok(());
+    }
+    
     let mut service = FileSharingService::new(storage_path, watch_path, database).await?;
+    println!("{} Upload hash algorithm: {}", "#️⃣".bright_cyan(), service.storage.hash_algo);
+    service.purge_expired().await?;
+    
+    // Administrators are appointed by whoever runs the binary, never by registering first
+    if let Ok(username) = std::env::var(ADMIN_USER_ENV) {
+        if let Err(e) = service.grant_admin(&username).await {
+            println!("{} Cannot make {} an administrator: {}", "❌".bright_red(), username, e);
+        }
+    }
     
     loop {
         println!("\n{}", "═══════════════════════════════════════".bright_blue());
@@ -58,8 +75,20 @@ async fn main() -> Result<()> {
             "6. Share File",
             "7. List Shared Files",
             "8. Verify File Integrity",
-            "9. System Statistics",
-            "10. Exit",
+            "9. Verify My Files",
+            "10. System Statistics",
+            "11. Change Password",
+            "12. Verify Local File",
+            "13. Compute All Hashes",
+            "14. My Activity",
+            "15. Delete File",
+            "16. Recycle Bin",
+            "17. My Shares",
+            "18. File Attributes",
+            "19. Groups",
+            "20. Admin Tools",
+            "21. Export CSV",
+            "22. Exit",
         ];
         
         let selection = Select::new()
@@ -77,8 +106,20 @@ async fn main() -> Result<()> {
             5 => share_file(&mut service).await?,
             6 => list_shared_files(&service).await?,
             7 => verify_file(&service).await?,
-            8 => print_stats(&service).await?,
-            9 => {
+            8 => verify_my_files(&service).await?,
+            9 => print_stats(&service).await?,
+            10 => change_password(&mut service).await?,
+            11 => verify_local_file(&service).await?,
+            12 => compute_all_hashes(&service).await?,
+            13 => my_activity(&service).await?,
+            14 => delete_file(&mut service).await?,
+            15 => recycle_bin(&mut service).await?,
+            16 => list_my_shares(&service).await?,
+            17 => file_attributes(&service).await?,
+            18 => manage_groups(&mut service).await?,
+            19 => admin_menu(&mut service).await?,
+            20 => export_listing_csv(&service).await?,
+            21 => {
                 println!("{}", "👋 Goodbye!".bright_green());
                 break;
             }
@@ -86,6 +127,7 @@ async fn main() -> Result<()> {
         }
     }
     
+    service.shutdown().await;
     Ok(())
 }
 
@@ -112,8 +154,18 @@ async fn register_user(service: &mut FileSharingService) -> Result<()> {
         .allow_empty(true)
         .interact_text()?;
     
-    let _user = service.register_user(&username, &password, 
-        if email.is_empty() { None } else { Some(&email) }).await?;
+    // The check above can race with another registration; the database has the final say
+    if let Err(e) = service.register_user(&username, &password, 
+        if email.is_empty() { None } else { Some(&email) }).await
+    {
+        match e.downcast_ref::<FileSharingError>() {
+            Some(FileSharingError::UsernameTaken(_)) => {
+                println!("{}", "❌ Username already exists!".bright_red());
+                return Ok(());
+            }
+            _ => return Err(e),
+        }
+    }
     
     println!("{} User '{}' registered successfully!", "✅".bright_green(), username.bright_cyan());
     Ok(())
@@ -130,13 +182,57 @@ async fn login_user(service: &mut FileSharingService) -> Result<()> {
         .with_prompt("Enter password")
         .interact()?;
     
-    match service.login(&username, &password).await? {
-        Some(_user) => {
-            println!("{} Welcome back, {}!", "✅".bright_green(), username.bright_cyan());
+    match service.login(&username, &password).await {
+        Ok(Some(user)) => {
+            println!("{} Welcome back, {}!", "✅".bright_green(), user.username.bright_cyan());
+            
+            let events = &service.login_security_events;
+            let failed = events.iter().filter(|e| e.kind == "login_failed").count();
+            let locks = events.iter().filter(|e| e.kind == "account_locked").count();
+            if failed > 0 {
+                println!("{} {} failed login attempt(s) since your last login", "⚠️".bright_yellow(), failed);
+            }
+            if locks > 0 {
+                println!("{} Your account was locked {} time(s) since your last login", "⚠️".bright_yellow(), locks);
+            }
         }
-        None => {
+        Ok(None) => {
             println!("{} Invalid username or password!", "❌".bright_red());
         }
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+        }
+    }
+    
+    Ok(())
+}
+
+async fn change_password(service: &mut FileSharingService) -> Result<()> {
+    println!("\n{}", "🔑 CHANGE PASSWORD".bright_magenta());
+    
+    if service.current_user.is_none() {
+        println!("{} Please login first!", "❌".bright_red());
+        return Ok(());
+    }
+    
+    let username = service.current_user.as_ref().unwrap().username.clone();
+    
+    let old_password: String = Password::new()
+        .with_prompt("Enter current password")
+        .interact()?;
+    
+    let new_password: String = Password::new()
+        .with_prompt("Enter new password")
+        .with_confirmation("Confirm new password", "Passwords don't match")
+        .interact()?;
+    
+    match service.change_password(&username, &old_password, &new_password).await {
+        Ok(()) => {
+            println!("{} Password changed! Please login again.", "✅".bright_green());
+        }
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+        }
     }
     
     Ok(())
@@ -165,22 +261,26 @@ async fn upload_file(service: &mut FileSharingService) -> Result<()> {
         .allow_empty(true)
         .interact_text()?;
     
+    let filename = match upload_name(path) {
+        Ok(name) => name,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
+    };
     let data = fs::read(path)?;
-    let filename = path.file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
     
     let username = service.current_user.as_ref().unwrap().username.clone();
     let metadata = service.upload_file(
         &data, 
         &filename, 
         &username,
-        if description.is_empty() { None } else { Some(&description) }
+        if description.is_empty() { None } else { Some(&description) },
+        None,
     ).await?;
     
     println!("{} File uploaded successfully!", "✅".bright_green());
-    println!("   Hash: {}", metadata.hash.to_hex().bright_cyan());
+    println!("   Hash: {}", metadata.hash.to_string().bright_cyan());
     println!("   Size: {} bytes", metadata.size.to_string().bright_yellow());
     println!("   Chunks: {}", metadata.chunks.len().to_string().bright_blue());
     
@@ -204,19 +304,22 @@ async fn list_my_files(service: &FileSharingService) -> Result<()> {
         return Ok(());
     }
     
-    println!("\n{:<5} {:<30} {:<10} {:<20}", 
+    println!("\n{:<5} {:<30} {:<10} {:<25} {:<20}", 
         "ID".bright_white(), 
         "Filename".bright_white(), 
         "Size".bright_white(), 
+        "Type".bright_white(),
         "Uploaded".bright_white()
     );
-    println!("{}", "─".repeat(70).bright_black());
+    println!("{}", "─".repeat(95).bright_black());
     
-    for (i, file) in files.iter().enumerate() {
-        println!("{:<5} {:<30} {:<10} {:<20}", 
+    let names = service.display_names(&files);
+    for (i, (file, name)) in files.iter().zip(&names).enumerate() {
+        println!("{:<5} {:<30} {:<10} {:<25} {:<20}", 
             (i+1).to_string().bright_blue(),
-            file.filename.chars().take(28).collect::<String>(),
+            name.chars().take(28).collect::<String>(),
             format!("{}B", file.size).bright_yellow(),
+            file.mime_type.chars().take(23).collect::<String>().bright_magenta(),
             file.created_at.format("%Y-%m-%d").to_string().bright_green()
         );
     }
@@ -241,38 +344,161 @@ async fn download_file(service: &FileSharingService) -> Result<()> {
         return Ok(());
     }
     
-    let filenames: Vec<String> = files.iter()
-        .map(|f| format!("{} ({} bytes)", f.filename, f.size))
-        .collect();
-    
-    let selection = Select::new()
-        .with_prompt("Select file to download")
-        .items(&filenames)
-        .interact()?;
-    
-    let selected = &files[selection];
+    let selected = match select_file(service, &files, "Select file to download").await? {
+        Some(file) => file,
+        None => return Ok(()),
+    };
     
     let output_path: String = Input::new()
-        .with_prompt("Enter output path")
-        .default("./downloaded".to_string())
+        .with_prompt("Enter output directory (ending in /) or file path")
+        .default("./downloaded/".to_string())
         .interact_text()?;
     
-    // Convert hash string to HashValue
-    let bytes = hex::decode(&selected.hash)?;
-    let hash = HashValue {
-        algo: HashAlgo::Sha256,
-        bytes,
+    let hash = selected.hash_value()?;
+    
+    let policy = match Select::new()
+        .with_prompt("If the file already exists")
+        .items(&["Rename (keep both)", "Overwrite", "Skip"])
+        .default(0)
+        .interact()?
+    {
+        1 => CollisionPolicy::Overwrite,
+        2 => CollisionPolicy::Skip,
+        _ => CollisionPolicy::Rename,
     };
     
-    let data = service.download_and_verify(&hash).await?;
-    let output_file = Path::new(&output_path).join(&selected.filename);
-    fs::write(&output_file, data)?;
+    match service.download_to_path(&hash, Path::new(&output_path), &selected.filename, policy).await? {
+        Some(output_file) => {
+            println!("{} File downloaded to: {}", "✅".bright_green(), output_file.display().to_string().bright_cyan());
+        }
+        None => {
+            println!("{} File already exists, skipped.", "⏭️".bright_yellow());
+        }
+    }
+    
+    Ok(())
+}
+
+async fn delete_file(service: &mut FileSharingService) -> Result<()> {
+    println!("\n{}", "🗑️  DELETE FILE".bright_magenta());
     
-    println!("{} File downloaded to: {}", "✅".bright_green(), output_file.display().to_string().bright_cyan());
+    if service.current_user.is_none() {
+        println!("{} Please login first!", "❌".bright_red());
+        return Ok(());
+    }
+    
+    let username = service.current_user.as_ref().unwrap().username.clone();
+    let files = service.get_user_files(&username).await?;
+    
+    if files.is_empty() {
+        println!("{} No files to delete.", "📭".bright_yellow());
+        return Ok(());
+    }
+    
+    let selected = match select_file(service, &files, "Select file to delete").await? {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+    
+    let confirm = Select::new()
+        .with_prompt(format!("Move {} to the recycle bin?", selected.filename))
+        .items(&["No", "Yes"])
+        .default(0)
+        .interact()?;
+    if confirm == 0 {
+        return Ok(());
+    }
+    
+    if let Err(e) = service.delete_file(&selected.hash_value()?, &username).await {
+        println!("{} {}", "❌".bright_red(), e);
+        return Ok(());
+    }
+    println!("{} Restorable for {} days from the recycle bin.", "✅".bright_green(), service.recycle_grace.num_days());
     
     Ok(())
 }
 
+async fn recycle_bin(service: &mut FileSharingService) -> Result<()> {
+    println!("\n{}", "♻️  RECYCLE BIN".bright_magenta());
+    
+    if service.current_user.is_none() {
+        println!("{} Please login first!", "❌".bright_red());
+        return Ok(());
+    }
+    
+    let username = service.current_user.as_ref().unwrap().username.clone();
+    let files = service.recycle_bin(&username).await?;
+    
+    if files.is_empty() {
+        println!("{} The recycle bin is empty.", "📭".bright_yellow());
+        return Ok(());
+    }
+    
+    let mut items = vec!["⬅️  Back".to_string()];
+    items.extend(files.iter().map(|f| {
+        let deleted = f.deleted_at.map(|d| d.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_default();
+        format!("{} ({} bytes, deleted {})", f.filename, f.size, deleted)
+    }));
+    
+    let selection = Select::new()
+        .with_prompt("Select file to restore")
+        .items(&items)
+        .default(0)
+        .interact()?;
+    if selection == 0 {
+        return Ok(());
+    }
+    
+    let file = &files[selection - 1];
+    match service.restore_file(&file.hash_value()?, &username).await {
+        Ok(restored) => println!("{} Restored {}", "✅".bright_green(), restored.filename.bright_cyan()),
+        Err(e) => println!("{} {}", "❌".bright_red(), e),
+    }
+    
+    Ok(())
+}
+
+// Pick one of the listed files, either from the menu or by (short) hash
+async fn select_file(service: &FileSharingService, files: &[FileRecord], prompt: &str) -> Result<Option<FileRecord>> {
+    let mut items = vec!["🔎 Enter hash or prefix...".to_string()];
+    items.extend(service.display_names(files).into_iter().zip(files)
+        .map(|(name, f)| format!("{} ({} bytes)", name, f.size)));
+    
+    let selection = Select::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .default(1)
+        .interact()?;
+    
+    if selection > 0 {
+        return Ok(Some(files[selection - 1].clone()));
+    }
+    
+    let username = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => return Ok(None),
+    };
+    let input: String = Input::new()
+        .with_prompt("Enter file hash (algo:hex or at least 8 hex chars)")
+        .interact_text()?;
+    
+    let resolved = match service.resolve_file(&username, &input).await {
+        Ok(file) => file,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(None);
+        }
+    };
+    
+    match files.iter().find(|f| f.hash == resolved.hash) {
+        Some(file) => Ok(Some(file.clone())),
+        None => {
+            println!("{} That file is not in your list.", "❌".bright_red());
+            Ok(None)
+        }
+    }
+}
+
 async fn share_file(service: &mut FileSharingService) -> Result<()> {
     println!("\n{}", "🔗 SHARE FILE".bright_magenta());
     
@@ -291,36 +517,192 @@ async fn share_file(service: &mut FileSharingService) -> Result<()> {
         return Ok(());
     }
     
-    let filenames: Vec<String> = files.iter()
-        .map(|f| f.filename.clone())
-        .collect();
-    
-    let selection = Select::new()
-        .with_prompt("Select file to share")
-        .items(&filenames)
-        .interact()?;
-    
-    let selected = &files[selection];
+    let selected = match select_file(service, &files, "Select file to share").await? {
+        Some(file) => file,
+        None => return Ok(()),
+    };
     
-    let target_username: String = Input::new()
-        .with_prompt("Enter username to share with")
+    let target_input: String = Input::new()
+        .with_prompt("Enter username(s) or @group(s) to share with (comma-separated)")
         .interact_text()?;
+    let (groups, targets): (Vec<&str>, Vec<&str>) = target_input.split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .partition(|t| t.starts_with('@'));
+    
+    let permission = match Select::new()
+        .with_prompt("Recipient permission")
+        .items(&["Read only", "Read and re-share"])
+        .default(0)
+        .interact()?
+    {
+        1 => SharePermission::Reshare,
+        _ => SharePermission::Read,
+    };
     
-    // Convert hash string to HashValue
-    let bytes = hex::decode(&selected.hash)?;
-    let hash = HashValue {
-        algo: HashAlgo::Sha256,
-        bytes,
+    let days: String = Input::new()
+        .with_prompt("Expire after N days (empty for the default)")
+        .allow_empty(true)
+        .interact_text()?;
+    let expires_at = match days.trim() {
+        "" => None,
+        n => match n.parse::<i64>() {
+            Ok(n) if n > 0 => Some(Utc::now() + chrono::Duration::days(n)),
+            _ => {
+                println!("{} Not a positive number: {}", "❌".bright_red(), n);
+                return Ok(());
+            }
+        },
     };
     
+    let hash = selected.hash_value()?;
+    
     // Use the cloned username here
-    service.share_file(
-        &hash, 
-        &current_username,
-        &target_username
-    ).await?;
+    let results = match service.share_file_batch(&hash, &current_username, &targets, permission, expires_at).await {
+        Ok(results) => results,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
+    };
+    
+    for (target, result) in results {
+        match result {
+            Ok(()) => println!("{} File shared with {} successfully!", "✅".bright_green(), target.bright_cyan()),
+            Err(e) => println!("{} {}: {}", "❌".bright_red(), target.bright_cyan(), e),
+        }
+    }
     
-    println!("{} File shared with {} successfully!", "✅".bright_green(), target_username.bright_cyan());
+    for group in groups {
+        match service.share_with_group(&hash, &current_username, &group[1..], permission, expires_at).await {
+            Ok(count) => println!("{} File shared with {} member(s) of {}", "✅".bright_green(), count, group.bright_cyan()),
+            Err(e) => println!("{} {}: {}", "❌".bright_red(), group.bright_cyan(), e),
+        }
+    }
+    
+    Ok(())
+}
+
+async fn file_attributes(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "🏷️  FILE ATTRIBUTES".bright_magenta());
+    
+    if service.current_user.is_none() {
+        println!("{} Please login first!", "❌".bright_red());
+        return Ok(());
+    }
+    
+    let username = service.current_user.as_ref().unwrap().username.clone();
+    let files = service.get_user_files(&username).await?;
+    
+    if files.is_empty() {
+        println!("{} No files.", "📭".bright_yellow());
+        return Ok(());
+    }
+    
+    let selected = match select_file(service, &files, "Select file").await? {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+    let hash = selected.hash_value()?;
+    
+    loop {
+        let attributes = service.get_file_attributes(&hash, &username).await?;
+        let mut keys: Vec<&String> = attributes.keys().collect();
+        keys.sort();
+        
+        println!("\n{} {}", "📄".bright_blue(), selected.filename.bright_white());
+        if keys.is_empty() {
+            println!("    {}", "(no attributes)".bright_black());
+        }
+        for key in &keys {
+            println!("    {:<20} {}", key.bright_cyan(), attributes[*key]);
+        }
+        
+        let action = Select::new()
+            .with_prompt("Attribute action")
+            .items(&["Back", "Set Attribute", "Remove Attribute"])
+            .default(0)
+            .interact()?;
+        
+        match action {
+            1 => {
+                let key: String = Input::new()
+                    .with_prompt("Key (e.g. project, classification, retention)")
+                    .interact_text()?;
+                let value: String = Input::new()
+                    .with_prompt("Value")
+                    .allow_empty(true)
+                    .interact_text()?;
+                if let Err(e) = service.set_file_attribute(&hash, &username, &key, &value).await {
+                    println!("{} {}", "❌".bright_red(), e);
+                }
+            }
+            2 if !keys.is_empty() => {
+                let idx = Select::new()
+                    .with_prompt("Attribute to remove")
+                    .items(&keys)
+                    .default(0)
+                    .interact()?;
+                service.remove_file_attribute(&hash, &username, keys[idx]).await?;
+            }
+            2 => println!("{} Nothing to remove.", "📭".bright_yellow()),
+            _ => return Ok(()),
+        }
+    }
+}
+
+async fn manage_groups(service: &mut FileSharingService) -> Result<()> {
+    println!("\n{}", "👥 GROUPS".bright_magenta());
+    
+    if service.current_user.is_none() {
+        println!("{} Please login first!", "❌".bright_red());
+        return Ok(());
+    }
+    
+    let username = service.current_user.as_ref().unwrap().username.clone();
+    
+    let selection = Select::new()
+        .with_prompt("Select a group action")
+        .items(&["List My Groups", "Create Group", "Add Member", "Back"])
+        .default(0)
+        .interact()?;
+    
+    match selection {
+        0 => {
+            let groups = service.get_user_groups(&username).await?;
+            if groups.is_empty() {
+                println!("{} You are not in any group.", "📭".bright_yellow());
+            }
+            for group in groups {
+                let members = service.get_group_members(&group.name).await?;
+                let names: Vec<&str> = members.iter().map(|m| m.username.as_str()).collect();
+                println!("  {} ({}): {}", group.name.bright_cyan(), members.len(), names.join(", "));
+            }
+        }
+        1 => {
+            let name: String = Input::new()
+                .with_prompt("Group name")
+                .interact_text()?;
+            match service.create_group(&username, name.trim()).await {
+                Ok(group) => println!("{} Group {} created. Share with it as @{}", "✅".bright_green(), group.name.bright_cyan(), group.name),
+                Err(e) => println!("{} {}", "❌".bright_red(), e),
+            }
+        }
+        2 => {
+            let group: String = Input::new()
+                .with_prompt("Group name")
+                .interact_text()?;
+            let member: String = Input::new()
+                .with_prompt("Username to add")
+                .interact_text()?;
+            match service.add_group_member(&username, group.trim(), member.trim()).await {
+                Ok(true) => println!("{} {} added to {}", "✅".bright_green(), member.bright_cyan(), group),
+                Ok(false) => println!("{} {} is already in {}", "ℹ️".bright_yellow(), member, group),
+                Err(e) => println!("{} {}", "❌".bright_red(), e),
+            }
+        }
+        _ => {}
+    }
     
     Ok(())
 }
@@ -342,43 +724,164 @@ async fn list_shared_files(service: &FileSharingService) -> Result<()> {
         return Ok(());
     }
     
-    println!("\n{:<5} {:<25} {:<15} {:<20}", 
+    println!("\n{:<5} {:<25} {:<15} {:<20} {:<10}", 
         "ID".bright_white(), 
         "Filename".bright_white(), 
         "Shared By".bright_white(), 
-        "Shared At".bright_white()
+        "Shared At".bright_white(),
+        "Access".bright_white()
     );
-    println!("{}", "─".repeat(70).bright_black());
+    println!("{}", "─".repeat(80).bright_black());
     
     for (i, share) in shares.iter().enumerate() {
-        println!("{:<5} {:<25} {:<15} {:<20}", 
+        let access = match share.permission {
+            SharePermission::Read => "read",
+            SharePermission::Reshare => "reshare",
+        };
+        println!("{:<5} {:<25} {:<15} {:<20} {:<10}", 
             (i+1).to_string().bright_blue(),
             share.filename.chars().take(23).collect::<String>(),
             share.shared_by.bright_green(),
-            share.shared_at.format("%Y-%m-%d %H:%M").to_string().bright_cyan()
+            share.shared_at.format("%Y-%m-%d %H:%M").to_string().bright_cyan(),
+            access.bright_magenta()
         );
     }
     
+    let labels: Vec<String> = shares.iter()
+        .map(|share| format!("{} (from {})", share.filename, share.shared_by))
+        .collect();
+    let selection = Select::new()
+        .with_prompt("Select a file to download (Esc to go back)")
+        .items(&labels)
+        .interact_opt()?;
+    
+    if let Some(idx) = selection {
+        download_shared_file(service, &shares[idx]).await?;
+    }
+    
     Ok(())
 }
 
-async fn verify_file(service: &FileSharingService) -> Result<()> {
-    println!("\n{}", "🔍 VERIFY FILE INTEGRITY".bright_magenta());
+async fn download_shared_file(service: &FileSharingService, share: &SharedFile) -> Result<()> {
+    let file = match service.database.get_file_by_id(share.file_id).await? {
+        Some(file) => file,
+        None => {
+            println!("{} Shared file no longer exists", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    let data = match service.download_and_verify(&file.hash_value()?).await {
+        Ok(data) => data,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
+    };
+    
+    // The owner's commitment is the recipient's proof this is the content that was shared;
+    // newer shares also carry the size and Merkle root to check against
+    let verified = match share.proof {
+        Some(_) => FileSharingService::verify_received(share, &data)?,
+        None => service.verify_share_commitment(share, &data).await?,
+    };
+    if verified {
+        println!("{} Share commitment verified", "✅".bright_green());
+    } else {
+        println!("{} Share commitment does NOT match this content", "⚠️".bright_yellow());
+        let keep = Select::new()
+            .with_prompt("Save it anyway?")
+            .items(&["No", "Yes"])
+            .default(0)
+            .interact()?;
+        if keep == 0 {
+            return Ok(());
+        }
+    }
+    
+    let output_path: String = Input::new()
+        .with_prompt("Enter output directory (ending in /) or file path")
+        .default("./downloaded/".to_string())
+        .interact_text()?;
+    
+    match service.save_download(&data, Path::new(&output_path), &share.filename, CollisionPolicy::Rename)? {
+        Some(output_file) => {
+            println!("{} File downloaded to: {}", "✅".bright_green(), output_file.display().to_string().bright_cyan());
+        }
+        None => {
+            println!("{} File already exists, skipped.", "⏭️".bright_yellow());
+        }
+    }
+    
+    Ok(())
+}
+
+async fn list_my_shares(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "📤 MY SHARES".bright_magenta());
     
     if service.current_user.is_none() {
         println!("{} Please login first!", "❌".bright_red());
         return Ok(());
     }
     
+    let shares = service.get_my_shares(
+        service.current_user.as_ref().unwrap().username.as_str()
+    ).await?;
+    
+    if shares.is_empty() {
+        println!("{} You haven't shared any files.", "📭".bright_yellow());
+        return Ok(());
+    }
+    
+    // Rows arrive ordered by file, so a new file starts a new group
+    let mut current_file = None;
+    for share in &shares {
+        if current_file != Some(share.file_id) {
+            current_file = Some(share.file_id);
+            let count = shares.iter().filter(|s| s.file_id == share.file_id).count();
+            println!("\n{} {} ({} recipient{})", "📄".bright_blue(), share.filename.bright_white(),
+                count, if count == 1 { "" } else { "s" });
+        }
+        let access = match share.permission {
+            SharePermission::Read => "read",
+            SharePermission::Reshare => "reshare",
+        };
+        let status = match share.status {
+            ShareStatus::Active => "active".bright_green(),
+            ShareStatus::Expired => "expired".bright_red(),
+        };
+        println!("    {:<15} {:<20} {:<10} {}",
+            share.shared_with_username.bright_cyan(),
+            share.shared_at.format("%Y-%m-%d %H:%M").to_string(),
+            access.bright_magenta(),
+            status
+        );
+    }
+    
+    Ok(())
+}
+
+async fn verify_file(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "🔍 VERIFY FILE INTEGRITY".bright_magenta());
+    
+    let username = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
     let file_hash: String = Input::new()
-        .with_prompt("Enter file hash to verify")
+        .with_prompt("Enter file hash to verify (algo:hex or short prefix)")
         .interact_text()?;
     
-    // Convert hex string to HashValue
-    let bytes = hex::decode(&file_hash)?;
-    let hash = HashValue {
-        algo: HashAlgo::Sha256,
-        bytes,
+    let hash = match service.resolve_file(&username, &file_hash).await.and_then(|f| f.hash_value()) {
+        Ok(hash) => hash,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
     };
     
     match service.verify_file_integrity(&hash).await? {
@@ -389,6 +892,589 @@ async fn verify_file(service: &FileSharingService) -> Result<()> {
     Ok(())
 }
 
+async fn verify_my_files(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "🔍 VERIFY MY FILES".bright_magenta());
+    
+    let username = match &service.current_user {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    let report = service.verify_user_files(&username).await?;
+    println!("{} {}/{} file(s) verified OK", "📊".bright_blue(), report.ok, report.total);
+    for (hash, reason) in &report.corrupt {
+        println!("{} {}: {}", "❌".bright_red(), hash.prefix(16).bright_yellow(), reason);
+    }
+    if report.corrupt.is_empty() && report.total > 0 {
+        println!("{} All your files passed the integrity check", "✅".bright_green());
+    }
+    
+    Ok(())
+}
+
+async fn export_listing_csv(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "📄 EXPORT CSV".bright_magenta());
+    
+    let username = match &service.current_user {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    let output_path: String = Input::new()
+        .with_prompt("Enter output file path")
+        .default("./listing.csv".to_string())
+        .interact_text()?;
+    let include_shares = Select::new()
+        .with_prompt("Include your shares?")
+        .items(&["Yes", "No"])
+        .default(0)
+        .interact()? == 0;
+    
+    let file = fs::File::create(&output_path)?;
+    let mut writer = std::io::BufWriter::new(file);
+    let rows = service.export_listing_csv(&username, include_shares, &mut writer).await?;
+    println!("{} Wrote {} row(s) to {}", "✅".bright_green(), rows, output_path.bright_cyan());
+    
+    Ok(())
+}
+
+async fn compute_all_hashes(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "#️⃣  COMPUTE ALL HASHES".bright_magenta());
+    
+    let username = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    let file_hash: String = Input::new()
+        .with_prompt("Enter file hash (algo:hex or short prefix)")
+        .interact_text()?;
+    
+    let hash = match service.resolve_file(&username, &file_hash).await.and_then(|f| f.hash_value()) {
+        Ok(hash) => hash,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
+    };
+    
+    let hashes = match service.multi_hash(&hash).await {
+        Ok(hashes) => hashes,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
+    };
+    
+    for hash in [&hashes.sha256, &hashes.sha512, &hashes.sha3_256, &hashes.sha3_512, &hashes.blake3] {
+        println!("{:<10}: {}", hash.algo.as_str().bright_white(), hash.to_hex().bright_cyan());
+    }
+    
+    Ok(())
+}
+
+async fn my_activity(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "📈 MY ACTIVITY".bright_magenta());
+    
+    let username = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    let activity = service.user_activity(&username).await?;
+    let when = |t: Option<DateTime<Utc>>| t
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "never".to_string());
+    
+    println!("\n{}", "═══════════════════════════════════════".bright_blue());
+    println!("{:<20}: {}", "Last Login".bright_white(), when(activity.last_login).bright_green());
+    println!("{:<20}: {}", "Files".bright_white(), activity.file_count.to_string().bright_yellow());
+    println!("{:<20}: {} bytes", "Total Size".bright_white(), activity.total_bytes.to_string().bright_yellow());
+    println!("{:<20}: {}", "Shares Made".bright_white(), activity.share_count.to_string().bright_yellow());
+    println!("{:<20}: {}", "Last Upload".bright_white(), when(activity.last_upload).bright_green());
+    println!("{}", "═══════════════════════════════════════".bright_blue());
+    
+    Ok(())
+}
+
+async fn admin_menu(service: &mut FileSharingService) -> Result<()> {
+    println!("\n{}", "🛠️  ADMIN TOOLS".bright_magenta());
+    
+    match service.current_user.as_ref() {
+        Some(user) if user.is_admin => {}
+        Some(_) => {
+            println!("{} Admin tools require an administrator account!", "❌".bright_red());
+            return Ok(());
+        }
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    }
+    
+    let options = vec![
+        "1. Rebuild Filter",
+        "2. List Users",
+        "3. Scan Storage Integrity",
+        "4. Quarantined Files",
+        "5. Reconcile Database and Storage",
+        "6. Health Check",
+        "7. Inspect Chunks",
+        "8. Hash Algorithm Report",
+        "9. Audit Log",
+        "10. Compact Storage",
+        "11. Run Self-Test",
+        "12. Back",
+    ];
+    
+    let selection = Select::new()
+        .with_prompt("Select an admin action")
+        .items(&options)
+        .default(0)
+        .interact()?;
+    
+    match selection {
+        0 => rebuild_filter(service).await?,
+        1 => list_users(service).await?,
+        2 => scan_integrity(service).await?,
+        3 => quarantined_files(service).await?,
+        4 => reconcile(service).await?,
+        5 => health_check(service).await?,
+        6 => inspect_chunks(service).await?,
+        7 => hash_algo_report(service).await?,
+        8 => audit_log(service).await?,
+        9 => compact_storage(service).await?,
+        10 => self_test(service).await?,
+        _ => {}
+    }
+    
+    Ok(())
+}
+
+async fn rebuild_filter(service: &mut FileSharingService) -> Result<()> {
+    let before = service.authenticator.bloom.false_positive_rate();
+    service.authenticator.rebuild()?;
+    let after = service.authenticator.bloom.false_positive_rate();
+    
+    println!("{} Filter rebuilt: FP rate {} -> {}", "✅".bright_green(),
+        format!("{:.4}", before).bright_yellow(),
+        format!("{:.4}", after).bright_green());
+    Ok(())
+}
+
+async fn verify_local_file(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "🔍 VERIFY LOCAL FILE".bright_magenta());
+    
+    let file_path: String = Input::new()
+        .with_prompt("Enter local file path")
+        .interact_text()?;
+    
+    let path = Path::new(&file_path);
+    if !path.exists() {
+        println!("{} File not found!", "❌".bright_red());
+        return Ok(());
+    }
+    
+    let expected: String = Input::new()
+        .with_prompt("Enter expected hash (algo:hex)")
+        .interact_text()?;
+    
+    let hash: HashValue = match expected.parse() {
+        Ok(hash) => hash,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
+    };
+    
+    match service.verify_local_file(path, &hash).await? {
+        true => println!("{} Local file matches: OK", "✅".bright_green()),
+        false => println!("{} Local file does NOT match the expected hash!", "❌".bright_red()),
+    }
+    
+    Ok(())
+}
+
+async fn list_users(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "👥 USERS".bright_magenta());
+    
+    let requester = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    let users = match service.list_users(&requester, 100, 0).await {
+        Ok(users) => users,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
+    };
+    
+    println!("\n{:<5} {:<20} {:<8} {:<8} {:<12} {:<20}", 
+        "ID".bright_white(), 
+        "Username".bright_white(), 
+        "Admin".bright_white(), 
+        "Files".bright_white(),
+        "Size".bright_white(),
+        "Last Login".bright_white()
+    );
+    println!("{}", "─".repeat(75).bright_black());
+    
+    for user in &users {
+        println!("{:<5} {:<20} {:<8} {:<8} {:<12} {:<20}", 
+            user.id.to_string().bright_blue(),
+            user.username.chars().take(18).collect::<String>().bright_cyan(),
+            if user.is_admin { "yes" } else { "no" },
+            user.file_count.to_string().bright_yellow(),
+            format!("{}B", user.total_bytes).bright_yellow(),
+            user.last_login
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "never".to_string())
+                .bright_green()
+        );
+    }
+    
+    Ok(())
+}
+
+async fn scan_integrity(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "🔍 STORAGE INTEGRITY SCAN".bright_magenta());
+    
+    let failures = service.verify_all_with_progress(&CancellationToken::new(), |checked, total| {
+        print!("\r   Checked {}/{} files", checked, total);
+        let _ = std::io::stdout().flush();
+        if checked == total {
+            println!();
+        }
+    }).await?.into_inner();
+    if failures.is_empty() {
+        println!("{} All stored files verified: OK", "✅".bright_green());
+    } else {
+        for (hash, reason) in &failures {
+            println!("{} {} - {}", "❌".bright_red(), hash.prefix(8).bright_cyan(), reason);
+        }
+        println!("{} {} file(s) quarantined", "☣️".bright_yellow(), failures.len());
+    }
+    
+    Ok(())
+}
+
+async fn health_check(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "🩺 HEALTH CHECK".bright_magenta());
+    
+    let health = service.health_check().await;
+    let flag = |ok: bool| if ok { "OK".bright_green() } else { "FAIL".bright_red() };
+    
+    println!("{:<20}: {}", "Database".bright_white(), flag(health.db_ok));
+    println!("{:<20}: {}", "Storage Writable".bright_white(), flag(health.storage_writable));
+    println!("{:<20}: {}", "Free Space".bright_white(), health.storage_dir_free_bytes
+        .map(|b| format!("{:.2} GB", b as f64 / 1_000_000_000.0))
+        .unwrap_or_else(|| "unknown".to_string())
+        .bright_yellow());
+    println!("{:<20}: {}", "Persistent DB".bright_white(), flag(!health.in_memory_fallback));
+    
+    Ok(())
+}
+
+async fn audit_log(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "📜 AUDIT LOG".bright_magenta());
+    
+    let requester = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    let user: String = Input::new()
+        .with_prompt("Filter by username (empty for all)")
+        .allow_empty(true)
+        .interact_text()?;
+    let action: String = Input::new()
+        .with_prompt("Filter by action, e.g. login, upload, share (empty for all)")
+        .allow_empty(true)
+        .interact_text()?;
+    let days: String = Input::new()
+        .with_prompt("Only the last N days (empty for all)")
+        .allow_empty(true)
+        .interact_text()?;
+    
+    let since = match days.trim() {
+        "" => None,
+        n => match n.parse::<i64>() {
+            Ok(n) => Some(Utc::now() - chrono::Duration::days(n)),
+            Err(_) => {
+                println!("{} Not a number: {}", "❌".bright_red(), n);
+                return Ok(());
+            }
+        },
+    };
+    let mut filter = AuditFilter {
+        user: Some(user.trim().to_string()).filter(|u| !u.is_empty()),
+        action: Some(action.trim().to_string()).filter(|a| !a.is_empty()),
+        since,
+        until: None,
+        limit: 20,
+        offset: 0,
+    };
+    
+    loop {
+        let entries = match service.query_audit(&requester, &filter).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("{} {}", "❌".bright_red(), e);
+                return Ok(());
+            }
+        };
+        if entries.is_empty() {
+            println!("{} No more entries.", "📭".bright_yellow());
+            return Ok(());
+        }
+        
+        println!("\n{:<20} {:<15} {:<16} {}", "Time".bright_white(), "User".bright_white(),
+            "Action".bright_white(), "Target".bright_white());
+        println!("{}", "─".repeat(80).bright_black());
+        for entry in &entries {
+            println!("{:<20} {:<15} {:<16} {}",
+                entry.timestamp.format("%Y-%m-%d %H:%M:%S").to_string().bright_cyan(),
+                entry.username.as_deref().unwrap_or("-"),
+                entry.action.bright_yellow(),
+                entry.target.as_deref().unwrap_or(""));
+        }
+        
+        if (entries.len() as i64) < filter.limit {
+            return Ok(());
+        }
+        let next = Select::new()
+            .with_prompt("More entries?")
+            .items(&["Back", "Next page"])
+            .default(1)
+            .interact()?;
+        if next == 0 {
+            return Ok(());
+        }
+        filter.offset += filter.limit;
+    }
+}
+
+async fn hash_algo_report(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "#️⃣  HASH ALGORITHM REPORT".bright_magenta());
+    
+    let report = service.hash_algo_report().await?;
+    if report.breakdown.is_empty() {
+        println!("{} No files stored.", "📭".bright_yellow());
+        return Ok(());
+    }
+    
+    for (algo, count) in &report.breakdown {
+        let strength = format!("{} bits", algo.strength_bits());
+        let strength = if algo.strength_bits() < service.min_hash_strength {
+            strength.bright_red()
+        } else {
+            strength.bright_green()
+        };
+        println!("{:<20}: {} files ({})", algo.as_str().bright_white(), count.to_string().bright_yellow(), strength);
+    }
+    
+    if report.below_minimum.is_empty() {
+        println!("\n{} All files meet the minimum of {} bits", "✅".bright_green(), service.min_hash_strength);
+        return Ok(());
+    }
+    
+    println!("\n{} {} file(s) below the minimum of {} bits:", "⚠️".bright_yellow(),
+        report.below_minimum.len(), service.min_hash_strength);
+    for file in &report.below_minimum {
+        println!("  {} ({}:{})", file.filename.bright_cyan(), file.hash_algo, &file.hash[..16.min(file.hash.len())]);
+    }
+    println!("{} Re-upload them with {} set to a stronger algorithm", "💡".bright_yellow(), DEFAULT_HASH_ENV);
+    
+    Ok(())
+}
+
+async fn reconcile(service: &mut FileSharingService) -> Result<()> {
+    println!("\n{}", "🧮 RECONCILE DATABASE AND STORAGE".bright_magenta());
+    
+    let report = service.reconcile(&CancellationToken::new()).await?.into_inner();
+    if report.is_clean() {
+        println!("{} Database and storage agree: OK", "✅".bright_green());
+        return Ok(());
+    }
+    
+    for hash in &report.db_without_storage {
+        println!("{} {} - row without stored content", "❌".bright_red(), hash.prefix(8).bright_cyan());
+    }
+    for hash in &report.storage_without_db {
+        println!("{} {} - stored content without a row", "⚠️".bright_yellow(), hash.prefix(8).bright_cyan());
+    }
+    
+    let clean = Select::new()
+        .with_prompt("Remove these orphans?")
+        .items(&["No", "Yes"])
+        .default(0)
+        .interact()?;
+    
+    if clean == 1 {
+        let requester = match service.current_user.as_ref() {
+            Some(user) => user.username.clone(),
+            None => {
+                println!("{} Please login first!", "❌".bright_red());
+                return Ok(());
+            }
+        };
+        service.clean_orphans(&requester, &report).await?;
+        println!("{} Orphans removed", "✅".bright_green());
+    }
+    
+    Ok(())
+}
+
+async fn self_test(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "🧪 SELF-TEST".bright_magenta());
+    
+    let report = service.self_test().await;
+    println!();
+    for step in &report.steps {
+        if step.passed {
+            println!("{} {}", "✅".bright_green(), step.name);
+        } else {
+            println!("{} {}: {}", "❌".bright_red(), step.name,
+                step.detail.as_deref().unwrap_or("failed").bright_red());
+        }
+    }
+    
+    if report.all_passed() {
+        println!("{} All self-test steps passed", "✅".bright_green());
+    } else {
+        println!("{} Self-test failed", "❌".bright_red());
+    }
+    Ok(())
+}
+
+async fn compact_storage(service: &mut FileSharingService) -> Result<()> {
+    println!("\n{}", "🧹 COMPACT STORAGE".bright_magenta());
+    
+    let requester = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    match service.compact_storage(&requester).await {
+        Ok(report) if report.removed_files == 0 => {
+            println!("{} No orphaned chunks found", "✅".bright_green());
+        }
+        Ok(report) => {
+            println!("{} Removed {} orphaned chunk file(s), reclaimed {} bytes", "✅".bright_green(),
+                report.removed_files.to_string().bright_cyan(),
+                report.reclaimed_bytes.to_string().bright_yellow());
+        }
+        Err(e) => println!("{} {}", "❌".bright_red(), e),
+    }
+    
+    Ok(())
+}
+
+async fn quarantined_files(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "☣️  QUARANTINED FILES".bright_magenta());
+    
+    let requester = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => {
+            println!("{} Please login first!", "❌".bright_red());
+            return Ok(());
+        }
+    };
+    
+    let entries = service.list_quarantined().await?;
+    if entries.is_empty() {
+        println!("{} No quarantined files.", "📭".bright_yellow());
+        return Ok(());
+    }
+    
+    let labels: Vec<String> = entries.iter()
+        .map(|q| format!("{}  {}  {}", 
+            &q.hash[..16.min(q.hash.len())], 
+            q.quarantined_at.format("%Y-%m-%d %H:%M"), 
+            q.reason))
+        .collect();
+    
+    let selection = Select::new()
+        .with_prompt("Select a file to clear after repair (Esc to go back)")
+        .items(&labels)
+        .interact_opt()?;
+    
+    if let Some(idx) = selection {
+        let hash = entries[idx].hash_value()?;
+        match service.clear_quarantine(&requester, &hash).await {
+            Ok(_) => println!("{} Quarantine cleared", "✅".bright_green()),
+            Err(e) => println!("{} {}", "❌".bright_red(), e),
+        }
+    }
+    
+    Ok(())
+}
+
+async fn inspect_chunks(service: &FileSharingService) -> Result<()> {
+    println!("\n{}", "🧩 INSPECT CHUNKS".bright_magenta());
+    
+    let username = match service.current_user.as_ref() {
+        Some(user) => user.username.clone(),
+        None => return Ok(()),
+    };
+    let file_hash: String = Input::new()
+        .with_prompt("Enter file hash (algo:hex or short prefix)")
+        .interact_text()?;
+    
+    let statuses = match service.resolve_file(&username, &file_hash).await
+        .and_then(|f| f.hash_value())
+        .and_then(|hash| service.chunk_status(&hash))
+    {
+        Ok(statuses) => statuses,
+        Err(e) => {
+            println!("{} {}", "❌".bright_red(), e);
+            return Ok(());
+        }
+    };
+    
+    println!("\n{:<6} {:<18} {:<10} Size (disk / expected)", "Index", "Chunk Hash", "Status");
+    for status in &statuses {
+        let state = if !status.present {
+            "MISSING".bright_red()
+        } else if status.expected_size.is_some_and(|e| Some(e) != status.size_on_disk) {
+            "SIZE".bright_yellow()
+        } else {
+            "ok".bright_green()
+        };
+        let size = |s: Option<u64>| s.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string());
+        println!("{:<6} {:<18} {:<10} {} / {}", status.index, status.hash_prefix, state,
+            size(status.size_on_disk), size(status.expected_size));
+    }
+    
+    let missing = statuses.iter().filter(|s| !s.present).count();
+    println!("\n{} {} chunks, {} missing", "🧩".bright_cyan(), statuses.len(), missing);
+    
+    Ok(())
+}
+
 async fn print_stats(service: &FileSharingService) -> Result<()> {
     println!("\n{}", "📊 SYSTEM STATISTICS".bright_magenta());
     
@@ -403,7 +1489,24 @@ async fn print_stats(service: &FileSharingService) -> Result<()> {
     println!("{:<20}: {} MB", "Saved Space".bright_white(), format!("{:.2}", stats.saved_bytes as f64 / 1_000_000.0).bright_green());
     println!("{:<20}: {:.1}%", "Deduplication Rate".bright_white(), format!("{:.1}", stats.dedup_rate).bright_blue());
     println!("{:<20}: {:.4}", "Bloom FP Rate".bright_white(), format!("{:.4}", stats.bloom_fp_rate).bright_magenta());
+    let store_root = service.storage.store_merkle_root();
+    println!("{:<20}: {}", "Store Root".bright_white(), store_root.to_string().bright_cyan());
     println!("{}", "═══════════════════════════════════════".bright_blue());
     
+    let recorded: String = Input::new()
+        .with_prompt("Compare with a recorded store root (leave empty to skip)")
+        .allow_empty(true)
+        .interact_text()?;
+    
+    if !recorded.trim().is_empty() {
+        match recorded.parse::<HashValue>() {
+            Ok(previous) if previous == store_root => {
+                println!("{} Store root unchanged", "✅".bright_green());
+            }
+            Ok(_) => println!("{} Store root has DRIFTED since it was recorded!", "⚠️".bright_yellow()),
+            Err(e) => println!("{} {}", "❌".bright_red(), e),
+        }
+    }
+    
     Ok(())
 }
\ No newline at end of file
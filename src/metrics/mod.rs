@@ -0,0 +1,5 @@
+// ============================================================================
+// Metrics Module - Prometheus Counters and Gauges
+// ============================================================================
+
+pub mod prometheus;
@@ -0,0 +1,53 @@
+// ============================================================================
+// Prometheus Text-Format Metrics
+// ============================================================================
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub uploads: AtomicU64,
+    pub downloads: AtomicU64,
+    pub shares: AtomicU64,
+    pub integrity_failures: AtomicU64,
+    pub dedup_hits: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inc(counter: &AtomicU64) {
+        Self::add(counter, 1);
+    }
+
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    // Render counters plus the supplied gauges (name, help, value)
+    pub fn render(&self, gauges: &[(&str, &str, u64)]) -> String {
+        let counters = [
+            ("sfs_uploads_total", "Files uploaded", &self.uploads),
+            ("sfs_downloads_total", "Files downloaded and verified", &self.downloads),
+            ("sfs_shares_total", "Shares created", &self.shares),
+            ("sfs_integrity_failures_total", "Integrity checks that failed", &self.integrity_failures),
+            ("sfs_dedup_hits_total", "Uploads served by deduplication", &self.dedup_hits),
+        ];
+
+        let mut out = String::new();
+        for (name, help, counter) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
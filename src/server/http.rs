@@ -0,0 +1,534 @@
+// ============================================================================
+// Minimal HTTP/1.1 Server (one request per connection)
+// ============================================================================
+
+use crate::crypto::hash::HashValue;
+use crate::db::User;
+use crate::error::FileSharingError;
+use crate::service::file_sharing::{FileHead, FileSharingService};
+use crate::storage::range::RangeRequest;
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+
+// Request line plus headers; anything longer is refused
+const MAX_HEAD_BYTES: usize = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String, // without the query string
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn new(method: &str, path: &str) -> Self {
+        Self { method: method.to_string(), path: path.to_string(), headers: Vec::new() }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // Header names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // The request line and headers, up to the blank line. None if malformed.
+    pub fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut parts = lines.next()?.split(' ');
+        let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() || !version.starts_with("HTTP/1.") || !target.starts_with('/') {
+            return None;
+        }
+
+        let mut request = Request::new(method, target.split('?').next().unwrap_or(target));
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            request.headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        Some(request)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(self, content_type: &str, body: Vec<u8>) -> Self {
+        let mut response = self
+            .with_header("Content-Type", content_type)
+            .with_header("Content-Length", &body.len().to_string());
+        response.body = body;
+        response
+    }
+
+    fn text(status: u16, message: &str) -> Self {
+        Self::new(status).with_body("text/plain; charset=utf-8", format!("{}\n", message).into_bytes())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            206 => "Partial Content",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            416 => "Range Not Satisfiable",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason());
+        for (name, value) in &self.headers {
+            out.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        out.push_str("Connection: close\r\n\r\n");
+        let mut bytes = out.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+// Route one request. Kept apart from the socket handling so it can be driven directly.
+pub async fn handle(service: &FileSharingService, request: &Request) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => Response::new(200)
+            .with_body("text/plain; version=0.0.4", service.metrics_text().into_bytes()),
+        (_, "/metrics") => Response::text(405, "method not allowed").with_header("Allow", "GET"),
+        ("GET", "/healthz") => healthz(service).await,
+        (_, "/healthz") => Response::text(405, "method not allowed").with_header("Allow", "GET"),
+        (_, path) if path.starts_with("/files/") => file(service, request, &path["/files/".len()..]).await,
+        _ => Response::text(404, "not found"),
+    }
+}
+
+// /files/<algo:hex>. Callers authenticate with HTTP Basic; a file they can't
+// read gets the same 404 as an unknown one, so hashes don't leak.
+async fn file(service: &FileSharingService, request: &Request, hash: &str) -> Response {
+    if request.method != "GET" && request.method != "HEAD" {
+        return Response::text(405, "method not allowed").with_header("Allow", "GET, HEAD");
+    }
+    let hash: HashValue = match hash.replace("%3A", ":").replace("%3a", ":").parse() {
+        Ok(hash) => hash,
+        Err(e) => return Response::text(400, &e.to_string()),
+    };
+    let user = match authenticate(service, request).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Response::text(401, "unauthorized")
+            .with_header("WWW-Authenticate", "Basic realm=\"secure-file-sharing\""),
+        Err(e) => return Response::text(500, &e.to_string()),
+    };
+    let head = match service.can_read(&user.username, &hash).await {
+        Ok(true) => service.head(&hash).await,
+        Ok(false) => Ok(None),
+        Err(e) => Err(e),
+    };
+    match head {
+        Ok(Some(head)) if request.method == "HEAD" => head_response(&head),
+        Ok(Some(head)) => get_file(service, request, &hash, &head, &user.username).await,
+        Ok(None) => Response::text(404, "not found"),
+        Err(e) => Response::text(500, &e.to_string()),
+    }
+}
+
+// The headers a GET of the whole file would carry, with no body
+fn head_response(head: &FileHead) -> Response {
+    Response::new(200)
+        .with_header("Content-Type", &head.mime_type)
+        .with_header("Content-Length", &head.size.to_string())
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("X-Chunk-Count", &head.chunks.to_string())
+        .with_header("X-Merkle-Root", &head.merkle_root)
+}
+
+// The whole file (200), or the single range in a Range header (206, or 416 when
+// it starts past the end), from the copy `reader` is entitled to. Either way
+// only verified bytes are sent.
+async fn get_file(service: &FileSharingService, request: &Request, hash: &HashValue, head: &FileHead, reader: &str) -> Response {
+    let range = RangeRequest::parse(request.header("Range"), head.size);
+    let (status, body) = match range {
+        RangeRequest::Full => (200, service.download_as(hash, Some(reader)).await),
+        RangeRequest::Partial { start, end } => (206, service.download_range(hash, Some(reader), start, end + 1).await),
+        RangeRequest::Unsatisfiable => {
+            return Response::text(416, "range not satisfiable")
+                .with_header("Content-Range", &format!("bytes */{}", head.size));
+        }
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => return Response::text(500, &e.to_string()),
+    };
+
+    let mut response = Response::new(status).with_header("Accept-Ranges", "bytes");
+    if let Some(content_range) = range.content_range(head.size) {
+        response = response.with_header("Content-Range", &content_range);
+    }
+    response
+        .with_header("X-Merkle-Root", &head.merkle_root)
+        .with_body(&head.mime_type, body)
+}
+
+// None for missing or wrong credentials, or a locked account
+async fn authenticate(service: &FileSharingService, request: &Request) -> Result<Option<User>> {
+    let credentials = request.header("Authorization")
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    let (username, password) = match credentials.as_deref().and_then(|c| c.split_once(':')) {
+        Some(pair) => pair,
+        None => return Ok(None),
+    };
+    match service.verify_credentials(username, password).await {
+        Err(e) if matches!(e.downcast_ref(), Some(FileSharingError::AccountLocked { .. })) => Ok(None),
+        result => result,
+    }
+}
+
+// Readiness probe: 200 when healthy, 503 otherwise, with the report as JSON either way
+async fn healthz(service: &FileSharingService) -> Response {
+    let report = service.health_check().await;
+    let status = if report.is_healthy() { 200 } else { 503 };
+    match serde_json::to_vec(&report) {
+        Ok(body) => Response::new(status).with_body("application/json", body),
+        Err(e) => Response::text(500, &e.to_string()),
+    }
+}
+
+// Accept connections until the listener fails. The lock is only read, so an
+// embedding application can keep using the service between requests.
+pub async fn serve(listener: TcpListener, service: Arc<RwLock<FileSharingService>>) -> Result<()> {
+    println!("🌐 HTTP server listening on {}", listener.local_addr()?);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &service).await {
+                println!("⚠️  HTTP connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, service: &RwLock<FileSharingService>) -> Result<()> {
+    let response = match read_head(&mut stream).await?.as_deref().and_then(Request::parse) {
+        Some(request) => handle(&*service.read().await, &request).await,
+        None => Response::text(400, "bad request"),
+    };
+    stream.write_all(&response.to_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// Bytes up to and including the blank line after the headers; None when the
+// client sends too much or something other than UTF-8
+async fn read_head(stream: &mut TcpStream) -> Result<Option<String>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD_BYTES {
+            return Ok(None);
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed before the request was complete");
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8(head).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::HashAlgo;
+    use crate::db::SharePermission;
+    use crate::service::file_sharing::ServiceConfig;
+    use tempfile::TempDir;
+
+    async fn open_service() -> (TempDir, FileSharingService) {
+        let dir = TempDir::new().unwrap();
+        let mut service = FileSharingService::open(ServiceConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..ServiceConfig::default()
+        }).await.unwrap();
+        service.storage.min_free_space = 0;
+        (dir, service)
+    }
+
+    fn basic(username: &str, password: &str) -> String {
+        format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password)))
+    }
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let request = Request::parse("GET /files/x?download=1 HTTP/1.1\r\nHost: a\r\nrange: bytes=0-1\r\n\r\n").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/files/x");
+        assert_eq!(request.header("Range"), Some("bytes=0-1"));
+        assert!(Request::parse("GET files HTTP/1.1\r\n\r\n").is_none());
+        assert!(Request::parse("nonsense\r\n\r\n").is_none());
+    }
+
+    #[tokio::test]
+    async fn metrics_path_serves_prometheus_text() {
+        let (_dir, mut service) = open_service().await;
+        service.database.create_user("alice", "unused", None).await.unwrap();
+        service.upload_file(b"counted", "c.txt", "alice", None, None).await.unwrap();
+
+        let response = handle(&service, &Request::new("GET", "/metrics")).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Type"), Some("text/plain; version=0.0.4"));
+        let body = String::from_utf8(response.body).unwrap();
+        assert!(body.contains("sfs_uploads_total 1\n"));
+
+        let response = handle(&service, &Request::new("POST", "/metrics")).await;
+        assert_eq!(response.status, 405);
+        assert_eq!(handle(&service, &Request::new("GET", "/nope")).await.status, 404);
+    }
+
+    #[tokio::test]
+    async fn serves_over_a_socket() {
+        let (_dir, service) = open_service().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::new(RwLock::new(service))));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n").await.unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await.unwrap();
+        assert!(reply.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(reply.contains("sfs_uploads_total 0\n"));
+    }
+
+    #[tokio::test]
+    async fn healthz_reports_readiness_as_json() {
+        let (dir, service) = open_service().await;
+        let response = handle(&service, &Request::new("GET", "/healthz")).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        let report: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(report["db_ok"], true);
+        assert_eq!(report["storage_writable"], true);
+
+        // A file where the storage directory should be defeats the write probe, even as root
+        let storage = dir.path().join("storage");
+        std::fs::remove_dir_all(&storage).unwrap();
+        std::fs::write(&storage, b"not a directory").unwrap();
+        let response = handle(&service, &Request::new("GET", "/healthz")).await;
+        assert_eq!(response.status, 503);
+        let report: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(report["storage_writable"], false);
+
+        assert_eq!(handle(&service, &Request::new("POST", "/healthz")).await.status, 405);
+    }
+
+    #[tokio::test]
+    async fn head_of_a_file_needs_credentials_and_access() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("alice", "alice password", None).await.unwrap();
+        service.register_user("bob", "bob password", None).await.unwrap();
+        let metadata = service.upload_file(b"<p>hello</p>", "page.html", "alice", None, None).await.unwrap();
+        let path = format!("/files/{}", metadata.hash);
+        let head = |user: &str, password: &str| Request::new("HEAD", &path).with_header("Authorization", &basic(user, password));
+
+        let response = handle(&service, &head("alice", "alice password")).await;
+        assert_eq!(response.status, 200);
+        assert!(response.body.is_empty());
+        assert_eq!(response.header("Content-Length"), Some("12"));
+        assert_eq!(response.header("Content-Type"), Some("text/html"));
+        assert_eq!(response.header("X-Chunk-Count"), Some("1"));
+        assert_eq!(response.header("X-Merkle-Root"), Some(metadata.merkle_root.to_hex().as_str()));
+
+        let response = handle(&service, &Request::new("HEAD", &path)).await;
+        assert_eq!(response.status, 401);
+        assert!(response.header("WWW-Authenticate").is_some());
+        assert_eq!(handle(&service, &head("alice", "wrong")).await.status, 401);
+        assert_eq!(handle(&service, &head("bob", "bob password")).await.status, 404);
+        service.share_file(&metadata.hash, "alice", "bob", SharePermission::Read).await.unwrap();
+        assert_eq!(handle(&service, &head("bob", "bob password")).await.status, 200);
+
+        let unknown = HashValue::compute(b"never stored", HashAlgo::Sha256);
+        let request = Request::new("HEAD", &format!("/files/{}", unknown)).with_header("Authorization", &basic("alice", "alice password"));
+        assert_eq!(handle(&service, &request).await.status, 404);
+        let request = Request::new("HEAD", "/files/not-a-hash").with_header("Authorization", &basic("alice", "alice password"));
+        assert_eq!(handle(&service, &request).await.status, 400);
+        assert_eq!(handle(&service, &Request::new("DELETE", &path)).await.status, 405);
+    }
+
+    #[tokio::test]
+    async fn get_of_a_file_honours_range_requests() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("alice", "alice password", None).await.unwrap();
+        service.storage.chunk_size = 4;
+        let data = b"0123456789abcdefghij";
+        let metadata = service.upload_file(data, "digits.txt", "alice", None, None).await.unwrap();
+        let path = format!("/files/{}", metadata.hash);
+        let get = |range: Option<&str>| {
+            let request = Request::new("GET", &path).with_header("Authorization", &basic("alice", "alice password"));
+            match range {
+                Some(range) => request.with_header("Range", range),
+                None => request,
+            }
+        };
+
+        let response = handle(&service, &get(None)).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, data);
+        assert_eq!(response.header("Accept-Ranges"), Some("bytes"));
+        assert_eq!(response.header("Content-Range"), None);
+
+        for (range, content_range, body) in [
+            ("bytes=3-9", "bytes 3-9/20", &data[3..10]),
+            ("bytes=15-", "bytes 15-19/20", &data[15..]),
+            ("bytes=-4", "bytes 16-19/20", &data[16..]),
+            ("bytes=18-99", "bytes 18-19/20", &data[18..]),
+        ] {
+            let response = handle(&service, &get(Some(range))).await;
+            assert_eq!(response.status, 206, "{}", range);
+            assert_eq!(response.header("Content-Range"), Some(content_range));
+            assert_eq!(response.header("Content-Length"), Some(body.len().to_string().as_str()));
+            assert_eq!(response.body, body);
+        }
+
+        let response = handle(&service, &get(Some("bytes=20-"))).await;
+        assert_eq!(response.status, 416);
+        assert_eq!(response.header("Content-Range"), Some("bytes */20"));
+        assert!(response.to_bytes().starts_with(b"HTTP/1.1 416 Range Not Satisfiable\r\n"));
+
+        // Malformed and multi-range headers fall back to the whole file
+        assert_eq!(handle(&service, &get(Some("bytes=0-1,5-6"))).await.status, 200);
+        assert_eq!(handle(&service, &get(Some("lines=1-2"))).await.status, 200);
+    }
+
+    #[tokio::test]
+    async fn ranged_get_never_serves_corrupt_bytes() {
+        let (dir, mut service) = open_service().await;
+        service.register_user("alice", "alice password", None).await.unwrap();
+        service.storage.chunk_size = 4;
+        let metadata = service.upload_file(b"0123456789abcdefghij", "digits.txt", "alice", None, None).await.unwrap();
+        let chunk = dir.path().join("storage").join(format!("{}_1.chunk", metadata.hash.to_hex()));
+        std::fs::write(&chunk, b"XXXX").unwrap();
+        let path = format!("/files/{}", metadata.hash);
+        let get = |range: &str| Request::new("GET", &path)
+            .with_header("Authorization", &basic("alice", "alice password"))
+            .with_header("Range", range);
+
+        // The first chunk is intact, so a range inside it is still served
+        assert_eq!(handle(&service, &get("bytes=0-2")).await.body, b"012");
+        let response = handle(&service, &get("bytes=4-7")).await;
+        assert_eq!(response.status, 500);
+        assert_eq!(service.list_quarantined().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ranged_get_is_cross_checked_against_the_db_merkle_root() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("alice", "alice password", None).await.unwrap();
+        service.storage.chunk_size = 4;
+        let metadata = service.upload_file(b"0123456789abcdefghij", "digits.txt", "alice", None, None).await.unwrap();
+        let path = format!("/files/{}", metadata.hash);
+        let get = Request::new("GET", &path)
+            .with_header("Authorization", &basic("alice", "alice password"))
+            .with_header("Range", "bytes=4-7");
+        assert_eq!(handle(&service, &get).await.body, b"4567");
+        assert!(service.metrics_text().contains("sfs_downloads_total 1\n"));
+
+        let bogus = HashValue::compute(b"not the root", HashAlgo::Sha256);
+        service.database.set_merkle_root(&metadata.hash, &bogus).await.unwrap();
+        let response = handle(&service, &get).await;
+        assert_eq!(response.status, 500);
+        assert!(String::from_utf8_lossy(&response.body).contains("merkle root mismatch"), "{:?}", response.body);
+        assert_eq!(service.list_quarantined().await.unwrap().len(), 1);
+        assert!(service.metrics_text().contains("sfs_downloads_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn recipients_lose_http_access_to_a_recycled_file() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("alice", "alice password", None).await.unwrap();
+        service.register_user("bob", "bob password", None).await.unwrap();
+        let metadata = service.upload_file(b"soon binned", "b.txt", "alice", None, None).await.unwrap();
+        service.share_file(&metadata.hash, "alice", "bob", SharePermission::Read).await.unwrap();
+        let path = format!("/files/{}", metadata.hash);
+        let request = |method: &str| Request::new(method, &path).with_header("Authorization", &basic("bob", "bob password"));
+        assert_eq!(handle(&service, &request("GET")).await.status, 200);
+
+        service.delete_file(&metadata.hash, "alice").await.unwrap();
+        assert_eq!(handle(&service, &request("GET")).await.status, 404);
+        assert_eq!(handle(&service, &request("HEAD")).await.status, 404);
+    }
+
+    #[tokio::test]
+    async fn http_auth_shares_the_login_lockout() {
+        let (_dir, mut service) = open_service().await;
+        service.lockout_threshold = 2;
+        service.register_user("alice", "alice password", None).await.unwrap();
+        let metadata = service.upload_file(b"guarded", "g.txt", "alice", None, None).await.unwrap();
+        let path = format!("/files/{}", metadata.hash);
+        let head = |password: &str| Request::new("HEAD", &path).with_header("Authorization", &basic("alice", password));
+        assert_eq!(handle(&service, &head("alice password")).await.status, 200);
+
+        // Locked through login(): the right password no longer works over HTTP either
+        assert!(service.login("alice", "wrong").await.unwrap().is_none());
+        assert!(service.login("alice", "wrong").await.is_err());
+        assert_eq!(handle(&service, &head("alice password")).await.status, 401);
+        service.lockout_threshold = 0;
+        assert_eq!(handle(&service, &head("alice password")).await.status, 200);
+    }
+
+    #[tokio::test]
+    async fn http_failures_count_toward_lockout_and_leave_last_login_alone() {
+        let (_dir, mut service) = open_service().await;
+        service.lockout_threshold = 2;
+        service.register_user("alice", "alice password", None).await.unwrap();
+        service.login("alice", "alice password").await.unwrap().unwrap();
+        let last_login = service.database.get_user_by_username("alice").await.unwrap().unwrap().last_login;
+        let metadata = service.upload_file(b"guarded", "g.txt", "alice", None, None).await.unwrap();
+        let path = format!("/files/{}", metadata.hash);
+        let head = |password: &str| Request::new("HEAD", &path).with_header("Authorization", &basic("alice", password));
+
+        assert_eq!(handle(&service, &head("alice password")).await.status, 200);
+        assert_eq!(handle(&service, &head("wrong")).await.status, 401);
+        assert_eq!(handle(&service, &head("alice password")).await.status, 200);
+        assert_eq!(service.database.get_user_by_username("alice").await.unwrap().unwrap().last_login, last_login);
+        assert_eq!(handle(&service, &head("wrong")).await.status, 401);
+        assert!(matches!(
+            service.login("alice", "alice password").await.unwrap_err().downcast_ref(),
+            Some(FileSharingError::AccountLocked { .. }),
+        ));
+    }
+}
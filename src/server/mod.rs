@@ -0,0 +1,5 @@
+// ============================================================================
+// Server Module - HTTP Access to the Service
+// ============================================================================
+
+pub mod http;
@@ -2,91 +2,654 @@
 // File Sharing Service - Main Orchestrator with Database
 // ============================================================================
 
-use crate::crypto::hash::HashValue;
-use crate::crypto::commitment::Commitment;
-use crate::core::file_metadata::FileMetadata;
-use crate::storage::engine::StorageEngine;
+use crate::crypto::hash::{HashAlgo, HashValue};
+use crate::crypto::commitment::{self, CommitmentKind};
+use crate::core::file_metadata::{upload_name, FileMetadata};
+use crate::core::merkle_tree::{MerkleTree, DEFAULT_ARITY};
+use crate::core::mime::detect_mime;
+use crate::storage::engine::{StorageEngine, ChunkStatus, CompactReport, DedupScope, DedupStats, CHUNK_SIZE};
+use crate::storage::archive;
+use crate::storage::stream::ChunkStream;
 use crate::auth::authenticator::FileAuthenticator;
-use crate::db::{Database, User, FileRecord, SharedFile, SystemStats};
+use crate::auth::password;
+use crate::auth::provider::{AuthProvider, DatabaseAuthProvider};
+use crate::db::{Database, DatabaseConfig, FieldLimits, User, UserSummary, UserActivity, FileRecord, SharedFile, SharePermission, SystemStats, QuarantinedFile, Group, OutgoingShare, ShareStatus, AuditEntry, AuditFilter, FileQuery, SecurityEvent, ShareProof};
+use crate::error::FileSharingError;
+use crate::metrics::prometheus::Metrics;
+use crate::service::scanner::{ContentScanner, NoopScanner, ScanResult};
+use crate::service::notifier::{Notification, Notifier, NoopNotifier};
 use anyhow::{Result, Context};
-use std::collections::HashMap;
-use std::path::Path;
-use sha2::{Sha256, Digest};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::panic::AssertUnwindSafe;
+use std::io::Write;
+use tokio_util::sync::CancellationToken;
+use std::path::{Path, PathBuf};
+
+// Most attributes one file may carry; their lengths are limited by the database's FieldLimits
+pub const MAX_ATTRIBUTES: usize = 32;
+
+// How far ahead of this clock an imported timestamp may be
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+// Columns of export_listing_csv. File rows leave the share columns empty; share
+// rows fill only filename and the share columns, with shared_at as created_at.
+pub const LISTING_CSV_COLUMNS: [&str; 12] = [
+    "kind", "filename", "hash", "size", "mime_type", "created_at", "modified_at", "description",
+    "shared_with", "permission", "expires_at", "status",
+];
+
+// Rows fetched per query while exporting
+const EXPORT_PAGE_SIZE: i64 = 500;
+
+// One CSV record (RFC 4180): fields with a comma, quote or line break are
+// quoted, with embedded quotes doubled
+fn write_csv_row<W: Write>(writer: &mut W, fields: &[&str]) -> std::io::Result<()> {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(writer, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            writer.write_all(field.as_bytes())?;
+        }
+    }
+    writer.write_all(b"\r\n")
+}
+
+// What to do when a download target already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    Overwrite,
+    Skip,
+    #[default]
+    Rename, // name (1).ext, name (2).ext, ...
+}
+
+// Account the CLI makes an administrator at startup; there is no other way to get one
+pub const ADMIN_USER_ENV: &str = "SFS_ADMIN_USER";
+
+// What to do when a user uploads content they already own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateUpload {
+    #[default]
+    ReturnExisting, // idempotent: hand back the existing file untouched
+    Refresh,        // bump modified_at and take the new description, if given
+}
+
+// Drift between the files table and the storage engine
+#[derive(Debug, Default)]
+pub struct ReconcileReport {
+    pub db_without_storage: Vec<HashValue>, // rows whose content is gone
+    pub storage_without_db: Vec<HashValue>, // stored content no row refers to
+}
+
+impl ReconcileReport {
+    pub fn is_clean(&self) -> bool {
+        self.db_without_storage.is_empty() && self.storage_without_db.is_empty()
+    }
+}
+
+// Offline share handed over as text (e.g. a QR code). Everything needed to find
+// and check the file travels with it; the server's MAC stops forged tickets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareTicket {
+    pub hash: HashValue,
+    pub proof: ShareProof, // size and Merkle root the redeemed bytes must match
+    pub owner: String,
+    pub target: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+const SHARE_TICKET_KEY: &str = "share_ticket_mac";
+
+// Integrity of one account's files; `corrupt` holds each failing hash and why
+#[derive(Debug, Default)]
+pub struct UserIntegrityReport {
+    pub total: usize,
+    pub ok: usize,
+    pub corrupt: Vec<(HashValue, String)>,
+}
+
+// Result of an operation that can be stopped early; Cancelled carries the work done so far
+#[derive(Debug)]
+pub enum Progress<T> {
+    Complete(T),
+    Cancelled(T),
+}
+
+impl<T> Progress<T> {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Progress::Cancelled(_))
+    }
+    
+    pub fn into_inner(self) -> T {
+        match self {
+            Progress::Complete(value) | Progress::Cancelled(value) => value,
+        }
+    }
+}
+
+// One file's content digested under every supported algorithm
+#[derive(Debug, Clone)]
+pub struct MultiHash {
+    pub sha256: HashValue,
+    pub sha512: HashValue,
+    pub sha3_256: HashValue,
+    pub sha3_512: HashValue,
+    pub blake3: HashValue,
+}
+
+// Which hash algorithms files use, and the files below the configured minimum
+#[derive(Debug, Clone)]
+pub struct HashAlgoReport {
+    pub breakdown: Vec<(HashAlgo, i64)>,
+    pub below_minimum: Vec<FileRecord>,
+}
+
+// What a download would return, without the bytes
+#[derive(Debug, Clone, Serialize)]
+pub struct FileHead {
+    pub size: u64,
+    pub chunks: usize,
+    pub merkle_root: String,
+    pub created_at: DateTime<Utc>,
+    pub mime_type: String,
+}
+
+// Returned alongside a download of a superseded version
+#[derive(Debug, Clone)]
+pub struct VersionInfo {
+    pub latest_hash: HashValue,
+    pub newer_versions: usize,
+}
+
+// Readiness snapshot for probes and monitoring
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub db_ok: bool,
+    pub storage_writable: bool,
+    pub storage_dir_free_bytes: Option<u64>,
+    pub in_memory_fallback: bool,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.db_ok && self.storage_writable && !self.in_memory_fallback
+    }
+}
+
+// One step of `self_test`; detail carries the error of a failed step
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestStep {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: Option<String>,
+}
+
+// Steps run in order and stop at the first failure; cleanup always runs
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SelfTestReport {
+    pub steps: Vec<SelfTestStep>,
+}
+
+impl SelfTestReport {
+    pub fn all_passed(&self) -> bool {
+        !self.steps.is_empty() && self.steps.iter().all(|s| s.passed)
+    }
+    
+    fn record<T>(&mut self, name: &'static str, result: Result<T>) -> Option<T> {
+        let detail = result.as_ref().err().map(|e| format!("{:#}", e));
+        self.steps.push(SelfTestStep { name, passed: detail.is_none(), detail });
+        result.ok()
+    }
+}
+
+// Everything `FileSharingService::open` needs; the database and the storage/
+// and watch/ directories all live under data_dir
+#[derive(Debug, Clone)]
+pub struct ServiceConfig {
+    pub data_dir: PathBuf,
+    pub chunk_size: usize,
+    pub hash_algo: HashAlgo,          // whole-file hash (the content address)
+    pub chunk_algo: HashAlgo,         // per-chunk and Merkle hash
+    pub allowed_hash_algos: Option<Vec<HashAlgo>>,
+    pub commitment_scheme: CommitmentKind,
+    pub duplicate_upload: DuplicateUpload,
+    pub recycle_grace: Duration,
+    pub default_share_ttl: Option<Duration>,
+    pub max_share_ttl: Option<Duration>,
+    pub owner_dirs: bool,             // storage_dir/<owner>/ layout for new files
+    pub dedup_scope: DedupScope,
+    pub field_limits: FieldLimits,    // description and attribute lengths
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("./data"),
+            chunk_size: CHUNK_SIZE,
+            hash_algo: HashAlgo::Sha256,
+            chunk_algo: HashAlgo::Sha256,
+            allowed_hash_algos: None,
+            commitment_scheme: CommitmentKind::default(),
+            duplicate_upload: DuplicateUpload::default(),
+            recycle_grace: Duration::days(30),
+            default_share_ttl: None,
+            max_share_ttl: None,
+            owner_dirs: false,
+            dedup_scope: DedupScope::default(),
+            field_limits: FieldLimits::default(),
+        }
+    }
+}
 
 pub struct FileSharingService {
     pub storage: StorageEngine,
     pub authenticator: FileAuthenticator,
     pub database: Database,
+    pub auth_provider: Box<dyn AuthProvider>,
     pub current_user: Option<User>,
+    pub scanner: Box<dyn ContentScanner>,
+    pub notifier: Box<dyn Notifier>,
+    pub metrics: Metrics,
+    pub verify_db_root: bool, // Cross-check downloads against the DB's merkle_root
+    pub idempotency_window: Duration, // How long an upload key is honoured
+    pub max_share_recipients: usize,  // Cap for share_file_batch
+    pub disambiguate_names: bool,     // Show a short hash on filenames that repeat
+    pub commitment_scheme: CommitmentKind, // Scheme for new share commitments
+    pub share_proofs: bool,           // Record size and Merkle root with new shares
+    pub recycle_grace: Duration,      // How long deleted files can be restored
+    pub default_share_ttl: Option<Duration>, // Expiry for shares made without one (None = never)
+    pub max_share_ttl: Option<Duration>,     // Longest expiry a share may have (None = unlimited)
+    pub min_hash_strength: usize,     // Bits below which hash_algo_report flags a file
+    pub allowed_hash_algos: Option<Vec<HashAlgo>>, // Algorithms uploads may use (None = any)
+    pub duplicate_upload: DuplicateUpload, // Re-upload of content the user already owns
+    pub verify_concurrency: usize,    // Files verify_all reads at once (1 = sequential)
+    pub report_newer_versions: bool,  // Whether downloads of old versions say so
+    pub lockout_threshold: u32,       // Failed logins that lock an account (0 = never lock)
+    pub lockout_duration: Duration,   // How long a lock lasts, and the window failures are counted in
+    pub reset_token_ttl: Duration,    // How long a password reset token can be used
+    pub login_security_events: Vec<SecurityEvent>, // What happened to the account since its previous login
     users: HashMap<String, User>, // Cache
     _shares: HashMap<String, Vec<crate::db::models::SharedFile>>, // Cache with underscore
 }
 
 impl FileSharingService {
     pub async fn new(storage_path: &Path, watch_path: &Path, database: Database) -> Result<Self> {
-        Ok(Self {
-            storage: StorageEngine::new(storage_path)?,
+        let mut storage = StorageEngine::new(storage_path)?;
+        storage.hash_algo = HashAlgo::from_env()?;
+        Ok(Self::with_parts(storage, watch_path, database))
+    }
+    
+    fn with_parts(storage: StorageEngine, watch_path: &Path, database: Database) -> Self {
+        Self {
+            storage,
             authenticator: FileAuthenticator::new(watch_path),
+            auth_provider: Box::new(DatabaseAuthProvider::new(database.clone())),
             database,
             current_user: None,
+            scanner: Box::new(NoopScanner),
+            notifier: Box::new(NoopNotifier),
+            metrics: Metrics::new(),
+            verify_db_root: true,
+            idempotency_window: Duration::hours(24),
+            max_share_recipients: 50,
+            disambiguate_names: true,
+            commitment_scheme: CommitmentKind::default(),
+            share_proofs: true,
+            recycle_grace: Duration::days(30),
+            default_share_ttl: None,
+            max_share_ttl: None,
+            min_hash_strength: 128,
+            allowed_hash_algos: None,
+            duplicate_upload: DuplicateUpload::default(),
+            verify_concurrency: 4,
+            report_newer_versions: true,
+            lockout_threshold: 5,
+            lockout_duration: Duration::minutes(15),
+            reset_token_ttl: Duration::hours(1),
+            login_security_events: Vec::new(),
             users: HashMap::new(),
             _shares: HashMap::new(),
-        })
+        }
     }
     
-    pub async fn register_user(&mut self, username: &str, password: &str, email: Option<&str>) -> Result<User> {
-        // Hash password (in production, use proper password hashing like bcrypt)
-        let mut hasher = Sha256::new();
-        hasher.update(password.as_bytes());
-        let password_hash = hex::encode(hasher.finalize());
+    // Single entry point for embedders: builds the database, storage engine and
+    // authenticator from one config. Unlike `new`, ignores SFS_DEFAULT_HASH.
+    pub async fn open(config: ServiceConfig) -> Result<Self> {
+        let storage_path = config.data_dir.join("storage");
+        let watch_path = config.data_dir.join("watch");
+        std::fs::create_dir_all(&watch_path)
+            .with_context(|| format!("Cannot create directory {}", watch_path.display()))?;
+        
+        let database = Database::with_config(DatabaseConfig {
+            data_dir: config.data_dir.clone(),
+            limits: config.field_limits.clone(),
+            ..DatabaseConfig::default()
+        }).await?;
         
-        let user = self.database.create_user(username, &password_hash, email).await?;
-        self.users.insert(username.to_string(), user.clone());
+        let mut storage = StorageEngine::new(&storage_path)?;
+        storage.chunk_size = config.chunk_size;
+        storage.hash_algo = config.hash_algo;
+        storage.chunk_algo = config.chunk_algo;
+        storage.owner_dirs = config.owner_dirs;
+        storage.dedup_scope = config.dedup_scope;
+        
+        let mut service = Self::with_parts(storage, &watch_path, database);
+        service.allowed_hash_algos = config.allowed_hash_algos;
+        service.commitment_scheme = config.commitment_scheme;
+        service.duplicate_upload = config.duplicate_upload;
+        service.recycle_grace = config.recycle_grace;
+        service.default_share_ttl = config.default_share_ttl;
+        service.max_share_ttl = config.max_share_ttl;
+        Ok(service)
+    }
+    
+    pub async fn register_user(&mut self, username: &str, password: &str, email: Option<&str>) -> Result<User> {
+        password::check_password(password)?;
+        let user = self.auth_provider.register(username, password, email).await?;
+        self.database.record_audit(Some(user.id), "register", None).await?;
+        self.users.insert(user.username.clone(), user.clone());
         println!("👤 User registered: {}", username);
         Ok(user)
     }
     
     pub async fn login(&mut self, username: &str, password: &str) -> Result<Option<User>> {
-        let user_opt = self.database.get_user_by_username(username).await?;
+        // Lockout bookkeeping needs a local account; unknown names just fail
+        let known = self.database.get_user_by_username(username).await?;
+        if let Some(known) = &known {
+            if let Some(until) = self.locked_until(known.id).await? {
+                return Err(FileSharingError::AccountLocked { until }.into());
+            }
+        }
         
-        if let Some(user) = user_opt {
-            // Verify password
-            let mut hasher = Sha256::new();
-            hasher.update(password.as_bytes());
-            let password_hash = hex::encode(hasher.finalize());
-            
-            if user.password_hash == password_hash {
+        let user = self.auth_provider.authenticate(username, password).await?;
+        
+        match (&user, &known) {
+            (Some(user), _) => {
+                if known.is_some() {
+                    self.database.update_last_login(user.id).await?;
+                }
                 self.current_user = Some(user.clone());
-                self.database.update_last_login(user.id).await?;
+                self.database.record_audit(Some(user.id), "login", None).await?;
+                self.login_security_events = self.database
+                    .security_events_since(user.id, known.as_ref().and_then(|k| k.last_login))
+                    .await?;
                 println!(" User logged in: {}", username);
-                return Ok(Some(user));
             }
+            (None, Some(known)) => self.record_failed_login(known).await?,
+            (None, None) => {}
+        }
+        
+        Ok(user)
+    }
+    
+    // Credentials for a single request (HTTP Basic), under the same lockout as
+    // login(). No session starts and last_login is left alone, so the failures
+    // shown at the next interactive login still include these.
+    pub async fn verify_credentials(&self, username: &str, password: &str) -> Result<Option<User>> {
+        let known = self.database.get_user_by_username(username).await?;
+        if let Some(known) = &known {
+            if let Some(until) = self.locked_until(known.id).await? {
+                return Err(FileSharingError::AccountLocked { until }.into());
+            }
+        }
+        
+        let user = self.auth_provider.authenticate(username, password).await?;
+        if let (None, Some(known)) = (&user, &known) {
+            self.record_failed_login(known).await?;
+        }
+        Ok(user)
+    }
+    
+    // When the account's latest lock is still in force
+    async fn locked_until(&self, user_id: i64) -> Result<Option<DateTime<Utc>>> {
+        if self.lockout_threshold == 0 {
+            return Ok(None);
+        }
+        let until = self.database.last_security_event(user_id, "account_locked").await?
+            .map(|locked_at| locked_at + self.lockout_duration);
+        Ok(until.filter(|until| *until > Utc::now()))
+    }
+    
+    // Failures count from the latest of: the start of the lockout window, the last
+    // successful login and the last lock, so each lock needs a fresh run of failures
+    async fn record_failed_login(&self, user: &User) -> Result<()> {
+        self.database.record_security_event(user.id, "login_failed", None).await?;
+        if self.lockout_threshold == 0 {
+            return Ok(());
+        }
+        
+        let since = [
+            Some(Utc::now() - self.lockout_duration),
+            user.last_login,
+            self.database.last_security_event(user.id, "account_locked").await?,
+        ].into_iter().flatten().max().unwrap_or_else(Utc::now);
+        let failures = self.database.count_security_events(user.id, "login_failed", since).await? as u32;
+        if failures < self.lockout_threshold {
+            return Ok(());
+        }
+        
+        let locked_until = Utc::now() + self.lockout_duration;
+        self.database.record_security_event(
+            user.id, "account_locked", Some(&format!("{} failed logins", failures)),
+        ).await?;
+        println!("🔒 Account locked after {} failed logins: {}", failures, user.username);
+        
+        if let Some(email) = &user.email {
+            self.notifier.notify(email, &Notification::AccountLocked {
+                username: user.username.clone(),
+                failed_attempts: failures,
+                locked_until,
+            }).await;
+        }
+        Err(FileSharingError::AccountLocked { until: locked_until }.into())
+    }
+    
+    pub async fn change_password(&mut self, username: &str, old_password: &str, new_password: &str) -> Result<()> {
+        let user = self.database.get_user_by_username(username).await?
+            .context("User not found")?;
+        
+        if !password::verify_password(old_password, &user.password_hash) {
+            anyhow::bail!("Old password is incorrect");
+        }
+        
+        password::check_password(new_password)?;
+        let new_hash = password::hash_password(new_password)?;
+        self.database.update_password(user.id, &new_hash).await?;
+        self.database.record_audit(Some(user.id), "password_change", None).await?;
+        self.users.remove(&user.username);
+        
+        // Invalidate the active session for this user
+        if self.current_user.as_ref().map(|u| u.id) == Some(user.id) {
+            self.current_user = None;
+        }
+        
+        println!("🔑 Password changed: {}", username);
+        Ok(())
+    }
+    
+    // Send a reset token through the notifier. Succeeds quietly for unknown users
+    // and users without an email, so the answer reveals nothing about accounts.
+    pub async fn request_password_reset(&self, username: &str) -> Result<()> {
+        let user = match self.database.get_user_by_username(username).await? {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+        let email = match &user.email {
+            Some(email) => email,
+            None => {
+                println!("⚠️  Password reset requested for {}, who has no email", user.username);
+                return Ok(());
+            }
+        };
+        
+        let token = self.database.create_reset_token(user.id, self.reset_token_ttl).await?;
+        self.notifier.notify(email, &Notification::PasswordReset {
+            username: user.username.clone(),
+            token,
+            expires_at: Utc::now() + self.reset_token_ttl,
+        }).await;
+        self.database.record_audit(Some(user.id), "password_reset_request", None).await?;
+        println!("📧 Password reset sent: {}", user.username);
+        Ok(())
+    }
+    
+    // Policy is checked before the token is spent, so a rejected password can be retried
+    pub async fn reset_password(&mut self, token: &str, new_password: &str) -> Result<()> {
+        password::check_password(new_password)?;
+        let user_id = self.database.consume_reset_token(token).await?
+            .ok_or(FileSharingError::InvalidResetToken)?;
+        let user = self.database.get_user_by_id(user_id).await?
+            .context("User not found")?;
+        
+        let new_hash = password::hash_password(new_password)?;
+        self.database.update_password(user.id, &new_hash).await?;
+        self.database.record_audit(Some(user.id), "password_reset", None).await?;
+        self.users.remove(&user.username);
+        
+        // Invalidate the active session for this user
+        if self.current_user.as_ref().map(|u| u.id) == Some(user.id) {
+            self.current_user = None;
         }
         
-        Ok(None)
+        println!("🔑 Password reset: {}", user.username);
+        Ok(())
     }
     
     pub fn logout(&mut self) {
         self.current_user = None;
+        self.login_security_events.clear();
         println!(" User logged out");
     }
     
+    // End the session and flush the database to disk
+    pub async fn shutdown(mut self) {
+        if self.current_user.is_some() {
+            self.logout();
+        }
+        self.database.close().await;
+    }
+    
     pub async fn upload_file(
         &mut self, 
         data: &[u8], 
         filename: &str, 
         owner: &str,
         description: Option<&str>,
+        idempotency_key: Option<&str>,
+    ) -> Result<FileMetadata> {
+        self.store_upload(data, filename, owner, description, idempotency_key, None, None).await
+    }
+    
+    // Upload that keeps a file's original timestamps, e.g. when importing history.
+    // Timestamps may not be in the future beyond MAX_CLOCK_SKEW_SECS.
+    pub async fn import_file(
+        &mut self,
+        data: &[u8],
+        filename: &str,
+        owner: &str,
+        description: Option<&str>,
+        created_at: DateTime<Utc>,
+        modified_at: Option<DateTime<Utc>>,
+    ) -> Result<FileMetadata> {
+        let latest = Utc::now() + Duration::seconds(MAX_CLOCK_SKEW_SECS);
+        if created_at > latest {
+            return Err(FileSharingError::InvalidTimestamp(format!("created_at {} is in the future", created_at)).into());
+        }
+        if let Some(modified_at) = modified_at {
+            if modified_at > latest {
+                return Err(FileSharingError::InvalidTimestamp(format!("modified_at {} is in the future", modified_at)).into());
+            }
+            if modified_at < created_at {
+                return Err(FileSharingError::InvalidTimestamp("modified_at is before created_at".to_string()).into());
+            }
+        }
+        
+        self.store_upload(data, filename, owner, description, None, Some(created_at), modified_at).await
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    async fn store_upload(
+        &mut self,
+        data: &[u8],
+        filename: &str,
+        owner: &str,
+        description: Option<&str>,
+        idempotency_key: Option<&str>,
+        created_at: Option<DateTime<Utc>>,
+        modified_at: Option<DateTime<Utc>>,
     ) -> Result<FileMetadata> {
+        self.check_hash_policy()?;
+        self.database.checked_description(description)?;
+        
         // Get user from database
         let user = self.database.get_user_by_username(owner).await?
             .context("User not found")?;
         
+        // A retried upload with a recent key gets the original result back
+        if let Some(key) = idempotency_key {
+            let since = Utc::now() - self.idempotency_window;
+            if let Some(record) = self.database.get_idempotent_upload(user.id, key, since).await? {
+                if let Some(mut metadata) = self.storage.metadata(&record.hash_value()?).cloned() {
+                    println!("🔁 Repeated upload key '{}': returning {}", key, metadata.hash.prefix(8));
+                    metadata.path = PathBuf::from(&record.filename);
+                    metadata.created_at = record.created_at;
+                    metadata.modified_at = record.modified_at;
+                    return Ok(metadata);
+                }
+            }
+        }
+        
+        // Scan content before any chunk is written
+        if let ScanResult::Infected(reason) = self.scanner.scan(data).await {
+            println!("🦠 Upload rejected: {} ({})", filename, reason);
+            return Err(FileSharingError::ContentRejected { reason }.into());
+        }
+        
+        let hash = HashValue::compute(data, self.storage.hash_algo);
+        
+        // Re-uploading a file from the recycle bin brings the old row back
+        if let Some(deleted) = self.database.get_deleted_file(&hash, user.id).await? {
+            if let Some(metadata) = self.storage.metadata(&deleted.hash_value()?).cloned() {
+                self.database.set_file_deleted(deleted.id, None).await?;
+                println!("♻️  Restored from recycle bin: {}", deleted.filename);
+                return Ok(metadata);
+            }
+        }
+        
+        // A second upload of the same bytes would violate UNIQUE(hash, owner_id)
+        if let Some(existing) = self.database.get_owned_file(&hash, user.id).await? {
+            if let Some(mut metadata) = self.storage.metadata(&hash).cloned() {
+                let record = match self.duplicate_upload {
+                    DuplicateUpload::ReturnExisting => existing,
+                    DuplicateUpload::Refresh => self.database.refresh_file(existing.id, description).await?,
+                };
+                println!("🔁 Already uploaded: {} (stored as {})", filename, record.filename);
+                metadata.path = PathBuf::from(&record.filename);
+                metadata.created_at = record.created_at;
+                metadata.modified_at = record.modified_at;
+                return Ok(metadata);
+            }
+        }
+        
         // Store file in storage engine
-        let metadata = self.storage.store_file(data, filename, owner)?;
+        let unique_before = self.storage.dedup_stats.unique_files;
+        let mut metadata = self.storage.store_file(data, filename, owner, created_at, modified_at)?;
+        let newly_stored = self.storage.dedup_stats.unique_files != unique_before;
+        if !newly_stored {
+            Metrics::inc(&self.metrics.dedup_hits);
+        }
         
-        // Save to database
-        self.database.save_file(
+        // Save to database; on failure roll back chunks written by this upload.
+        // Dedup hits reuse chunks other files depend on, so those are left alone.
+        let saved = self.database.save_file(
             &metadata.hash,
             filename,
             metadata.size,
@@ -94,68 +657,3307 @@ impl FileSharingService {
             description,
             metadata.chunks.len(),
             &metadata.merkle_root,
-        ).await?;
+            &detect_mime(filename, data),
+            created_at,
+            modified_at,
+        ).await;
+        
+        let record = match saved {
+            Ok(record) => record,
+            Err(e) => {
+                if newly_stored {
+                    // Only this owner's new copy goes if others share the content
+                    let rollback = self.storage.remove_copy(&metadata.hash, owner)
+                        .and_then(|removed| if removed { Ok(()) } else { self.storage.delete_file(&metadata.hash) });
+                    if let Err(cleanup) = rollback {
+                        println!("⚠️  rollback of {} failed: {}", metadata.hash.prefix(8), cleanup);
+                    }
+                }
+                return Err(e.context("Failed to record upload"));
+            }
+        };
+        
+        if let Some(key) = idempotency_key {
+            self.database.record_idempotency_key(user.id, key, record.id).await?;
+        }
+        self.database.record_audit(Some(user.id), "upload", Some(&metadata.hash.to_string())).await?;
+        
+        // Register with authenticator only once the DB row is committed, keyed per owner so equal filenames don't collide
+        self.authenticator.register_bytes(&format!("{}/{}", owner, filename), data);
+        
+        // Timestamps and name of this owner's copy, not of the shared content
+        metadata.path = PathBuf::from(filename);
+        metadata.created_at = record.created_at;
+        metadata.modified_at = record.modified_at;
+        
+        Metrics::inc(&self.metrics.uploads);
+        Ok(metadata)
+    }
+    
+    // Upload every regular file directly inside `dir`, in name order. Cancellation
+    // is checked between files, so each file is either fully recorded or untouched.
+    pub async fn upload_directory(
+        &mut self,
+        dir: &Path,
+        owner: &str,
+        cancel: &CancellationToken,
+    ) -> Result<Progress<Vec<FileMetadata>>> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+        
+        // Check every name first so a bad one doesn't leave the upload half done
+        let named = paths.into_iter()
+            .map(|path| upload_name(&path).map(|name| (path, name)))
+            .collect::<Result<Vec<_>, _>>()?;
+        
+        let mut uploaded = Vec::new();
+        for (path, filename) in named {
+            if cancel.is_cancelled() {
+                println!("⏹️  Directory upload cancelled after {} file(s)", uploaded.len());
+                return Ok(Progress::Cancelled(uploaded));
+            }
+            let data = std::fs::read(&path)?;
+            uploaded.push(self.upload_file(&data, &filename, owner, None, None).await?);
+        }
+        Ok(Progress::Complete(uploaded))
+    }
+    
+    // Move a file to the recycle bin. Its chunks stay until purge_expired runs
+    // after the grace period.
+    pub async fn delete_file(&mut self, file_hash: &HashValue, owner: &str) -> Result<()> {
+        let user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        let file = self.database.get_owned_file(file_hash, user.id).await?
+            .context("File not found")?;
+        
+        self.database.set_file_deleted(file.id, Some(Utc::now())).await?;
+        self.database.record_audit(Some(user.id), "delete", Some(&file_hash.to_string())).await?;
+        println!("🗑️  Moved to recycle bin: {}", file.filename);
+        Ok(())
+    }
+    
+    pub async fn restore_file(&mut self, file_hash: &HashValue, owner: &str) -> Result<FileRecord> {
+        let user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        let file = self.database.get_deleted_file(file_hash, user.id).await?
+            .context("File is not in the recycle bin")?;
+        if !self.storage.contains(file_hash) {
+            anyhow::bail!("content of {} is no longer stored", file.filename);
+        }
+        
+        self.database.set_file_deleted(file.id, None).await?;
+        self.database.record_audit(Some(user.id), "restore", Some(&file_hash.to_string())).await?;
+        println!("♻️  Restored: {}", file.filename);
+        Ok(FileRecord { deleted_at: None, ..file })
+    }
+    
+    pub async fn recycle_bin(&self, owner: &str) -> Result<Vec<FileRecord>> {
+        let user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        self.database.get_deleted_files(user.id).await
+    }
+    
+    // Hard-delete files past the grace period; content goes once no row refers to it
+    pub async fn purge_expired(&mut self) -> Result<usize> {
+        let purged = self.database.purge_deleted_before(Utc::now() - self.recycle_grace).await?;
+        
+        for file in &purged {
+            let hash = file.hash_value()?;
+            if !self.storage.contains(&hash) {
+                continue;
+            }
+            if self.database.count_files_with_hash(&hash).await? == 0 {
+                self.storage.delete_file(&hash)?;
+            } else if self.database.get_owned_file(&hash, file.owner_id).await?.is_none() {
+                // Others still use the content; drop this owner's private copy, if any
+                if let Some(owner) = self.database.get_user_by_id(file.owner_id).await? {
+                    self.storage.remove_copy(&hash, &owner.username)?;
+                }
+            }
+        }
+        if !purged.is_empty() {
+            println!("🧹 Purged {} expired file(s) from the recycle bin", purged.len());
+        }
+        Ok(purged.len())
+    }
+    
+    // Both the content address and the chunk hashes of new content must meet the policy
+    fn check_hash_policy(&self) -> Result<()> {
+        if let Some(allowed) = &self.allowed_hash_algos {
+            for algo in [self.storage.hash_algo, self.storage.chunk_algo] {
+                if !allowed.contains(&algo) {
+                    return Err(FileSharingError::HashAlgoNotAllowed(algo.to_string()).into());
+                }
+            }
+        }
+        Ok(())
+    }
+    
+    // Replace a file's content with a new version. The new version keeps the
+    // original created_at and advances modified_at; the old version remains stored.
+    pub async fn update_file(&mut self, old_hash: &HashValue, data: &[u8], owner: &str) -> Result<FileMetadata> {
+        self.update_file_with_reshare(old_hash, data, owner, false).await
+    }
+    
+    // With `reshare`, everyone holding an active share of the old version gets one
+    // of the new version too, with the same permission and expiry
+    pub async fn update_file_with_reshare(
+        &mut self,
+        old_hash: &HashValue,
+        data: &[u8],
+        owner: &str,
+        reshare: bool,
+    ) -> Result<FileMetadata> {
+        self.check_hash_policy()?;
+        let user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        let previous = self.database.get_owned_file(old_hash, user.id).await?
+            .context("File not found")?;
+        
+        if let ScanResult::Infected(reason) = self.scanner.scan(data).await {
+            println!("🦠 Update rejected: {} ({})", previous.filename, reason);
+            return Err(FileSharingError::ContentRejected { reason }.into());
+        }
+        
+        let unique_before = self.storage.dedup_stats.unique_files;
+        let mut metadata = self.storage.store_file(data, &previous.filename, owner, None, None)?;
+        let newly_stored = self.storage.dedup_stats.unique_files != unique_before;
+        
+        let saved = self.database.save_file_version(
+            &previous,
+            &metadata.hash,
+            metadata.size,
+            metadata.chunks.len(),
+            &metadata.merkle_root,
+            &detect_mime(&previous.filename, data),
+        ).await;
         
-        // Register with authenticator
-        let temp_path = self.authenticator.watch_dir.join(filename);
-        std::fs::write(&temp_path, data)?;
-        self.authenticator.register(&temp_path)?;
+        let record = match saved {
+            Ok(record) => record,
+            Err(e) => {
+                if newly_stored {
+                    // Only this owner's new copy goes if others share the content
+                    let rollback = self.storage.remove_copy(&metadata.hash, owner)
+                        .and_then(|removed| if removed { Ok(()) } else { self.storage.delete_file(&metadata.hash) });
+                    if let Err(cleanup) = rollback {
+                        println!("⚠️  rollback of {} failed: {}", metadata.hash.prefix(8), cleanup);
+                    }
+                }
+                return Err(e.context("Failed to record file update"));
+            }
+        };
+        
+        self.authenticator.register_bytes(&format!("{}/{}", owner, previous.filename), data);
+        self.database.record_audit(Some(user.id), "update", Some(&metadata.hash.to_string())).await?;
+        
+        if reshare {
+            let commitment_bytes = commitment::commit_bytes(self.commitment_scheme, metadata.hash.bytes.as_slice())?;
+            let proof = self.share_proof(&metadata.hash);
+            let copied = self.database.copy_shares(
+                previous.id, record.id, &commitment_bytes, self.commitment_scheme, proof.as_ref(),
+            ).await?;
+            println!("🔗 Re-shared new version with {} recipient(s)", copied);
+        }
         
+        metadata.created_at = record.created_at;
+        metadata.modified_at = record.modified_at;
+        println!("📝 File updated: {} ({} -> {})", previous.filename, old_hash.prefix(8), metadata.hash.prefix(8));
         Ok(metadata)
     }
     
-    pub async fn share_file(&mut self, file_hash: &HashValue, owner: &str, target: &str) -> Result<()> {
+    pub async fn share_file(
+        &mut self, 
+        file_hash: &HashValue, 
+        owner: &str, 
+        target: &str,
+        permission: SharePermission,
+    ) -> Result<()> {
+        self.share_file_until(file_hash, owner, target, permission, None).await
+    }
+    
+    // Apply the expiry policy: no expiry means default_share_ttl, and any
+    // expiry (or the lack of one) is capped at max_share_ttl
+    pub fn share_expiry(&self, requested: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        let expiry = requested.or_else(|| self.default_share_ttl.map(|ttl| now + ttl));
+        match self.max_share_ttl.map(|max| now + max) {
+            Some(latest) => Some(expiry.map_or(latest, |e| e.min(latest))),
+            None => expiry,
+        }
+    }
+    
+    pub async fn share_file_until(
+        &mut self,
+        file_hash: &HashValue,
+        owner: &str,
+        target: &str,
+        permission: SharePermission,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
         // Get users
         let owner_user = self.database.get_user_by_username(owner).await?
             .context("Owner not found")?;
         let target_user = self.database.get_user_by_username(target).await?
             .context("Target user not found")?;
         
-        // Get file
-        let file = self.database.get_file_by_hash(file_hash).await?
-            .context("File not found")?;
+        if owner_user.id == target_user.id {
+            return Err(FileSharingError::CannotShareWithSelf.into());
+        }
+        if self.database.get_owned_file(file_hash, target_user.id).await?.is_some() {
+            return Err(FileSharingError::TargetAlreadyOwns { target: target.to_string() }.into());
+        }
+        
+        let (file_id, sharer_expiry) = self.shareable_file_id(file_hash, &owner_user).await?;
         
         // Create commitment
-        let commitment = Commitment::commit(file_hash.bytes.as_slice());
-        let commitment_bytes = bincode::serialize(&commitment)?;
+        let commitment_bytes = commitment::commit_bytes(self.commitment_scheme, file_hash.bytes.as_slice())?;
         
         // Save to database
         self.database.create_share(
-            file.id,
+            file_id,
             owner_user.id,
             target_user.id,
             Some(&commitment_bytes),
-            None, // No expiration
+            self.commitment_scheme,
+            self.share_proof(file_hash).as_ref(),
+            permission,
+            self.reshare_expiry(expires_at, sharer_expiry),
         ).await?;
         
+        Metrics::inc(&self.metrics.shares);
+        self.database.record_audit(Some(owner_user.id), "share", Some(&format!("{} -> {}", file_hash, target))).await?;
         println!("🔗 File shared: {} -> {}", owner, target);
         Ok(())
     }
     
-    pub async fn download_and_verify(&self, file_hash: &HashValue) -> Result<Vec<u8>> {
-        let data = self.storage.retrieve_file(file_hash)?;
-        println!(" File verified: {} integrity check passed", file_hash.prefix(8));
-        Ok(data)
-    }
-    
-    pub async fn get_user_files(&self, username: &str) -> Result<Vec<FileRecord>> {
-        self.database.get_user_files(username).await
-    }
-    
-    pub async fn get_shared_files(&self, username: &str) -> Result<Vec<SharedFile>> {
-        self.database.get_shared_files(username).await
+    fn share_proof(&self, file_hash: &HashValue) -> Option<ShareProof> {
+        if !self.share_proofs {
+            return None;
+        }
+        self.storage.metadata(file_hash).map(ShareProof::from_metadata)
     }
     
-    pub async fn verify_file_integrity(&self, file_hash: &HashValue) -> Result<bool> {
-        match self.storage.retrieve_file(file_hash) {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
+    // Encode a ShareTicket as base64url(bincode(ticket bytes, keyed BLAKE3 MAC)).
+    // No share row is written until the recipient redeems it.
+    pub async fn share_token_string(
+        &self,
+        file_hash: &HashValue,
+        owner: &str,
+        target: &str,
+        ttl: Duration,
+    ) -> Result<String> {
+        let owner_user = self.database.get_user_by_username(owner).await?
+            .context("Owner not found")?;
+        let target_user = self.database.get_user_by_username(target).await?
+            .context("Target user not found")?;
+        if owner_user.id == target_user.id {
+            return Err(FileSharingError::CannotShareWithSelf.into());
         }
+        self.database.get_owned_file(file_hash, owner_user.id).await?
+            .context("File not found")?;
+        let metadata = self.storage.metadata(file_hash)
+            .context("File content not found in storage")?;
+        
+        let ticket = ShareTicket {
+            hash: file_hash.clone(),
+            proof: ShareProof::from_metadata(metadata),
+            owner: owner_user.username,
+            target: target_user.username,
+            expires_at: Utc::now() + ttl,
+        };
+        let body = bincode::serialize(&ticket)?;
+        let key = self.database.get_or_create_secret(SHARE_TICKET_KEY).await?;
+        let mac = *blake3::keyed_hash(&key, &body).as_bytes();
+        
+        println!("🎫 Share string created: {} -> {}", file_hash.prefix(8), target);
+        Ok(URL_SAFE_NO_PAD.encode(bincode::serialize(&(body, mac))?))
     }
     
-    pub async fn get_system_stats(&self) -> Result<SystemStats> {
-        let mut stats = self.database.get_system_stats().await?;
-        stats.bloom_fp_rate = self.authenticator.bloom.false_positive_rate();
-        Ok(stats)
-    }
-}
\ No newline at end of file
+    // Check a share string, fetch and verify the file against it, and record the
+    // share so it also appears in the recipient's list. Redeeming twice is harmless.
+    pub async fn redeem_share_string(&self, share_string: &str, redeemer: &str) -> Result<Vec<u8>> {
+        let invalid = |reason: &str| FileSharingError::InvalidShareString(reason.to_string());
+        let raw = URL_SAFE_NO_PAD.decode(share_string.trim())
+            .map_err(|_| invalid("not base64url"))?;
+        let (body, mac): (Vec<u8>, [u8; 32]) = bincode::deserialize(&raw)
+            .map_err(|_| invalid("malformed"))?;
+        let key = self.database.get_or_create_secret(SHARE_TICKET_KEY).await?;
+        if blake3::keyed_hash(&key, &body) != blake3::Hash::from(mac) {
+            return Err(invalid("signature does not match").into());
+        }
+        let ticket: ShareTicket = bincode::deserialize(&body)
+            .map_err(|_| invalid("malformed"))?;
+        if ticket.expires_at <= Utc::now() {
+            return Err(invalid("expired").into());
+        }
+        
+        let redeemer_user = self.database.get_user_by_username(redeemer).await?
+            .context("User not found")?;
+        let target_user = self.database.get_user_by_username(&ticket.target).await?;
+        if target_user.map(|u| u.id) != Some(redeemer_user.id) {
+            return Err(FileSharingError::PermissionDenied(
+                "this share string is for another user".to_string()
+            ).into());
+        }
+        let owner_user = self.database.get_user_by_username(&ticket.owner).await?
+            .context("Owner not found")?;
+        let file = self.database.get_owned_file(&ticket.hash, owner_user.id).await?
+            .context("The owner no longer has this file")?;
+        
+        let data = self.download_copy(&ticket.hash, Some(file.clone())).await?;
+        if HashValue::compute(&data, ticket.hash.algo) != ticket.hash || !ticket.proof.matches(&data) {
+            return Err(invalid("content does not match").into());
+        }
+        
+        let commitment_bytes = commitment::commit_bytes(self.commitment_scheme, ticket.hash.bytes.as_slice())?;
+        self.database.create_shares(
+            file.id,
+            owner_user.id,
+            &[redeemer_user.id],
+            Some(&commitment_bytes),
+            self.commitment_scheme,
+            Some(&ticket.proof),
+            SharePermission::Read,
+            self.share_expiry(Some(ticket.expires_at)),
+        ).await?;
+        self.database.record_audit(
+            Some(redeemer_user.id), "redeem_share_string", Some(&ticket.hash.to_string()),
+        ).await?;
+        println!("🎫 Share string redeemed: {} by {}", ticket.hash.prefix(8), redeemer);
+        Ok(data)
+    }
+    
+    // Only the file's owner or a recipient granted `reshare` may share it. Also
+    // returns the expiry a re-sharer's own share has, which new shares can't outlast.
+    async fn shareable_file_id(&self, file_hash: &HashValue, sharer: &User) -> Result<(i64, Option<DateTime<Utc>>)> {
+        if let Some(file) = self.database.get_owned_file(file_hash, sharer.id).await? {
+            return Ok((file.id, None));
+        }
+        let share = self.database.get_received_share(file_hash, sharer.id).await?
+            .context("File not found")?;
+        if share.permission != SharePermission::Reshare {
+            return Err(FileSharingError::PermissionDenied(
+                format!("{} may not re-share this file", sharer.username)
+            ).into());
+        }
+        Ok((share.file_id, share.expires_at))
+    }
+    
+    // Expiry for a share made by someone whose own access ends at `limit`
+    fn reshare_expiry(&self, requested: Option<DateTime<Utc>>, limit: Option<DateTime<Utc>>) -> Option<DateTime<Utc>> {
+        match (self.share_expiry(requested), limit) {
+            (Some(expiry), Some(limit)) => Some(expiry.min(limit)),
+            (expiry, limit) => expiry.or(limit),
+        }
+    }
+    
+    pub async fn create_group(&self, owner: &str, name: &str) -> Result<Group> {
+        let user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        let group = self.database.create_group(name, user.id).await?;
+        println!("👥 Group created: {}", name);
+        Ok(group)
+    }
+    
+    // Only the group's owner manages its membership
+    pub async fn add_group_member(&self, owner: &str, group_name: &str, member: &str) -> Result<bool> {
+        let owner_user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        let group = self.database.get_group_by_name(group_name).await?
+            .context("Group not found")?;
+        if group.owner_id != owner_user.id {
+            return Err(FileSharingError::PermissionDenied(
+                format!("{} does not own group {}", owner, group_name)
+            ).into());
+        }
+        let member_user = self.database.get_user_by_username(member).await?
+            .context("User not found")?;
+        
+        self.database.add_member(group.id, member_user.id).await
+    }
+    
+    pub async fn get_user_groups(&self, username: &str) -> Result<Vec<Group>> {
+        let user = self.database.get_user_by_username(username).await?
+            .context("User not found")?;
+        self.database.get_user_groups(user.id).await
+    }
+    
+    pub async fn get_group_members(&self, group_name: &str) -> Result<Vec<User>> {
+        let group = self.database.get_group_by_name(group_name).await?
+            .context("Group not found")?;
+        self.database.get_group_members(group.id).await
+    }
+    
+    // Share with everyone in the group right now, in one transaction. This is a
+    // snapshot: members added later do not get the file. The sharer, members who
+    // own the file and members who already have it are skipped. Returns the
+    // number of new shares.
+    pub async fn share_with_group(
+        &mut self,
+        file_hash: &HashValue,
+        owner: &str,
+        group_name: &str,
+        permission: SharePermission,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<usize> {
+        let owner_user = self.database.get_user_by_username(owner).await?
+            .context("Owner not found")?;
+        let group = self.database.get_group_by_name(group_name).await?
+            .context("Group not found")?;
+        let members = self.database.get_group_members(group.id).await?;
+        if group.owner_id != owner_user.id && !members.iter().any(|m| m.id == owner_user.id) {
+            return Err(FileSharingError::PermissionDenied(
+                format!("{} is not in group {}", owner, group_name)
+            ).into());
+        }
+        
+        let (file_id, sharer_expiry) = self.shareable_file_id(file_hash, &owner_user).await?;
+        
+        let mut recipients = Vec::new();
+        for member in &members {
+            if member.id != owner_user.id && self.database.get_owned_file(file_hash, member.id).await?.is_none() {
+                recipients.push(member.id);
+            }
+        }
+        if recipients.is_empty() {
+            println!("👥 Nobody in {} to share with", group_name);
+            return Ok(0);
+        }
+        
+        let commitment_bytes = commitment::commit_bytes(self.commitment_scheme, file_hash.bytes.as_slice())?;
+        let created = self.database.create_shares(
+            file_id,
+            owner_user.id,
+            &recipients,
+            Some(&commitment_bytes),
+            self.commitment_scheme,
+            self.share_proof(file_hash).as_ref(),
+            permission,
+            self.reshare_expiry(expires_at, sharer_expiry),
+        ).await?;
+        
+        Metrics::add(&self.metrics.shares, created);
+        self.database.record_audit(Some(owner_user.id), "share_group", Some(&format!("{} -> @{}", file_hash, group_name))).await?;
+        println!("🔗 File shared: {} -> group {} ({} new)", owner, group_name, created);
+        Ok(created as usize)
+    }
+    
+    // Share with several users at once. Repeated usernames are shared once, results
+    // follow input order, and an oversized list is rejected before anything is shared.
+    pub async fn share_file_batch(
+        &mut self,
+        file_hash: &HashValue,
+        owner: &str,
+        targets: &[&str],
+        permission: SharePermission,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Vec<(String, Result<()>)>> {
+        let mut unique: Vec<&str> = Vec::new();
+        for target in targets {
+            if !unique.contains(target) {
+                unique.push(target);
+            }
+        }
+        if unique.len() > self.max_share_recipients {
+            return Err(FileSharingError::TooManyRecipients {
+                count: unique.len(),
+                max: self.max_share_recipients,
+            }.into());
+        }
+        
+        let mut results = Vec::with_capacity(unique.len());
+        for target in unique {
+            let result = self.share_file_until(file_hash, owner, target, permission, expires_at).await;
+            results.push((target.to_string(), result));
+        }
+        Ok(results)
+    }
+    
+    // Recipient-side check that the owner committed to exactly this content: the
+    // data is hashed with the shared file's algorithm and checked against the
+    // stored commitment, using the scheme it was made with
+    pub async fn verify_share_commitment(&self, share: &SharedFile, file_data: &[u8]) -> Result<bool> {
+        let file = self.database.get_file_by_id(share.file_id).await?
+            .context("Shared file not found")?;
+        let file_hash = HashValue::compute(file_data, file.hash_value()?.algo);
+        
+        Ok(share.commitment.as_deref()
+            .is_some_and(|bytes| commitment::verify_bytes(share.commitment_scheme, bytes, &file_hash.bytes)))
+    }
+    
+    // Full recipient-side check using only the share record: size and Merkle root
+    // from its proof, whole-file hash against its commitment. Shares made without
+    // a proof need verify_share_commitment instead.
+    pub fn verify_received(share: &SharedFile, data: &[u8]) -> Result<bool> {
+        let proof = share.proof.as_ref()
+            .context("Share has no proof recorded; use verify_share_commitment")?;
+        if !proof.matches(data) {
+            return Ok(false);
+        }
+        let file_hash = HashValue::compute(data, proof.hash_algo);
+        Ok(share.commitment.as_deref()
+            .is_some_and(|bytes| commitment::verify_bytes(share.commitment_scheme, bytes, &file_hash.bytes)))
+    }
+    
+    // Download as the logged-in user (see download_as)
+    pub async fn download_and_verify(&self, file_hash: &HashValue) -> Result<Vec<u8>> {
+        let reader = self.current_user.as_ref().map(|user| user.username.as_str());
+        self.download_as(file_hash, reader).await
+    }
+    
+    // Download the copy `reader` is entitled to: under a per-user dedup scope
+    // each owner has their own, so damage to one owner's copy never blocks
+    // another's. Without a reader the first stored copy is read.
+    pub async fn download_as(&self, file_hash: &HashValue, reader: Option<&str>) -> Result<Vec<u8>> {
+        let source = self.source_file(file_hash, reader).await?;
+        self.download_copy(file_hash, source).await
+    }
+    
+    async fn download_copy(&self, file_hash: &HashValue, source: Option<FileRecord>) -> Result<Vec<u8>> {
+        let owner = self.copy_owner(source.as_ref()).await?;
+        let copy = self.storage.copy_id(file_hash, owner.as_deref());
+        // Never serve content already known to be bad
+        self.check_quarantine(file_hash, copy.as_deref()).await?;
+        
+        let data = match self.storage.retrieve_copy_verified(file_hash, owner.as_deref()) {
+            Ok(data) => data,
+            Err(e) => {
+                self.record_integrity_failure(file_hash, copy.as_deref(), &e.to_string()).await?;
+                return Err(e);
+            }
+        };
+        
+        // The engine's metadata could itself be stale, so compare with the DB's copy too
+        if self.verify_db_root {
+            let file = match source {
+                Some(file) => Some(file),
+                None => self.database.get_file_by_hash(file_hash).await?,
+            };
+            if let Some(file) = file {
+                let computed = self.merkle_root_of(file_hash, owner.as_deref(), &data);
+                if computed.to_hex() != file.merkle_root {
+                    let err = FileSharingError::MerkleRootMismatch {
+                        hash: file_hash.to_string(),
+                        expected: file.merkle_root,
+                        actual: computed.to_hex(),
+                    };
+                    self.record_integrity_failure(file_hash, copy.as_deref(), &err.to_string()).await?;
+                    return Err(err.into());
+                }
+            }
+        }
+        Metrics::inc(&self.metrics.downloads);
+        println!(" File verified: {} integrity check passed", file_hash.prefix(8));
+        Ok(data)
+    }
+    
+    // Verified bytes [start, end) of the copy `reader` is entitled to, reading
+    // only the chunks they span. Quarantine and integrity failures are handled
+    // as in download_as.
+    pub async fn download_range(&self, file_hash: &HashValue, reader: Option<&str>, start: u64, end: u64) -> Result<Vec<u8>> {
+        let source = self.source_file(file_hash, reader).await?;
+        let owner = self.copy_owner(source.as_ref()).await?;
+        let copy = self.storage.copy_id(file_hash, owner.as_deref());
+        self.check_quarantine(file_hash, copy.as_deref()).await?;
+        
+        // A range alone doesn't give the Merkle root, so the chunk hashes the
+        // range is verified against are checked against the DB's root instead
+        if self.verify_db_root {
+            let file = match source {
+                Some(file) => Some(file),
+                None => self.database.get_file_by_hash(file_hash).await?,
+            };
+            if let (Some(file), Some(metadata)) = (file, self.storage.copy_metadata(file_hash, owner.as_deref())) {
+                let computed = MerkleTree::with_arity(&metadata.chunks, metadata.merkle_arity).root();
+                if computed.to_hex() != file.merkle_root {
+                    let err = FileSharingError::MerkleRootMismatch {
+                        hash: file_hash.to_string(),
+                        expected: file.merkle_root,
+                        actual: computed.to_hex(),
+                    };
+                    self.record_integrity_failure(file_hash, copy.as_deref(), &err.to_string()).await?;
+                    return Err(err.into());
+                }
+            }
+        }
+        
+        match self.storage.read_range(file_hash, owner.as_deref(), start, end) {
+            Ok(data) => {
+                Metrics::inc(&self.metrics.downloads);
+                Ok(data)
+            }
+            Err(e) => {
+                self.record_integrity_failure(file_hash, copy.as_deref(), &e.to_string()).await?;
+                Err(e)
+            }
+        }
+    }
+    
+    // The file record a read by `reader` is served from: their own, else the
+    // one shared with them. None without a reader or access.
+    async fn source_file(&self, file_hash: &HashValue, reader: Option<&str>) -> Result<Option<FileRecord>> {
+        let user = match reader {
+            Some(name) => self.database.get_user_by_username(name).await?,
+            None => None,
+        };
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        if let Some(file) = self.database.get_owned_file(file_hash, user.id).await? {
+            return Ok(Some(file));
+        }
+        match self.database.get_received_share(file_hash, user.id).await? {
+            Some(share) => self.database.get_file_by_id(share.file_id).await,
+            None => Ok(None),
+        }
+    }
+    
+    // Owner whose stored copy of `file` is read; None reads the first stored copy
+    async fn copy_owner(&self, file: Option<&FileRecord>) -> Result<Option<String>> {
+        match file {
+            Some(file) => Ok(self.database.get_user_by_id(file.owner_id).await?.map(|user| user.username)),
+            None => Ok(None),
+        }
+    }
+    
+    async fn check_quarantine(&self, file_hash: &HashValue, copy: Option<&str>) -> Result<()> {
+        if let Some(entry) = self.database.get_quarantine(file_hash, copy).await? {
+            return Err(FileSharingError::Quarantined { hash: entry.hash, reason: entry.reason }.into());
+        }
+        Ok(())
+    }
+    
+    // Cheap existence/size check: reads the DB and the in-memory index, never chunk
+    // files. None when the hash is unknown or its content is not in storage.
+    pub async fn head(&self, file_hash: &HashValue) -> Result<Option<FileHead>> {
+        if !self.storage.contains(file_hash) {
+            return Ok(None);
+        }
+        let file = match self.database.get_file_by_hash(file_hash).await? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        
+        Ok(Some(FileHead {
+            size: file.size as u64,
+            chunks: file.chunks as usize,
+            merkle_root: file.merkle_root,
+            created_at: file.created_at,
+            mime_type: file.mime_type,
+        }))
+    }
+    
+    // Whether `username` owns the file or holds an unexpired share of it
+    pub async fn can_read(&self, username: &str, file_hash: &HashValue) -> Result<bool> {
+        let user = match self.database.get_user_by_username(username).await? {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+        if self.database.get_owned_file(file_hash, user.id).await?.is_some() {
+            return Ok(true);
+        }
+        Ok(self.database.get_received_share(file_hash, user.id).await?.is_some())
+    }
+    
+    // Download plus, for a superseded version, which version is current
+    pub async fn download_with_version_info(&self, file_hash: &HashValue) -> Result<(Vec<u8>, Option<VersionInfo>)> {
+        let data = self.download_and_verify(file_hash).await?;
+        let info = self.version_info(file_hash).await?;
+        Ok((data, info))
+    }
+    
+    // The logged-in user's copy is preferred, since each owner has their own version chain
+    pub async fn version_info(&self, file_hash: &HashValue) -> Result<Option<VersionInfo>> {
+        if !self.report_newer_versions {
+            return Ok(None);
+        }
+        let owned = match &self.current_user {
+            Some(user) => self.database.get_owned_file(file_hash, user.id).await?,
+            None => None,
+        };
+        let file = match owned {
+            Some(file) => file,
+            None => match self.database.get_file_by_hash(file_hash).await? {
+                Some(file) => file,
+                None => return Ok(None),
+            },
+        };
+        
+        let newer = self.database.get_newer_versions(file.id).await?;
+        match newer.last() {
+            Some(latest) => Ok(Some(VersionInfo {
+                latest_hash: latest.hash_value()?,
+                newer_versions: newer.len(),
+            })),
+            None => Ok(None),
+        }
+    }
+    
+    // Chunk-by-chunk download for large files; each chunk is verified as it is read
+    pub async fn download_stream(&self, file_hash: &HashValue) -> Result<ChunkStream> {
+        self.check_quarantine(file_hash, self.storage.copy_id(file_hash, None).as_deref()).await?;
+        Metrics::inc(&self.metrics.downloads);
+        self.storage.stream_file(file_hash)
+    }
+    
+    // Copy a file into `writer` one verified chunk at a time, so at most a chunk
+    // (plus read-ahead) is held in memory. Returns the bytes written.
+    pub async fn export_to_writer<W: Write>(&self, file_hash: &HashValue, writer: &mut W) -> Result<u64> {
+        let mut written = 0u64;
+        for chunk in self.download_stream(file_hash).await? {
+            let chunk = chunk?;
+            writer.write_all(&chunk)?;
+            written += chunk.len() as u64;
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+    
+    // The file as a tar of its verified chunks plus a manifest, for clients
+    // that keep the chunked form in their own storage
+    pub async fn download_chunks_archive(&self, file_hash: &HashValue) -> Result<Vec<u8>> {
+        let metadata = self.storage.metadata(file_hash).cloned()
+            .context("File not found")?;
+        let chunks = self.download_stream(file_hash).await?;
+        archive::build_chunk_archive(&metadata, chunks)
+    }
+    
+    // Rebuild a file from a download_chunks_archive tar. Nothing is stored unless
+    // every chunk, the Merkle root and the whole-file hash check out.
+    pub async fn import_chunks_archive(&mut self, archive: &[u8], filename: &str, owner: &str) -> Result<FileMetadata> {
+        let (manifest, data) = archive::read_chunk_archive(archive)?;
+        println!("📦 Chunk archive verified: {} ({} chunks)", manifest.hash.prefix(8), manifest.chunks.len());
+        self.upload_file(&data, filename, owner, None, None).await
+    }
+    
+    // Check a file chunk by chunk, stopping at the first bad one
+    pub async fn verify_stream(&self, file_hash: &HashValue) -> Result<ChunkStream> {
+        self.check_quarantine(file_hash, self.storage.copy_id(file_hash, None).as_deref()).await?;
+        self.storage.verify_stream(file_hash)
+    }
+    
+    // Create a bearer link to one of the owner's files. With `bound_user`, only
+    // that user (authenticated at redemption) can use it; otherwise anyone can.
+    pub async fn create_download_link(
+        &self,
+        file_hash: &HashValue,
+        owner: &str,
+        bound_user: Option<&str>,
+        ttl: Option<Duration>,
+    ) -> Result<String> {
+        let owner_user = self.database.get_user_by_username(owner).await?
+            .context("Owner not found")?;
+        let file = self.database.get_owned_file(file_hash, owner_user.id).await?
+            .context("File not found")?;
+        let bound_user_id = match bound_user {
+            Some(name) => Some(self.database.get_user_by_username(name).await?
+                .context("Bound user not found")?.id),
+            None => None,
+        };
+        
+        let mut token = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut token);
+        let token = hex::encode(token);
+        
+        self.database.create_download_link(
+            &token,
+            file.id,
+            owner_user.id,
+            bound_user_id,
+            ttl.map(|ttl| Utc::now() + ttl),
+        ).await?;
+        
+        println!("🔗 Download link created for {}", file_hash.prefix(8));
+        Ok(token)
+    }
+    
+    // `redeemer` is the authenticated username, or None for an anonymous request
+    pub async fn redeem_download_link(&self, token: &str, redeemer: Option<&str>) -> Result<Vec<u8>> {
+        let link = self.database.get_download_link(token).await?
+            .ok_or_else(|| FileSharingError::InvalidDownloadLink("unknown token".to_string()))?;
+        
+        if link.expires_at.is_some_and(|t| t <= Utc::now()) {
+            return Err(FileSharingError::InvalidDownloadLink("link expired".to_string()).into());
+        }
+        
+        if let Some(bound_id) = link.bound_user_id {
+            let redeemer_id = match redeemer {
+                Some(name) => self.database.get_user_by_username(name).await?.map(|u| u.id),
+                None => None,
+            };
+            if redeemer_id != Some(bound_id) {
+                return Err(FileSharingError::PermissionDenied(
+                    "this link is bound to another user".to_string()
+                ).into());
+            }
+        }
+        
+        let file = self.database.get_file_by_id(link.file_id).await?
+            .context("File not found")?;
+        if file.deleted_at.is_some() {
+            return Err(FileSharingError::InvalidDownloadLink("file is in the recycle bin".to_string()).into());
+        }
+        self.download_copy(&file.hash_value()?, Some(file)).await
+    }
+    
+    // Download plus the stored content type, for callers that need to label the bytes
+    pub async fn download_with_mime(&self, file_hash: &HashValue) -> Result<(Vec<u8>, String)> {
+        let file = self.database.get_file_by_hash(file_hash).await?
+            .context("File not found")?;
+        let data = self.download_and_verify(file_hash).await?;
+        Ok((data, file.mime_type))
+    }
+    
+    // `output` may be a directory (the filename is appended), or a file path,
+    // existing or not. Missing parent directories are created. Returns the
+    // written path, or None if the target existed and the policy is Skip.
+    pub async fn download_to_path(
+        &self,
+        file_hash: &HashValue,
+        output: &Path,
+        filename: &str,
+        policy: CollisionPolicy,
+    ) -> Result<Option<PathBuf>> {
+        let target = match Self::prepare_target(output, filename, policy)? {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        
+        let data = self.download_and_verify(file_hash).await?;
+        std::fs::write(&target, data)?;
+        Ok(Some(target))
+    }
+    
+    // Write already-downloaded bytes with the same path rules as download_to_path
+    pub fn save_download(&self, data: &[u8], output: &Path, filename: &str, policy: CollisionPolicy) -> Result<Option<PathBuf>> {
+        let target = match Self::prepare_target(output, filename, policy)? {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        
+        std::fs::write(&target, data)?;
+        Ok(Some(target))
+    }
+    
+    // Final output path with parent directories created; None when policy says skip
+    fn prepare_target(output: &Path, filename: &str, policy: CollisionPolicy) -> Result<Option<PathBuf>> {
+        let requested = Self::resolve_output_path(output, filename);
+        if let Some(parent) = requested.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Cannot create directory {}", parent.display()))?;
+        }
+        
+        let target = Self::resolve_collision(&requested, policy);
+        if target.is_none() {
+            println!("⏭️  skipped existing file: {}", requested.display());
+        }
+        Ok(target)
+    }
+    
+    // A trailing separator marks a directory that doesn't exist yet
+    pub fn resolve_output_path(output: &Path, filename: &str) -> PathBuf {
+        let text = output.to_string_lossy();
+        if output.is_dir() || text.ends_with('/') || text.ends_with(std::path::MAIN_SEPARATOR) {
+            output.join(filename)
+        } else {
+            output.to_path_buf()
+        }
+    }
+    
+    fn resolve_collision(path: &Path, policy: CollisionPolicy) -> Option<PathBuf> {
+        if !path.exists() {
+            return Some(path.to_path_buf());
+        }
+        
+        match policy {
+            CollisionPolicy::Overwrite => Some(path.to_path_buf()),
+            CollisionPolicy::Skip => None,
+            CollisionPolicy::Rename => {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+                (1..)
+                    .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+                    .find(|candidate| !candidate.exists())
+            }
+        }
+    }
+    
+    // Accepts a full `algo:hex` hash or a unique hex prefix, among the files
+    // `requester` owns or has been shared
+    pub async fn resolve_file(&self, requester: &str, input: &str) -> Result<FileRecord> {
+        let user = self.database.get_user_by_username(requester).await?
+            .context("User not found")?;
+        if input.contains(':') {
+            let hash: HashValue = input.parse()?;
+            return self.database.resolve_short_hash(&hash.to_hex(), user.id).await.ok()
+                .filter(|file| file.hash_value().is_ok_and(|h| h == hash))
+                .context("File not found");
+        }
+        self.database.resolve_short_hash(input, user.id).await
+    }
+    
+    pub async fn get_user_files(&self, username: &str) -> Result<Vec<FileRecord>> {
+        self.database.get_user_files(username).await
+    }
+    
+    pub async fn query_files(&self, username: &str, query: &FileQuery) -> Result<Vec<FileRecord>> {
+        let user = self.database.get_user_by_username(username).await?
+            .context("User not found")?;
+        self.database.query_files(user.id, query).await
+    }
+    
+    // Names to show for a file list; with disambiguate_names, files sharing a
+    // filename get their short hash appended so they can be told apart
+    pub fn display_names(&self, files: &[FileRecord]) -> Vec<String> {
+        files.iter()
+            .map(|file| {
+                let repeated = files.iter().filter(|f| f.filename == file.filename).count() > 1;
+                if self.disambiguate_names && repeated {
+                    file.name_with_short_hash()
+                } else {
+                    file.filename.clone()
+                }
+            })
+            .collect()
+    }
+    
+    // Attributes belong to the owner's copy and carry over to new versions
+    pub async fn set_file_attribute(&self, file_hash: &HashValue, owner: &str, key: &str, value: &str) -> Result<()> {
+        let file = self.owned_file(file_hash, owner).await?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(FileSharingError::AttributeRejected("key must not be empty".to_string()).into());
+        }
+        
+        // Overwriting an existing key doesn't count against the limit
+        let existing = self.database.get_attributes(file.id).await?;
+        if !existing.contains_key(key) && existing.len() >= MAX_ATTRIBUTES {
+            return Err(FileSharingError::AttributeRejected(
+                format!("a file may have at most {} attributes", MAX_ATTRIBUTES)
+            ).into());
+        }
+        
+        self.database.set_attribute(file.id, key, value).await
+    }
+    
+    pub async fn remove_file_attribute(&self, file_hash: &HashValue, owner: &str, key: &str) -> Result<bool> {
+        let file = self.owned_file(file_hash, owner).await?;
+        self.database.remove_attribute(file.id, key.trim()).await
+    }
+    
+    pub async fn get_file_attributes(&self, file_hash: &HashValue, owner: &str) -> Result<HashMap<String, String>> {
+        let file = self.owned_file(file_hash, owner).await?;
+        self.database.get_attributes(file.id).await
+    }
+    
+    async fn owned_file(&self, file_hash: &HashValue, owner: &str) -> Result<FileRecord> {
+        let user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        self.database.get_owned_file(file_hash, user.id).await?
+            .context("File not found")
+    }
+    
+    pub async fn get_my_shares(&self, owner: &str) -> Result<Vec<OutgoingShare>> {
+        let user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        self.database.get_outgoing_shares(user.id).await
+    }
+    
+    pub async fn get_shared_files(&self, username: &str) -> Result<Vec<SharedFile>> {
+        self.database.get_shared_files(username).await
+    }
+    
+    // The owner's files, then optionally the shares they've made, as CSV with a
+    // header row. Reads a page at a time, so memory use doesn't grow with the
+    // listing. Returns the number of rows written, not counting the header.
+    pub async fn export_listing_csv<W: Write>(&self, owner: &str, include_shares: bool, writer: &mut W) -> Result<u64> {
+        let user = self.database.get_user_by_username(owner).await?
+            .context("User not found")?;
+        write_csv_row(writer, &LISTING_CSV_COLUMNS)?;
+        let mut rows = 0u64;
+        
+        let mut offset = 0;
+        loop {
+            let page = self.database.get_user_files_page(user.id, EXPORT_PAGE_SIZE, offset).await?;
+            for file in &page {
+                write_csv_row(writer, &[
+                    "file",
+                    &file.filename,
+                    &file.hash,
+                    &file.size.to_string(),
+                    &file.mime_type,
+                    &file.created_at.to_rfc3339(),
+                    &file.modified_at.to_rfc3339(),
+                    file.description.as_deref().unwrap_or(""),
+                    "", "", "", "",
+                ])?;
+            }
+            rows += page.len() as u64;
+            if (page.len() as i64) < EXPORT_PAGE_SIZE {
+                break;
+            }
+            offset += EXPORT_PAGE_SIZE;
+        }
+        
+        if include_shares {
+            let mut offset = 0;
+            loop {
+                let page = self.database.get_outgoing_shares_page(user.id, EXPORT_PAGE_SIZE, offset).await?;
+                for share in &page {
+                    let permission = match share.permission {
+                        SharePermission::Read => "read",
+                        SharePermission::Reshare => "reshare",
+                    };
+                    let status = match share.status {
+                        ShareStatus::Active => "active",
+                        ShareStatus::Expired => "expired",
+                    };
+                    write_csv_row(writer, &[
+                        "share",
+                        &share.filename,
+                        "", "", "",
+                        &share.shared_at.to_rfc3339(),
+                        "", "",
+                        &share.shared_with_username,
+                        permission,
+                        &share.expires_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+                        status,
+                    ])?;
+                }
+                rows += page.len() as u64;
+                if (page.len() as i64) < EXPORT_PAGE_SIZE {
+                    break;
+                }
+                offset += EXPORT_PAGE_SIZE;
+            }
+        }
+        
+        writer.flush()?;
+        Ok(rows)
+    }
+    
+    // Checks every stored copy of the file
+    pub async fn verify_file_integrity(&self, file_hash: &HashValue) -> Result<bool> {
+        let failures = self.storage.verify_files(std::slice::from_ref(file_hash), None).remove(0);
+        for (copy, e) in &failures {
+            self.record_integrity_failure(file_hash, Some(copy), &e.to_string()).await?;
+        }
+        Ok(failures.is_empty())
+    }
+    
+    // Scan the whole store and quarantine every file that fails verification.
+    // Cancellation is checked between batches of `verify_concurrency` files.
+    pub async fn verify_all(&self, cancel: &CancellationToken) -> Result<Progress<Vec<(HashValue, String)>>> {
+        self.verify_all_with_progress(cancel, |_, _| {}).await
+    }
+    
+    // Same as verify_all, reporting (checked, total) after each file. Files are
+    // verified `verify_concurrency` at a time, but results (and progress calls)
+    // are still handled in store order. A panicking callback is logged and not
+    // called again; the scan itself carries on.
+    pub async fn verify_all_with_progress(
+        &self,
+        cancel: &CancellationToken,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Progress<Vec<(HashValue, String)>>> {
+        let hashes = self.storage.hashes();
+        let total = hashes.len();
+        let mut checked = 0;
+        let mut report_progress = true;
+        let mut failures = Vec::new();
+        
+        for batch in hashes.chunks(self.verify_concurrency.max(1)) {
+            if cancel.is_cancelled() {
+                return Ok(Progress::Cancelled(failures));
+            }
+            
+            for (hash, copy_failures) in batch.iter().zip(self.storage.verify_files(batch, None)) {
+                for (copy, e) in copy_failures {
+                    self.record_integrity_failure(hash, Some(&copy), &e.to_string()).await?;
+                    failures.push((hash.clone(), e.to_string()));
+                }
+                checked += 1;
+                
+                if report_progress {
+                    let call = std::panic::catch_unwind(AssertUnwindSafe(|| progress(checked, total)));
+                    if call.is_err() {
+                        println!("⚠️  progress callback panicked; continuing without progress");
+                        report_progress = false;
+                    }
+                }
+            }
+        }
+        Ok(Progress::Complete(failures))
+    }
+    
+    // verify_all restricted to the files `username` owns in the DB, and to their
+    // own copies of them, so one account can be checked without scanning the
+    // whole store
+    pub async fn verify_user_files(&self, username: &str) -> Result<UserIntegrityReport> {
+        let mut hashes = Vec::new();
+        for file in self.get_user_files(username).await? {
+            hashes.push(file.hash_value()?);
+        }
+        let mut report = UserIntegrityReport { total: hashes.len(), ..Default::default() };
+        
+        let (present, missing): (Vec<HashValue>, Vec<HashValue>) = hashes.into_iter()
+            .partition(|hash| self.storage.contains(hash));
+        for hash in missing {
+            report.corrupt.push((hash, "content missing from storage".to_string()));
+        }
+        for batch in present.chunks(self.verify_concurrency.max(1)) {
+            for (hash, failures) in batch.iter().zip(self.storage.verify_files(batch, Some(username))) {
+                match failures.into_iter().next() {
+                    None => report.ok += 1,
+                    Some((copy, e)) => {
+                        self.record_integrity_failure(hash, Some(&copy), &e.to_string()).await?;
+                        report.corrupt.push((hash.clone(), e.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+    
+    // `copy` is the failing copy (StorageEngine::copy_id); None quarantines them all
+    async fn record_integrity_failure(&self, file_hash: &HashValue, copy: Option<&str>, reason: &str) -> Result<()> {
+        Metrics::inc(&self.metrics.integrity_failures);
+        // A hash unknown to the engine is missing, not corrupt
+        if self.storage.contains(file_hash) {
+            self.database.quarantine_file(file_hash, copy, reason).await?;
+            self.database.record_audit(None, "quarantine", Some(&file_hash.to_string())).await?;
+            println!("☣️  File quarantined: {} ({})", file_hash.prefix(8), reason);
+        }
+        Ok(())
+    }
+    
+    pub async fn reconcile(&self, cancel: &CancellationToken) -> Result<Progress<ReconcileReport>> {
+        let in_db = self.database.file_hashes().await?;
+        let mut report = ReconcileReport::default();
+        
+        for hash in &in_db {
+            if cancel.is_cancelled() {
+                return Ok(Progress::Cancelled(report));
+            }
+            if !self.storage.contains(hash) {
+                report.db_without_storage.push(hash.clone());
+            }
+        }
+        let in_db: HashSet<String> = in_db.iter().map(HashValue::to_hex).collect();
+        for hash in self.storage.hashes() {
+            if cancel.is_cancelled() {
+                return Ok(Progress::Cancelled(report));
+            }
+            if !in_db.contains(&hash.to_hex()) {
+                report.storage_without_db.push(hash);
+            }
+        }
+        Ok(Progress::Complete(report))
+    }
+    
+    // Delete the orphans found by `reconcile`: dangling rows and unreferenced content.
+    // Each hash is checked again first, so a stale report can't delete anything
+    // uploaded since it was made.
+    pub async fn clean_orphans(&mut self, requester: &str, report: &ReconcileReport) -> Result<()> {
+        self.require_admin(requester).await?;
+        
+        for hash in &report.db_without_storage {
+            if self.storage.contains(hash) {
+                println!("⏭️  {} has content again, keeping its rows", hash.prefix(8));
+                continue;
+            }
+            let rows = self.database.delete_files_by_hash(hash).await?;
+            println!("🧹 removed {} orphan row(s) for {}", rows, hash.prefix(8));
+        }
+        for hash in &report.storage_without_db {
+            if self.database.count_files_with_hash(hash).await? > 0 {
+                println!("⏭️  {} is referenced again, keeping its content", hash.prefix(8));
+                continue;
+            }
+            self.storage.delete_file(hash)?;
+            println!("🧹 removed orphan content {}", hash.prefix(8));
+        }
+        Ok(())
+    }
+    
+    // Reclaim space from chunk files no stored file refers to. Content still
+    // indexed by the engine is kept, whichever owners reference it.
+    pub async fn compact_storage(&mut self, requester: &str) -> Result<CompactReport> {
+        self.require_admin(requester).await?;
+        self.storage.compact()
+    }
+    
+    pub async fn list_quarantined(&self) -> Result<Vec<QuarantinedFile>> {
+        self.database.list_quarantined().await
+    }
+    
+    // Lift the quarantine once every copy of the file verifies cleanly again (e.g. after repair)
+    pub async fn clear_quarantine(&self, requester: &str, file_hash: &HashValue) -> Result<bool> {
+        self.require_admin(requester).await?;
+        
+        if let Some((_, e)) = self.storage.verify_files(std::slice::from_ref(file_hash), None).remove(0).into_iter().next() {
+            return Err(e.context("File still fails verification"));
+        }
+        self.database.clear_quarantine(file_hash).await
+    }
+    
+    pub fn metrics_text(&self) -> String {
+        let stats = &self.storage.dedup_stats;
+        self.metrics.render(&[
+            ("sfs_files", "Unique files held by the storage engine", stats.unique_files as u64),
+            ("sfs_stored_bytes", "Bytes accepted for storage, including deduplicated uploads", stats.total_bytes),
+        ])
+    }
+    
+    pub fn chunk_status(&self, file_hash: &HashValue) -> Result<Vec<ChunkStatus>> {
+        self.storage.chunk_status(file_hash)
+    }
+    
+    // Fix a single corrupt chunk from a good copy of its bytes. Returns whether the
+    // whole file verifies afterwards, lifting its quarantine if so.
+    pub async fn reupload_chunk(&self, requester: &str, file_hash: &HashValue, chunk_index: usize, chunk_data: &[u8]) -> Result<bool> {
+        self.require_admin(requester).await?;
+        
+        self.storage.replace_chunk(file_hash, chunk_index, chunk_data)?;
+        
+        if !self.storage.verify_files(std::slice::from_ref(file_hash), None).remove(0).is_empty() {
+            return Ok(false);
+        }
+        self.database.clear_quarantine(file_hash).await?;
+        Ok(true)
+    }
+    
+    pub async fn repair_metadata(&mut self, requester: &str, file_hash: &HashValue) -> Result<FileMetadata> {
+        self.require_admin(requester).await?;
+        
+        let file = self.database.get_file_by_hash(file_hash).await?
+            .context("File not found")?;
+        let owner = self.database.get_user_by_id(file.owner_id).await?
+            .context("Owner not found")?;
+        
+        self.storage.repair_metadata(file_hash, &file.filename, &owner.username, &file.merkle_root)
+    }
+    
+    // Read the verified bytes once and digest them under every algorithm,
+    // for comparing against systems that expect a particular one
+    pub async fn multi_hash(&self, file_hash: &HashValue) -> Result<MultiHash> {
+        self.check_quarantine(file_hash, self.storage.copy_id(file_hash, None).as_deref()).await?;
+        let data = self.storage.retrieve_file_verified(file_hash)?;
+        
+        Ok(MultiHash {
+            sha256: HashValue::compute(&data, HashAlgo::Sha256),
+            sha512: HashValue::compute(&data, HashAlgo::Sha512),
+            sha3_256: HashValue::compute(&data, HashAlgo::Sha3_256),
+            sha3_512: HashValue::compute(&data, HashAlgo::Sha3_512),
+            blake3: HashValue::compute(&data, HashAlgo::Blake3),
+        })
+    }
+    
+    // Check a local copy (e.g. a finished download) against an expected hash,
+    // and against the stored Merkle root when the file is known to this engine
+    pub async fn verify_local_file(&self, path: &Path, expected_hash: &HashValue) -> Result<bool> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        
+        if HashValue::compute(&data, expected_hash.algo) != *expected_hash {
+            return Ok(false);
+        }
+        
+        if let Some(metadata) = self.storage.metadata(expected_hash) {
+            if self.merkle_root_of(expected_hash, None, &data) != metadata.merkle_root {
+                return Ok(false);
+            }
+        }
+        
+        Ok(true)
+    }
+    
+    // Merkle root of `data`, chunked the way the copy `owner` reads was
+    fn merkle_root_of(&self, file_hash: &HashValue, owner: Option<&str>, data: &[u8]) -> HashValue {
+        let (pieces, algo, arity) = match self.storage.copy_metadata(file_hash, owner) {
+            Some(metadata) => (metadata.split(data, CHUNK_SIZE), metadata.chunk_algo(), metadata.merkle_arity),
+            None => (data.chunks(self.storage.chunk_size.max(1)).collect(), self.storage.chunk_algo, DEFAULT_ARITY),
+        };
+        let chunks: Vec<HashValue> = pieces.into_iter()
+            .map(|chunk| HashValue::compute(chunk, algo))
+            .collect();
+        MerkleTree::with_arity(&chunks, arity).root()
+    }
+    
+    // Rebuild the Merkle root from verified storage and write it back to the DB
+    // if the recorded one is wrong. Returns whether anything was corrected.
+    pub async fn recompute_merkle_root(&self, requester: &str, file_hash: &HashValue) -> Result<bool> {
+        self.require_admin(requester).await?;
+        
+        let data = self.storage.retrieve_file_verified(file_hash)?;
+        let root = self.merkle_root_of(file_hash, None, &data);
+        let corrected = self.database.set_merkle_root(file_hash, &root).await?;
+        if corrected > 0 {
+            println!("🔧 Corrected merkle root of {} on {} record(s)", file_hash.prefix(8), corrected);
+        }
+        Ok(corrected > 0)
+    }
+    
+    pub async fn list_users(&self, requester: &str, limit: i64, offset: i64) -> Result<Vec<UserSummary>> {
+        self.require_admin(requester).await?;
+        self.database.list_users(limit, offset).await
+    }
+    
+    // Operator bootstrap: no requester is checked, so only call this from startup
+    // configuration (SFS_ADMIN_USER in the CLI), never from a user-facing action
+    pub async fn grant_admin(&self, username: &str) -> Result<User> {
+        let mut user = self.database.get_user_by_username(username).await?
+            .context("User not found")?;
+        if !user.is_admin {
+            self.database.set_admin(user.id, true).await?;
+            self.database.record_audit(None, "grant_admin", Some(&user.username)).await?;
+            user.is_admin = true;
+            println!("🛡️  Administrator granted: {}", user.username);
+        }
+        Ok(user)
+    }
+    
+    async fn require_admin(&self, username: &str) -> Result<User> {
+        let user = self.database.get_user_by_username(username).await?
+            .context("User not found")?;
+        if !user.is_admin {
+            return Err(FileSharingError::PermissionDenied(
+                format!("{} is not an administrator", username)
+            ).into());
+        }
+        Ok(user)
+    }
+    
+    pub async fn query_audit(&self, requester: &str, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        self.require_admin(requester).await?;
+        self.database.query_audit(filter).await
+    }
+    
+    pub async fn user_activity(&self, username: &str) -> Result<UserActivity> {
+        self.database.user_activity(username).await
+    }
+    
+    pub async fn health_check(&self) -> HealthReport {
+        HealthReport {
+            db_ok: self.database.ping().await,
+            storage_writable: self.storage.is_writable(),
+            storage_dir_free_bytes: self.storage.free_space().ok(),
+            in_memory_fallback: self.database.is_in_memory(),
+        }
+    }
+    
+    // Smoke test of the full pipeline on a scratch instance under the system temp
+    // directory, configured like this one, so real users and files are untouched
+    pub async fn self_test(&self) -> SelfTestReport {
+        let mut nonce = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let root = std::env::temp_dir().join(format!("sfs-selftest-{}", hex::encode(nonce)));
+        
+        let mut report = SelfTestReport::default();
+        self.run_self_test(&root, &mut report).await;
+        
+        let cleanup = std::fs::remove_dir_all(&root)
+            .with_context(|| format!("Cannot remove {}", root.display()));
+        report.record("clean up", cleanup);
+        report
+    }
+    
+    async fn run_self_test(&self, root: &Path, report: &mut SelfTestReport) -> Option<()> {
+        const OWNER: &str = "selftest-owner";
+        const RECIPIENT: &str = "selftest-recipient";
+        let mut secret = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let password = hex::encode(secret);
+        
+        let mut scratch = report.record("start scratch instance", self.scratch_instance(root).await)?;
+        
+        report.record("register users", async {
+            scratch.register_user(OWNER, &password, None).await?;
+            scratch.register_user(RECIPIENT, &password, None).await
+        }.await)?;
+        
+        let mut data = vec![0u8; 256 * 1024];
+        rand::thread_rng().fill_bytes(&mut data);
+        let metadata = report.record("upload",
+            scratch.upload_file(&data, "selftest.bin", OWNER, None, None).await)?;
+        
+        report.record("download and verify", async {
+            if scratch.download_and_verify(&metadata.hash).await? != data {
+                anyhow::bail!("downloaded bytes differ from the upload");
+            }
+            Ok(())
+        }.await)?;
+        
+        report.record("share",
+            scratch.share_file(&metadata.hash, OWNER, RECIPIENT, SharePermission::Read).await)?;
+        
+        report.record("read as recipient", async {
+            let share = scratch.get_shared_files(RECIPIENT).await?.into_iter().next()
+                .context("share not visible to the recipient")?;
+            let received = scratch.download_and_verify(&metadata.hash).await?;
+            if received != data {
+                anyhow::bail!("recipient received different bytes");
+            }
+            if !scratch.verify_share_commitment(&share, &received).await? {
+                anyhow::bail!("share commitment does not match the content");
+            }
+            Ok(())
+        }.await)
+    }
+    
+    async fn scratch_instance(&self, root: &Path) -> Result<FileSharingService> {
+        let mut scratch = FileSharingService::open(ServiceConfig {
+            data_dir: root.to_path_buf(),
+            chunk_size: self.storage.chunk_size,
+            hash_algo: self.storage.hash_algo,
+            chunk_algo: self.storage.chunk_algo,
+            allowed_hash_algos: self.allowed_hash_algos.clone(),
+            commitment_scheme: self.commitment_scheme,
+            owner_dirs: self.storage.owner_dirs,
+            dedup_scope: self.storage.dedup_scope,
+            ..ServiceConfig::default()
+        }).await?;
+        
+        scratch.storage.merkle_arity = self.storage.merkle_arity;
+        scratch.storage.min_free_space = self.storage.min_free_space;
+        scratch.verify_db_root = self.verify_db_root;
+        Ok(scratch)
+    }
+    
+    pub async fn hash_algo_report(&self) -> Result<HashAlgoReport> {
+        let breakdown = self.database.files_by_hash_algo().await?;
+        let weak: Vec<HashAlgo> = breakdown.iter()
+            .map(|(algo, _)| *algo)
+            .filter(|algo| algo.strength_bits() < self.min_hash_strength)
+            .collect();
+        let below_minimum = self.database.get_files_with_hash_algo(&weak).await?;
+        
+        Ok(HashAlgoReport { breakdown, below_minimum })
+    }
+    
+    // Size of the dedicated chunk-hashing pool (None = share rayon's global pool)
+    pub fn set_hash_threads(&mut self, threads: Option<usize>) -> Result<()> {
+        self.storage.set_hash_threads(threads)
+    }
+    
+    // Counters kept by the storage engine since startup, as opposed to the
+    // database-wide totals in get_system_stats
+    pub fn storage_stats(&self) -> DedupStats {
+        self.storage.dedup_stats.clone()
+    }
+    
+    pub async fn get_system_stats(&self) -> Result<SystemStats> {
+        let mut stats = self.database.get_system_stats().await?;
+        stats.bloom_fp_rate = self.authenticator.bloom.false_positive_rate();
+        Ok(stats)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use tempfile::TempDir;
+    
+    async fn open_service() -> (TempDir, FileSharingService) {
+        let dir = TempDir::new().unwrap();
+        let mut service = FileSharingService::open(ServiceConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..ServiceConfig::default()
+        }).await.unwrap();
+        service.storage.min_free_space = 0;
+        (dir, service)
+    }
+    
+    // Accounts made straight in the database skip Argon2 for tests that never log in
+    async fn add_user(service: &FileSharingService, username: &str) -> User {
+        service.database.create_user(username, "unused", None).await.unwrap()
+    }
+    
+    async fn upload(service: &mut FileSharingService, owner: &str, data: &[u8]) -> HashValue {
+        service.upload_file(data, "file.txt", owner, None, None).await.unwrap().hash
+    }
+    
+    // A second connection to the service's database file, making every later
+    // insert into `files` fail
+    async fn fail_file_inserts(dir: &TempDir) {
+        use sqlx::ConnectOptions;
+        let mut conn = sqlx::sqlite::SqliteConnectOptions::new()
+            .filename(dir.path().join("secure_files.db"))
+            .connect().await.unwrap();
+        sqlx::query("CREATE TRIGGER fail_file_insert BEFORE INSERT ON files BEGIN SELECT RAISE(ABORT, 'forced failure'); END")
+            .execute(&mut conn).await.unwrap();
+    }
+    
+    // Same-length damage to one chunk of a file stored in the shared storage directory
+    fn corrupt_chunk(dir: &TempDir, hash: &HashValue, index: usize) {
+        let path = dir.path().join("storage").join(format!("{}_{}.chunk", hash.to_hex(), index));
+        let mut data = std::fs::read(&path).unwrap();
+        data[0] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+    }
+    
+    fn chunk_files(dir: &Path) -> usize {
+        std::fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| match path.is_dir() {
+                true => chunk_files(&path),
+                false => path.extension().is_some_and(|ext| ext == "chunk") as usize,
+            })
+            .sum()
+    }
+    
+    fn stored_files(dir: &Path) -> usize {
+        std::fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| if path.is_dir() { stored_files(&path) } else { 1 })
+            .sum()
+    }
+    
+    struct PatternScanner(&'static [u8]);
+    
+    #[async_trait]
+    impl ContentScanner for PatternScanner {
+        async fn scan(&self, data: &[u8]) -> ScanResult {
+            if data.windows(self.0.len()).any(|window| window == self.0) {
+                ScanResult::Infected("test signature".to_string())
+            } else {
+                ScanResult::Clean
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn infected_upload_is_rejected_and_nothing_persisted() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        service.scanner = Box::new(PatternScanner(b"EVIL"));
+        let files_before = stored_files(&dir.path().join("storage"));
+        
+        let err = service.upload_file(b"harmless EVIL payload", "bad.bin", "alice", None, None).await.unwrap_err();
+        match err.downcast_ref::<FileSharingError>() {
+            Some(FileSharingError::ContentRejected { reason }) => assert_eq!(reason, "test signature"),
+            other => panic!("expected ContentRejected, got {:?}", other),
+        }
+        
+        assert!(service.storage.hashes().is_empty());
+        assert_eq!(stored_files(&dir.path().join("storage")), files_before);
+        assert!(service.get_user_files("alice").await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn clean_upload_passes_the_scanner() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        service.scanner = Box::new(PatternScanner(b"EVIL"));
+        
+        let metadata = service.upload_file(b"perfectly fine", "good.txt", "alice", None, None).await.unwrap();
+        assert_eq!(service.storage.hashes(), vec![metadata.hash]);
+        assert_eq!(service.get_user_files("alice").await.unwrap().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn change_password_with_correct_old_password() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("alice", "old secret", None).await.unwrap();
+        
+        service.change_password("alice", "old secret", "new secret").await.unwrap();
+        assert!(service.login("alice", "old secret").await.unwrap().is_none());
+        assert!(service.login("alice", "new secret").await.unwrap().is_some());
+    }
+    
+    #[tokio::test]
+    async fn change_password_rejects_wrong_old_password() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("alice", "old secret", None).await.unwrap();
+        
+        assert!(service.change_password("alice", "guess", "new secret").await.is_err());
+        assert!(service.login("alice", "old secret").await.unwrap().is_some());
+    }
+    
+    #[tokio::test]
+    async fn change_password_ends_the_session() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("alice", "old secret", None).await.unwrap();
+        service.login("alice", "old secret").await.unwrap().unwrap();
+        assert!(service.current_user.is_some());
+        
+        service.change_password("alice", "old secret", "new secret").await.unwrap();
+        assert!(service.current_user.is_none());
+    }
+    
+    #[tokio::test]
+    async fn read_only_recipient_cannot_reshare() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"shared content").await;
+        service.share_file(&hash, "alice", "bob", SharePermission::Read).await.unwrap();
+        
+        let err = service.share_file(&hash, "bob", "carol", SharePermission::Read).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        assert!(service.database.get_shared_files("carol").await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn reshare_recipient_can_reshare() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"shared content").await;
+        service.share_file(&hash, "alice", "bob", SharePermission::Reshare).await.unwrap();
+        
+        service.share_file(&hash, "bob", "carol", SharePermission::Read).await.unwrap();
+        let received = service.database.get_shared_files("carol").await.unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].shared_by, "bob");
+        assert_eq!(received[0].permission, SharePermission::Read);
+    }
+    
+    #[tokio::test]
+    async fn metrics_count_operations() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        add_user(&service, "carol").await;
+        let hash = upload(&mut service, "alice", b"metered").await;
+        upload(&mut service, "bob", b"metered").await;
+        service.share_file(&hash, "alice", "carol", SharePermission::Read).await.unwrap();
+        upload(&mut service, "alice", b"second file").await;
+        service.download_and_verify(&hash).await.unwrap();
+        
+        let text = service.metrics_text();
+        assert!(text.contains("# TYPE sfs_uploads_total counter\n"));
+        assert!(text.contains("sfs_uploads_total 3\n"));
+        assert!(text.contains("sfs_dedup_hits_total 1\n"));
+        assert!(text.contains("sfs_downloads_total 1\n"));
+        assert!(text.contains("sfs_shares_total 1\n"));
+        assert!(text.contains("sfs_files 2\n"));
+    }
+    
+    #[tokio::test]
+    async fn same_filename_from_two_users_registers_separately() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        service.upload_file(b"alice's report", "report.txt", "alice", None, None).await.unwrap();
+        service.upload_file(b"bob's report", "report.txt", "bob", None, None).await.unwrap();
+        
+        assert!(service.authenticator.verify_bytes("alice/report.txt", b"alice's report").unwrap());
+        assert!(service.authenticator.verify_bytes("bob/report.txt", b"bob's report").unwrap());
+        assert_eq!(stored_files(&dir.path().join("watch")), 0);
+    }
+    
+    #[tokio::test]
+    async fn mime_type_is_stored_and_returned_on_download() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        let png_hash = service.upload_file(&png, "renamed.txt", "alice", None, None).await.unwrap().hash;
+        let text_hash = service.upload_file(b"hello", "hello.txt", "alice", None, None).await.unwrap().hash;
+        
+        let (data, mime) = service.download_with_mime(&png_hash).await.unwrap();
+        assert_eq!(data, png);
+        assert_eq!(mime, "image/png");
+        assert_eq!(service.download_with_mime(&text_hash).await.unwrap().1, "text/plain");
+        
+        let files = service.get_user_files("alice").await.unwrap();
+        let record = files.iter().find(|f| f.filename == "renamed.txt").unwrap();
+        assert_eq!(record.mime_type, "image/png");
+    }
+    
+    #[tokio::test]
+    async fn failed_db_insert_leaves_no_orphan_chunks() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        fail_file_inserts(&dir).await;
+        
+        let err = service.upload_file(b"never recorded", "f.txt", "alice", None, None).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("forced failure"));
+        assert_eq!(chunk_files(&dir.path().join("storage")), 0);
+        assert!(service.storage.hashes().is_empty());
+        assert!(service.authenticator.verify_bytes("alice/f.txt", b"never recorded").is_err());
+    }
+    
+    #[tokio::test]
+    async fn rollback_keeps_chunks_another_owner_uses() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        let hash = upload(&mut service, "bob", b"shared bytes").await;
+        let chunks_before = chunk_files(&dir.path().join("storage"));
+        fail_file_inserts(&dir).await;
+        
+        assert!(service.upload_file(b"shared bytes", "f.txt", "alice", None, None).await.is_err());
+        assert_eq!(chunk_files(&dir.path().join("storage")), chunks_before);
+        assert_eq!(service.download_and_verify(&hash).await.unwrap(), b"shared bytes");
+    }
+    
+    #[tokio::test]
+    async fn download_twice_under_each_collision_policy() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let hash = upload(&mut service, "alice", b"version one").await;
+        let out = dir.path().join("out");
+        let first = out.join("report.txt");
+        
+        for (policy, second) in [
+            (CollisionPolicy::Rename, Some(out.join("report (1).txt"))),
+            (CollisionPolicy::Skip, None),
+            (CollisionPolicy::Overwrite, Some(first.clone())),
+        ] {
+            let _ = std::fs::remove_dir_all(&out);
+            std::fs::create_dir_all(&out).unwrap();
+            let written = service.download_to_path(&hash, &out, "report.txt", policy).await.unwrap();
+            assert_eq!(written, Some(first.clone()));
+            std::fs::write(&first, b"edited locally").unwrap();
+            
+            let written = service.download_to_path(&hash, &out, "report.txt", policy).await.unwrap();
+            assert_eq!(written, second, "{:?}", policy);
+            let expected_first: &[u8] = if policy == CollisionPolicy::Overwrite { b"version one" } else { b"edited locally" };
+            assert_eq!(std::fs::read(&first).unwrap(), expected_first);
+        }
+        
+        let third = service.download_to_path(&hash, &out, "report.txt", CollisionPolicy::Rename).await.unwrap();
+        assert_eq!(third, Some(out.join("report (1).txt")));
+    }
+    
+    #[tokio::test]
+    async fn verify_local_file_matches_only_an_exact_copy() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let original = b"downloaded content to check".to_vec();
+        let hash = upload(&mut service, "alice", &original).await;
+        
+        let copy = dir.path().join("copy.bin");
+        std::fs::write(&copy, &original).unwrap();
+        assert!(service.verify_local_file(&copy, &hash).await.unwrap());
+        
+        let mut damaged = original.clone();
+        damaged[5] ^= 0x01;
+        std::fs::write(&copy, &damaged).unwrap();
+        assert!(!service.verify_local_file(&copy, &hash).await.unwrap());
+    }
+    
+    #[tokio::test]
+    async fn new_accounts_are_not_admins() {
+        let (_dir, mut service) = open_service().await;
+        let first = service.register_user("first", "a password", None).await.unwrap();
+        assert!(!first.is_admin);
+        assert!(service.list_users("first", 10, 0).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn list_users_is_admin_only_and_counts_files() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "admin").await;
+        add_user(&service, "alice").await;
+        service.grant_admin("admin").await.unwrap();
+        upload(&mut service, "alice", b"one").await;
+        upload(&mut service, "alice", b"three").await;
+        
+        let err = service.list_users("alice", 10, 0).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        
+        let users = service.list_users("admin", 10, 0).await.unwrap();
+        let alice = users.iter().find(|u| u.username == "alice").unwrap();
+        assert_eq!((alice.file_count, alice.total_bytes), (2, 8));
+        assert!(users.iter().find(|u| u.username == "admin").unwrap().is_admin);
+        
+        let json = serde_json::to_string(&users).unwrap();
+        assert!(!json.contains("password"));
+        assert!(!json.contains("unused"));
+    }
+    
+    #[tokio::test]
+    async fn corrupt_file_is_quarantined_until_cleared() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "admin").await;
+        add_user(&service, "alice").await;
+        service.grant_admin("admin").await.unwrap();
+        let data = b"will be damaged".to_vec();
+        let hash = upload(&mut service, "alice", &data).await;
+        corrupt_chunk(&dir, &hash, 0);
+        
+        assert!(service.download_and_verify(&hash).await.is_err());
+        let quarantined = service.list_quarantined().await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].hash_value().unwrap(), hash);
+        let audit = service.database.query_audit(&AuditFilter { action: Some("quarantine".to_string()), ..Default::default() }).await.unwrap();
+        assert_eq!(audit.len(), 1);
+        
+        // Refused from quarantine, even once the bytes are good again, until cleared
+        corrupt_chunk(&dir, &hash, 0);
+        let err = service.download_and_verify(&hash).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::Quarantined { .. })));
+        
+        let err = service.clear_quarantine("alice", &hash).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        assert!(service.clear_quarantine("admin", &hash).await.unwrap());
+        assert_eq!(service.download_and_verify(&hash).await.unwrap(), data);
+    }
+    
+    #[tokio::test]
+    async fn quarantine_keeps_the_hash_algorithm() {
+        let (_dir, service) = open_service().await;
+        let hash = HashValue::compute(b"x", HashAlgo::Blake3);
+        service.database.quarantine_file(&hash, None, "test").await.unwrap();
+        let entry = service.database.get_quarantine(&hash, None).await.unwrap().unwrap();
+        assert_eq!(entry.hash_algo, "blake3");
+        assert_eq!(entry.hash_value().unwrap(), hash);
+    }
+    
+    #[tokio::test]
+    async fn update_keeps_created_at_and_advances_modified_at() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let original = service.upload_file(b"first draft", "doc.txt", "alice", None, None).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        
+        let updated = service.update_file(&original.hash, b"second draft", "alice").await.unwrap();
+        assert_eq!(updated.created_at, original.created_at);
+        assert!(updated.modified_at > original.modified_at);
+        
+        let alice = service.database.get_user_by_username("alice").await.unwrap().unwrap();
+        let record = service.database.get_owned_file(&updated.hash, alice.id).await.unwrap().unwrap();
+        assert_eq!(record.created_at, original.created_at);
+        assert_eq!(record.modified_at, updated.modified_at);
+    }
+    
+    #[tokio::test]
+    async fn resolve_file_hides_other_users_files() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        let hash = upload(&mut service, "alice", b"private to alice").await;
+        
+        assert_eq!(service.resolve_file("alice", &hash.to_string()).await.unwrap().hash, hash.to_hex());
+        assert!(service.resolve_file("bob", &hash.to_string()).await.is_err());
+        assert!(service.resolve_file("bob", &hash.prefix(4)).await.is_err());
+        
+        let other_algo = HashValue::from_bytes(HashAlgo::Blake3, hash.bytes.clone()).unwrap();
+        assert!(service.resolve_file("alice", &other_algo.to_string()).await.is_err());
+        
+        service.share_file(&hash, "alice", "bob", SharePermission::Read).await.unwrap();
+        assert_eq!(service.resolve_file("bob", &hash.to_string()).await.unwrap().hash, hash.to_hex());
+    }
+    
+    #[tokio::test]
+    async fn cannot_share_with_self_or_an_owner() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        let hash = upload(&mut service, "alice", b"common file").await;
+        upload(&mut service, "bob", b"common file").await;
+        
+        let err = service.share_file(&hash, "alice", "alice", SharePermission::Read).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::CannotShareWithSelf)));
+        let err = service.share_file(&hash, "alice", "ALICE", SharePermission::Read).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::CannotShareWithSelf)));
+        
+        let err = service.share_file(&hash, "alice", "bob", SharePermission::Read).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::TargetAlreadyOwns { .. })));
+        assert!(service.database.get_shared_files("alice").await.unwrap().is_empty());
+        assert!(service.database.get_shared_files("bob").await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn download_fails_when_db_merkle_root_disagrees() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let hash = upload(&mut service, "alice", b"cross-checked").await;
+        let bogus = HashValue::compute(b"not the root", HashAlgo::Sha256);
+        service.database.set_merkle_root(&hash, &bogus).await.unwrap();
+        
+        service.verify_db_root = false;
+        assert_eq!(service.download_and_verify(&hash).await.unwrap(), b"cross-checked");
+        
+        service.verify_db_root = true;
+        let err = service.download_and_verify(&hash).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::MerkleRootMismatch { .. })));
+    }
+    
+    #[tokio::test]
+    async fn repeated_idempotency_key_returns_the_original_upload() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let first = service.upload_file(b"retried body", "a.txt", "alice", Some("desc"), Some("key-1")).await.unwrap();
+        let again = service.upload_file(b"retried body", "a.txt", "alice", Some("desc"), Some("key-1")).await.unwrap();
+        
+        assert_eq!(again.hash, first.hash);
+        assert_eq!(again.path, first.path);
+        assert_eq!(again.created_at, first.created_at);
+        assert_eq!(again.modified_at, first.modified_at);
+        assert_eq!(service.get_user_files("alice").await.unwrap().len(), 1);
+        assert_eq!(service.metrics_text().lines().find(|l| l.starts_with("sfs_uploads_total")), Some("sfs_uploads_total 1"));
+    }
+    
+    #[tokio::test]
+    async fn reconcile_reports_each_kind_of_orphan() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "admin").await;
+        add_user(&service, "alice").await;
+        service.grant_admin("admin").await.unwrap();
+        let row_only = upload(&mut service, "alice", b"content goes missing").await;
+        let content_only = upload(&mut service, "alice", b"row goes missing").await;
+        let intact = upload(&mut service, "alice", b"left alone").await;
+        service.storage.delete_file(&row_only).unwrap();
+        service.database.delete_files_by_hash(&content_only).await.unwrap();
+        
+        let report = service.reconcile(&CancellationToken::new()).await.unwrap().into_inner();
+        assert_eq!(report.db_without_storage, vec![row_only.clone()]);
+        assert_eq!(report.storage_without_db, vec![content_only.clone()]);
+        
+        let err = service.clean_orphans("alice", &report).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        service.clean_orphans("admin", &report).await.unwrap();
+        
+        assert!(service.reconcile(&CancellationToken::new()).await.unwrap().into_inner().is_clean());
+        assert_eq!(service.download_and_verify(&intact).await.unwrap(), b"left alone");
+    }
+    
+    #[tokio::test]
+    async fn clean_orphans_rechecks_a_stale_report() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "admin").await;
+        add_user(&service, "alice").await;
+        service.grant_admin("admin").await.unwrap();
+        let hash = upload(&mut service, "alice", b"uploaded after the report").await;
+        
+        // Made up, or made before this upload: claims both halves are orphaned
+        let report = ReconcileReport {
+            db_without_storage: vec![hash.clone()],
+            storage_without_db: vec![hash.clone()],
+        };
+        service.clean_orphans("admin", &report).await.unwrap();
+        
+        assert_eq!(service.get_user_files("alice").await.unwrap().len(), 1);
+        assert_eq!(service.download_and_verify(&hash).await.unwrap(), b"uploaded after the report");
+    }
+    
+    #[tokio::test]
+    async fn storage_repairs_are_admin_only() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let hash = upload(&mut service, "alice", b"owner is not an admin").await;
+        
+        let err = service.reupload_chunk("alice", &hash, 0, b"owner is not an admin").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        let err = service.repair_metadata("alice", &hash).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+    }
+    
+    #[tokio::test]
+    async fn batch_share_dedups_targets_in_order() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"for the team").await;
+        
+        let results = service.share_file_batch(&hash, "alice", &["carol", "bob", "carol", "bob"], SharePermission::Read, None).await.unwrap();
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["carol", "bob"]);
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(service.get_shared_files("bob").await.unwrap().len(), 1);
+        assert_eq!(service.get_shared_files("carol").await.unwrap().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn batch_share_over_the_cap_shares_nothing() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"for the team").await;
+        service.max_share_recipients = 1;
+        
+        let err = service.share_file_batch(&hash, "alice", &["bob", "carol"], SharePermission::Read, None).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::TooManyRecipients { count: 2, max: 1 })));
+        assert!(service.get_shared_files("bob").await.unwrap().is_empty());
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn concurrent_registration_of_one_name_has_one_winner() {
+        let (dir, mut first) = open_service().await;
+        let mut second = FileSharingService::open(ServiceConfig {
+            data_dir: dir.path().to_path_buf(),
+            ..ServiceConfig::default()
+        }).await.unwrap();
+        
+        let (a, b) = tokio::join!(
+            first.register_user("carol", "a password", None),
+            second.register_user("carol", "a password", None),
+        );
+        let (won, lost) = match (a, b) {
+            (Ok(user), Err(e)) | (Err(e), Ok(user)) => (user, e),
+            (a, b) => panic!("expected one winner, got {:?} and {:?}", a.is_ok(), b.is_ok()),
+        };
+        assert_eq!(won.username, "carol");
+        assert!(matches!(lost.downcast_ref(), Some(FileSharingError::UsernameTaken(name)) if name == "carol"));
+    }
+    
+    #[tokio::test]
+    async fn user_activity_aggregates_uploads_and_shares() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("alice", "a password", None).await.unwrap();
+        add_user(&service, "bob").await;
+        
+        let fresh = service.user_activity("alice").await.unwrap();
+        assert_eq!((fresh.file_count, fresh.total_bytes, fresh.share_count), (0, 0, 0));
+        assert!(fresh.last_login.is_none() && fresh.last_upload.is_none());
+        
+        service.login("alice", "a password").await.unwrap().unwrap();
+        let first = upload(&mut service, "alice", b"twelve bytes").await;
+        let second = upload(&mut service, "alice", b"three").await;
+        service.share_file(&first, "alice", "bob", SharePermission::Read).await.unwrap();
+        service.share_file(&second, "alice", "bob", SharePermission::Read).await.unwrap();
+        
+        let activity = service.user_activity("alice").await.unwrap();
+        assert_eq!((activity.file_count, activity.total_bytes, activity.share_count), (2, 17, 2));
+        assert!(activity.last_login.is_some());
+        let latest = service.get_user_files("alice").await.unwrap().into_iter().map(|f| f.created_at).max();
+        assert_eq!(activity.last_upload, latest);
+        
+        assert_eq!(service.user_activity("bob").await.unwrap().share_count, 0);
+        assert!(service.user_activity("nobody").await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn same_filename_uploads_stay_distinct() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let first = service.upload_file(b"first report", "report.pdf", "alice", None, None).await.unwrap();
+        let second = service.upload_file(b"second report", "report.pdf", "alice", None, None).await.unwrap();
+        
+        assert_eq!(service.download_and_verify(&first.hash).await.unwrap(), b"first report");
+        assert_eq!(service.download_and_verify(&second.hash).await.unwrap(), b"second report");
+        assert_eq!(first.path, PathBuf::from("report.pdf"));
+        assert_eq!(second.path, PathBuf::from("report.pdf"));
+        
+        let files = service.get_user_files("alice").await.unwrap();
+        let names = service.display_names(&files);
+        assert_eq!(names.len(), 2);
+        assert_ne!(names[0], names[1]);
+        for (name, file) in names.iter().zip(&files) {
+            assert_eq!(*name, format!("report ({}).pdf", &file.hash[..8]));
+        }
+        
+        service.disambiguate_names = false;
+        assert_eq!(service.display_names(&files), ["report.pdf", "report.pdf"]);
+    }
+    
+    // Cancels the token once it has passed `after` uploads, like a Ctrl-C mid-run
+    struct CancelAfter {
+        token: CancellationToken,
+        after: usize,
+        seen: std::sync::atomic::AtomicUsize,
+    }
+    
+    #[async_trait]
+    impl ContentScanner for CancelAfter {
+        async fn scan(&self, _data: &[u8]) -> ScanResult {
+            if self.seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1 >= self.after {
+                self.token.cancel();
+            }
+            ScanResult::Clean
+        }
+    }
+    
+    #[tokio::test]
+    async fn cancelled_directory_upload_keeps_finished_files() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let source = dir.path().join("source");
+        std::fs::create_dir(&source).unwrap();
+        for i in 0..4 {
+            std::fs::write(source.join(format!("{}.txt", i)), format!("file number {}", i)).unwrap();
+        }
+        let cancel = CancellationToken::new();
+        service.scanner = Box::new(CancelAfter { token: cancel.clone(), after: 2, seen: Default::default() });
+        
+        let progress = service.upload_directory(&source, "alice", &cancel).await.unwrap();
+        assert!(progress.is_cancelled());
+        let uploaded = progress.into_inner();
+        assert_eq!(uploaded.len(), 2);
+        for (i, metadata) in uploaded.iter().enumerate() {
+            assert_eq!(service.download_and_verify(&metadata.hash).await.unwrap(), format!("file number {}", i).as_bytes());
+        }
+        assert_eq!(service.get_user_files("alice").await.unwrap().len(), 2);
+        
+        assert!(service.verify_all(&cancel).await.unwrap().is_cancelled());
+        assert!(service.reconcile(&cancel).await.unwrap().is_cancelled());
+    }
+    
+    // Stands in for an external directory: one fixed credential, mirrored locally
+    struct FixedCredentialProvider {
+        user: User,
+        password: &'static str,
+    }
+    
+    #[async_trait]
+    impl AuthProvider for FixedCredentialProvider {
+        async fn authenticate(&self, username: &str, password: &str) -> Result<Option<User>> {
+            Ok((username == self.user.username && password == self.password).then(|| self.user.clone()))
+        }
+        
+        async fn get_user(&self, username: &str) -> Result<Option<User>> {
+            Ok((username == self.user.username).then(|| self.user.clone()))
+        }
+        
+        async fn register(&self, _username: &str, _password: &str, _email: Option<&str>) -> Result<User> {
+            anyhow::bail!("accounts are managed by the directory")
+        }
+    }
+    
+    #[tokio::test]
+    async fn login_goes_through_the_auth_provider() {
+        let (_dir, mut service) = open_service().await;
+        let user = add_user(&service, "dave").await;
+        service.auth_provider = Box::new(FixedCredentialProvider { user, password: "directory secret" });
+        
+        assert!(service.login("dave", "unused").await.unwrap().is_none());
+        assert!(service.current_user.is_none());
+        let user = service.login("dave", "directory secret").await.unwrap().unwrap();
+        assert_eq!(service.current_user.as_ref().unwrap().id, user.id);
+        assert!(service.register_user("erin", "a password", None).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn multi_hash_matches_reference_digests() {
+        use sha2::Digest;
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let data = b"The quick brown fox jumps over the lazy dog";
+        let hash = upload(&mut service, "alice", data).await;
+        
+        let digests = service.multi_hash(&hash).await.unwrap();
+        assert_eq!(digests.sha256.to_hex(), "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592");
+        assert_eq!(digests.sha512.bytes, sha2::Sha512::digest(data).to_vec());
+        assert_eq!(digests.sha3_256.bytes, sha3::Sha3_256::digest(data).to_vec());
+        assert_eq!(digests.sha3_512.bytes, sha3::Sha3_512::digest(data).to_vec());
+        assert_eq!(digests.blake3.bytes, blake3::hash(data).as_bytes().to_vec());
+        assert_eq!(digests.blake3.algo, HashAlgo::Blake3);
+    }
+    
+    #[tokio::test]
+    async fn health_check_reports_db_and_storage() {
+        let (dir, service) = open_service().await;
+        let report = service.health_check().await;
+        assert!(report.db_ok && report.storage_writable && !report.in_memory_fallback);
+        assert!(report.storage_dir_free_bytes.is_some());
+        assert!(report.is_healthy());
+        
+        let storage = dir.path().join("storage");
+        std::fs::remove_dir_all(&storage).unwrap();
+        std::fs::write(&storage, b"not a directory").unwrap();
+        let report = service.health_check().await;
+        assert!(report.db_ok);
+        assert!(!report.storage_writable);
+        assert!(!report.is_healthy());
+    }
+    
+    #[tokio::test]
+    async fn bound_download_link_only_redeems_for_its_user() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"linked").await;
+        
+        let bound = service.create_download_link(&hash, "alice", Some("bob"), None).await.unwrap();
+        assert_eq!(service.redeem_download_link(&bound, Some("bob")).await.unwrap(), b"linked");
+        for redeemer in [Some("carol"), None] {
+            let err = service.redeem_download_link(&bound, redeemer).await.unwrap_err();
+            assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        }
+        
+        let open = service.create_download_link(&hash, "alice", None, None).await.unwrap();
+        for redeemer in [Some("carol"), None] {
+            assert_eq!(service.redeem_download_link(&open, redeemer).await.unwrap(), b"linked");
+        }
+    }
+    
+    #[tokio::test]
+    async fn verify_progress_counts_every_file_in_order() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        for i in 0..5 {
+            upload(&mut service, "alice", format!("file {}", i).as_bytes()).await;
+        }
+        service.verify_concurrency = 2;
+        
+        let mut seen = Vec::new();
+        let failures = service.verify_all_with_progress(&CancellationToken::new(), |checked, total| seen.push((checked, total)))
+            .await.unwrap().into_inner();
+        assert!(failures.is_empty());
+        assert_eq!(seen, (1..=5).map(|i| (i, 5)).collect::<Vec<_>>());
+    }
+    
+    #[tokio::test]
+    async fn panicking_progress_callback_does_not_stop_the_scan() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        upload(&mut service, "alice", b"fine").await;
+        let damaged = upload(&mut service, "alice", b"damaged").await;
+        corrupt_chunk(&dir, &damaged, 0);
+        
+        let progress = service.verify_all_with_progress(&CancellationToken::new(), |_, _| panic!("progress bar broke"))
+            .await.unwrap();
+        assert!(!progress.is_cancelled());
+        let failures = progress.into_inner();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, damaged);
+    }
+    
+    #[tokio::test]
+    async fn deleted_file_leaves_listings_and_restores() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let hash = upload(&mut service, "alice", b"oops").await;
+        
+        service.delete_file(&hash, "alice").await.unwrap();
+        assert!(service.get_user_files("alice").await.unwrap().is_empty());
+        assert_eq!(service.recycle_bin("alice").await.unwrap().len(), 1);
+        assert!(service.storage.contains(&hash));
+        
+        service.restore_file(&hash, "alice").await.unwrap();
+        assert_eq!(service.get_user_files("alice").await.unwrap().len(), 1);
+        assert!(service.recycle_bin("alice").await.unwrap().is_empty());
+        assert_eq!(service.download_and_verify(&hash).await.unwrap(), b"oops");
+    }
+    
+    #[tokio::test]
+    async fn purge_waits_for_the_grace_period() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let hash = upload(&mut service, "alice", b"gone for good").await;
+        service.delete_file(&hash, "alice").await.unwrap();
+        
+        assert_eq!(service.purge_expired().await.unwrap(), 0);
+        assert!(service.storage.contains(&hash));
+        
+        service.recycle_grace = Duration::zero();
+        assert_eq!(service.purge_expired().await.unwrap(), 1);
+        assert!(!service.storage.contains(&hash));
+        assert!(service.recycle_bin("alice").await.unwrap().is_empty());
+        assert!(service.restore_file(&hash, "alice").await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn recycled_files_are_unreachable_through_shares_and_links() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"into the bin").await;
+        service.share_file(&hash, "alice", "bob", SharePermission::Reshare).await.unwrap();
+        let link = service.create_download_link(&hash, "alice", None, None).await.unwrap();
+        service.delete_file(&hash, "alice").await.unwrap();
+        
+        assert!(!service.can_read("bob", &hash).await.unwrap());
+        assert!(service.share_file(&hash, "bob", "carol", SharePermission::Read).await.is_err());
+        assert!(service.get_shared_files("carol").await.unwrap().is_empty());
+        let err = service.redeem_download_link(&link, None).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidDownloadLink(_))));
+        
+        service.restore_file(&hash, "alice").await.unwrap();
+        assert!(service.can_read("bob", &hash).await.unwrap());
+        assert_eq!(service.redeem_download_link(&link, None).await.unwrap(), b"into the bin");
+        service.share_file(&hash, "bob", "carol", SharePermission::Read).await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn usage_counts_leave_out_recycled_and_superseded_files() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        let binned = upload(&mut service, "alice", b"binned").await;
+        let old = upload(&mut service, "alice", b"version one").await;
+        service.share_file(&binned, "alice", "bob", SharePermission::Read).await.unwrap();
+        service.delete_file(&binned, "alice").await.unwrap();
+        service.update_file(&old, b"version two", "alice").await.unwrap();
+        
+        let activity = service.database.user_activity("alice").await.unwrap();
+        assert_eq!((activity.file_count, activity.total_bytes, activity.share_count), (1, 11, 0));
+        let users = service.database.list_users(10, 0).await.unwrap();
+        let alice = users.iter().find(|u| u.username == "alice").unwrap();
+        assert_eq!((alice.file_count, alice.total_bytes), (1, 11));
+        let stats = service.get_system_stats().await.unwrap();
+        assert_eq!((stats.total_files, stats.unique_files, stats.total_bytes, stats.total_shares), (1, 1, 11, 0));
+    }
+    
+    #[tokio::test]
+    async fn reuploading_a_corrupt_chunk_restores_the_file() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "admin").await;
+        add_user(&service, "alice").await;
+        service.grant_admin("admin").await.unwrap();
+        service.storage.chunk_size = 4;
+        let hash = upload(&mut service, "alice", b"abcdefghijkl").await;
+        corrupt_chunk(&dir, &hash, 1);
+        
+        let failures = service.verify_all(&CancellationToken::new()).await.unwrap().into_inner();
+        assert_eq!(failures.len(), 1);
+        
+        let err = service.reupload_chunk("admin", &hash, 1, b"EFGH").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::ChunkMismatch { index: 1, .. })));
+        assert!(service.reupload_chunk("admin", &hash, 1, b"efgh").await.unwrap());
+        
+        assert!(service.list_quarantined().await.unwrap().is_empty());
+        assert_eq!(service.download_and_verify(&hash).await.unwrap(), b"abcdefghijkl");
+        assert!(service.verify_all(&CancellationToken::new()).await.unwrap().into_inner().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn storage_stats_count_duplicates_and_agree_with_the_db() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        upload(&mut service, "alice", b"shared").await;
+        upload(&mut service, "alice", b"alice only").await;
+        upload(&mut service, "bob", b"shared").await;
+        
+        let stats = service.storage_stats();
+        assert_eq!(stats, DedupStats { total_files: 3, unique_files: 2, total_bytes: 22, saved_bytes: 6 });
+        assert_eq!(
+            serde_json::to_string(&stats).unwrap(),
+            r#"{"total_files":3,"unique_files":2,"total_bytes":22,"saved_bytes":6}"#,
+        );
+        
+        let db = service.get_system_stats().await.unwrap();
+        assert_eq!((db.total_files, db.unique_files), (stats.total_files as i64, stats.unique_files as i64));
+        assert_eq!((db.total_bytes, db.saved_bytes), (stats.total_bytes as i64, stats.saved_bytes as i64));
+    }
+    
+    #[tokio::test]
+    async fn import_keeps_a_backdated_timestamp() {
+        let (_dir, mut service) = open_service().await;
+        let alice = add_user(&service, "alice").await;
+        let created_at = DateTime::parse_from_rfc3339("2019-03-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        
+        let metadata = service.import_file(b"from the archive", "old.txt", "alice", None, created_at, None).await.unwrap();
+        assert_eq!(metadata.created_at, created_at);
+        assert_eq!(metadata.modified_at, created_at);
+        let record = service.database.get_owned_file(&metadata.hash, alice.id).await.unwrap().unwrap();
+        assert_eq!(record.created_at, created_at);
+        assert_eq!(record.modified_at, created_at);
+        
+        let future = Utc::now() + Duration::hours(1);
+        let err = service.import_file(b"from the future", "new.txt", "alice", None, future, None).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidTimestamp(_))));
+        let err = service.import_file(b"out of order", "o.txt", "alice", None, created_at, Some(created_at - Duration::days(1))).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidTimestamp(_))));
+    }
+    
+    #[tokio::test]
+    async fn group_share_reaches_every_current_member() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol", "dave", "erin"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"team notes").await;
+        service.create_group("alice", "team").await.unwrap();
+        for member in ["bob", "carol", "dave"] {
+            service.add_group_member("alice", "team", member).await.unwrap();
+        }
+        
+        assert_eq!(service.share_with_group(&hash, "alice", "team", SharePermission::Read, None).await.unwrap(), 3);
+        for member in ["bob", "carol", "dave"] {
+            assert_eq!(service.get_shared_files(member).await.unwrap().len(), 1, "{}", member);
+        }
+        
+        // Membership is read at share time; later members get nothing retroactively
+        service.add_group_member("alice", "team", "erin").await.unwrap();
+        assert!(service.get_shared_files("erin").await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn sharing_with_an_empty_group_shares_nothing() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let hash = upload(&mut service, "alice", b"nobody to tell").await;
+        service.create_group("alice", "empty").await.unwrap();
+        
+        assert_eq!(service.share_with_group(&hash, "alice", "empty", SharePermission::Read, None).await.unwrap(), 0);
+        assert!(service.share_with_group(&hash, "alice", "missing", SharePermission::Read, None).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn hash_algo_report_flags_weaker_files() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        service.storage.hash_algo = HashAlgo::Sha256;
+        upload(&mut service, "alice", b"old one").await;
+        upload(&mut service, "alice", b"old two").await;
+        service.storage.hash_algo = HashAlgo::Sha3_512;
+        let strong = upload(&mut service, "alice", b"new").await;
+        service.min_hash_strength = HashAlgo::Sha3_512.strength_bits();
+        
+        let mut breakdown = service.database.files_by_hash_algo().await.unwrap();
+        breakdown.sort_by_key(|(_, count)| *count);
+        assert_eq!(breakdown, [(HashAlgo::Sha3_512, 1), (HashAlgo::Sha256, 2)]);
+        
+        let report = service.hash_algo_report().await.unwrap();
+        assert_eq!(report.below_minimum.len(), 2);
+        assert!(report.below_minimum.iter().all(|f| f.hash_algo == "sha256"));
+        assert!(report.below_minimum.iter().all(|f| f.hash != strong.to_hex()));
+        
+        service.min_hash_strength = HashAlgo::Sha256.strength_bits();
+        assert!(service.hash_algo_report().await.unwrap().below_minimum.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn outgoing_shares_are_grouped_by_file() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let second = service.upload_file(b"second", "b.txt", "alice", None, None).await.unwrap().hash;
+        let first = service.upload_file(b"first", "a.txt", "alice", None, None).await.unwrap().hash;
+        service.share_file(&second, "alice", "bob", SharePermission::Read).await.unwrap();
+        service.share_file(&first, "alice", "carol", SharePermission::Reshare).await.unwrap();
+        service.share_file(&first, "alice", "bob", SharePermission::Read).await.unwrap();
+        
+        let shares = service.get_my_shares("alice").await.unwrap();
+        let rows: Vec<(&str, &str)> = shares.iter()
+            .map(|s| (s.filename.as_str(), s.shared_with_username.as_str()))
+            .collect();
+        assert_eq!(rows, [("a.txt", "carol"), ("a.txt", "bob"), ("b.txt", "bob")]);
+        assert_eq!(shares[0].file_id, shares[1].file_id);
+        assert_ne!(shares[1].file_id, shares[2].file_id);
+        assert!(shares.iter().all(|s| matches!(s.status, ShareStatus::Active)));
+        assert!(service.get_my_shares("bob").await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn old_version_download_names_the_latest() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let v1 = upload(&mut service, "alice", b"version one").await;
+        let v2 = service.update_file(&v1, b"version two", "alice").await.unwrap().hash;
+        
+        let (data, info) = service.download_with_version_info(&v1).await.unwrap();
+        assert_eq!(data, b"version one");
+        let info = info.unwrap();
+        assert_eq!((info.latest_hash, info.newer_versions), (v2.clone(), 1));
+        
+        let (_, info) = service.download_with_version_info(&v2).await.unwrap();
+        assert!(info.is_none());
+        
+        let v3 = service.update_file(&v2, b"version three", "alice").await.unwrap().hash;
+        let info = service.version_info(&v1).await.unwrap().unwrap();
+        assert_eq!((info.latest_hash, info.newer_versions), (v3, 2));
+        
+        service.report_newer_versions = false;
+        assert!(service.download_with_version_info(&v1).await.unwrap().1.is_none());
+    }
+    
+    #[tokio::test]
+    async fn attributes_set_overwrite_and_remove() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        let hash = upload(&mut service, "alice", b"classified").await;
+        
+        service.set_file_attribute(&hash, "alice", "project", "apollo").await.unwrap();
+        service.set_file_attribute(&hash, "alice", "retention", "1y").await.unwrap();
+        service.set_file_attribute(&hash, "alice", "project", "gemini").await.unwrap();
+        let attributes = service.get_file_attributes(&hash, "alice").await.unwrap();
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes["project"], "gemini");
+        
+        assert!(service.remove_file_attribute(&hash, "alice", "retention").await.unwrap());
+        assert!(!service.remove_file_attribute(&hash, "alice", "retention").await.unwrap());
+        assert_eq!(service.get_file_attributes(&hash, "alice").await.unwrap(), HashMap::from([("project".to_string(), "gemini".to_string())]));
+        
+        assert!(service.set_file_attribute(&hash, "bob", "project", "mine").await.is_err());
+        assert!(service.set_file_attribute(&hash, "alice", "  ", "blank").await.is_err());
+        assert!(service.set_file_attribute(&hash, "alice", "notes", &"x".repeat(100_000)).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn attribute_count_is_capped_but_overwrites_are_allowed() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let hash = upload(&mut service, "alice", b"well annotated").await;
+        for i in 0..MAX_ATTRIBUTES {
+            service.set_file_attribute(&hash, "alice", &format!("k{}", i), "v").await.unwrap();
+        }
+        
+        let err = service.set_file_attribute(&hash, "alice", "one more", "v").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::AttributeRejected(_))));
+        service.set_file_attribute(&hash, "alice", "k0", "changed").await.unwrap();
+        assert_eq!(service.get_file_attributes(&hash, "alice").await.unwrap()["k0"], "changed");
+    }
+    
+    #[tokio::test]
+    async fn shares_get_the_default_ttl_and_are_capped_at_the_maximum() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"time limited").await;
+        service.default_share_ttl = Some(Duration::days(7));
+        service.max_share_ttl = Some(Duration::days(30));
+        
+        let before = Utc::now();
+        service.share_file(&hash, "alice", "bob", SharePermission::Read).await.unwrap();
+        let expires = service.get_shared_files("bob").await.unwrap()[0].expires_at.unwrap();
+        assert!(expires >= before + Duration::days(7) && expires <= Utc::now() + Duration::days(7));
+        
+        let requested = Utc::now() + Duration::days(365);
+        service.share_file_until(&hash, "alice", "carol", SharePermission::Read, Some(requested)).await.unwrap();
+        let expires = service.get_shared_files("carol").await.unwrap()[0].expires_at.unwrap();
+        assert!(expires <= Utc::now() + Duration::days(30));
+        assert!(expires >= before + Duration::days(30));
+        
+        let shorter = Utc::now() + Duration::days(1);
+        assert_eq!(service.share_expiry(Some(shorter)), Some(shorter));
+    }
+    
+    #[tokio::test]
+    async fn download_output_may_be_a_dir_a_file_or_a_new_path() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let hash = upload(&mut service, "alice", b"fresh copy").await;
+        let out = dir.path().join("out");
+        std::fs::create_dir(&out).unwrap();
+        let download = |output: PathBuf| {
+            let (service, hash) = (&service, &hash);
+            async move { service.download_to_path(hash, &output, "file.txt", CollisionPolicy::Overwrite).await }
+        };
+        
+        // Existing directory: the filename is appended
+        assert_eq!(download(out.clone()).await.unwrap(), Some(out.join("file.txt")));
+        // Existing file: written over in place
+        let existing = out.join("existing.txt");
+        std::fs::write(&existing, b"stale").unwrap();
+        assert_eq!(download(existing.clone()).await.unwrap(), Some(existing.clone()));
+        assert_eq!(std::fs::read(&existing).unwrap(), b"fresh copy");
+        // Nonexistent path: a file, with its parent directories created
+        let nested = out.join("a").join("b").join("copy.txt");
+        assert_eq!(download(nested.clone()).await.unwrap(), Some(nested.clone()));
+        assert_eq!(std::fs::read(&nested).unwrap(), b"fresh copy");
+        
+        // A parent that is really a file can't be created
+        let err = download(existing.join("inside.txt")).await.unwrap_err();
+        assert!(err.to_string().starts_with("Cannot create directory"), "{}", err);
+    }
+    
+    #[tokio::test]
+    async fn head_describes_a_stored_file_without_its_bytes() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        service.storage.chunk_size = 4;
+        let metadata = service.upload_file(b"plain text body", "note.txt", "alice", None, None).await.unwrap();
+        // Damaged chunks don't matter: head never reads them
+        corrupt_chunk(&dir, &metadata.hash, 0);
+        
+        let head = service.head(&metadata.hash).await.unwrap().unwrap();
+        assert_eq!((head.size, head.chunks), (15, 4));
+        assert_eq!(head.merkle_root, metadata.merkle_root.to_hex());
+        assert_eq!(head.created_at, metadata.created_at);
+        assert_eq!(head.mime_type, "text/plain");
+        
+        let unknown = HashValue::compute(b"never stored", HashAlgo::Sha256);
+        assert!(service.head(&unknown).await.unwrap().is_none());
+        
+        assert!(service.can_read("alice", &metadata.hash).await.unwrap());
+        assert!(!service.can_read("bob", &metadata.hash).await.unwrap());
+        service.share_file(&metadata.hash, "alice", "bob", SharePermission::Read).await.unwrap();
+        assert!(service.can_read("bob", &metadata.hash).await.unwrap());
+        assert!(!service.can_read("nobody", &metadata.hash).await.unwrap());
+    }
+    
+    #[tokio::test]
+    async fn usernames_are_case_insensitive_but_keep_their_casing() {
+        let (_dir, mut service) = open_service().await;
+        service.register_user("Alice", "a password", None).await.unwrap();
+        
+        let err = service.register_user("alice", "another password", None).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::UsernameTaken(_))));
+        
+        let user = service.login("ALICE", "a password").await.unwrap().unwrap();
+        assert_eq!(user.username, "Alice");
+        assert_eq!(service.database.get_user_by_username("aLiCe").await.unwrap().unwrap().id, user.id);
+        
+        // Listings resolve the name the same way
+        add_user(&service, "Bob").await;
+        let hash = upload(&mut service, "ALICE", b"any casing").await;
+        service.share_file(&hash, "alice", "BOB", SharePermission::Read).await.unwrap();
+        assert_eq!(service.get_user_files("aLICE").await.unwrap().len(), 1);
+        assert_eq!(service.get_shared_files("bob").await.unwrap().len(), 1);
+        let activity = service.database.user_activity("alice").await.unwrap();
+        assert_eq!((activity.file_count, activity.share_count), (1, 1));
+        let audit = service.database.query_audit(&AuditFilter { user: Some("ALICE".to_string()), ..Default::default() }).await.unwrap();
+        assert!(audit.iter().any(|entry| entry.action == "upload"));
+        assert!(service.get_user_files("nobody").await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn reshares_expire_no_later_than_the_resharers_own_share() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol", "dave", "erin", "frank"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"borrowed access").await;
+        let bobs_expiry = Utc::now() + Duration::days(3);
+        service.share_file_until(&hash, "alice", "bob", SharePermission::Reshare, Some(bobs_expiry)).await.unwrap();
+        
+        service.share_file_until(&hash, "bob", "carol", SharePermission::Read, None).await.unwrap();
+        service.share_file_until(&hash, "bob", "dave", SharePermission::Read, Some(Utc::now() + Duration::days(30))).await.unwrap();
+        for name in ["carol", "dave"] {
+            let expires = service.get_shared_files(name).await.unwrap()[0].expires_at;
+            assert_eq!(expires.map(|t| t.timestamp()), Some(bobs_expiry.timestamp()), "{name}");
+        }
+        let sooner = Utc::now() + Duration::days(1);
+        service.share_file_until(&hash, "bob", "erin", SharePermission::Read, Some(sooner)).await.unwrap();
+        assert_eq!(service.get_shared_files("erin").await.unwrap()[0].expires_at.map(|t| t.timestamp()), Some(sooner.timestamp()));
+        
+        service.create_group("bob", "friends").await.unwrap();
+        service.add_group_member("bob", "friends", "frank").await.unwrap();
+        service.share_with_group(&hash, "bob", "friends", SharePermission::Read, None).await.unwrap();
+        assert_eq!(service.get_shared_files("frank").await.unwrap()[0].expires_at.map(|t| t.timestamp()), Some(bobs_expiry.timestamp()));
+    }
+    
+    #[tokio::test]
+    async fn compact_storage_is_admin_only() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "admin").await;
+        add_user(&service, "alice").await;
+        service.grant_admin("admin").await.unwrap();
+        let orphan = dir.path().join("storage").join(format!("{}_0.chunk", "ab".repeat(32)));
+        std::fs::write(&orphan, b"left behind").unwrap();
+        
+        let err = service.compact_storage("alice").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        assert!(orphan.exists());
+        
+        let report = service.compact_storage("admin").await.unwrap();
+        assert_eq!((report.removed_files, report.reclaimed_bytes), (1, 11));
+        assert!(!orphan.exists());
+    }
+    
+    #[tokio::test]
+    async fn hash_policy_rejects_disallowed_algorithms() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        service.allowed_hash_algos = Some(vec![HashAlgo::Sha3_512]);
+        service.storage.hash_algo = HashAlgo::Sha256;
+        service.storage.chunk_algo = HashAlgo::Sha3_512;
+        
+        let err = service.upload_file(b"weak", "weak.txt", "alice", None, None).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::HashAlgoNotAllowed(algo)) if algo == "sha256"));
+        assert!(service.storage.hashes().is_empty());
+        
+        service.storage.hash_algo = HashAlgo::Sha3_512;
+        let stored = service.upload_file(b"strong", "strong.txt", "alice", None, None).await.unwrap();
+        assert_eq!(stored.hash.algo, HashAlgo::Sha3_512);
+        
+        // Updates store new content too
+        service.storage.chunk_algo = HashAlgo::Sha256;
+        let err = service.update_file(&stored.hash, b"stronger", "alice").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::HashAlgoNotAllowed(_))));
+    }
+    
+    #[tokio::test]
+    async fn recipient_checks_the_share_commitment() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        for scheme in [CommitmentKind::Hash, CommitmentKind::Pedersen] {
+            service.commitment_scheme = scheme;
+            let data = format!("committed under {:?}", scheme).into_bytes();
+            let hash = upload(&mut service, "alice", &data).await;
+            service.share_file(&hash, "alice", "bob", SharePermission::Read).await.unwrap();
+            let share = service.get_shared_files("bob").await.unwrap().into_iter()
+                .find(|s| s.commitment_scheme == scheme).unwrap();
+            
+            assert!(service.verify_share_commitment(&share, &data).await.unwrap());
+            assert!(!service.verify_share_commitment(&share, b"different content").await.unwrap());
+            assert!(FileSharingService::verify_received(&share, &data).unwrap());
+            assert!(!FileSharingService::verify_received(&share, b"different content").unwrap());
+        }
+    }
+    
+    // Hashes what passes through, remembering only the largest single write
+    #[derive(Default)]
+    struct CountingWriter {
+        hasher: sha2::Sha256,
+        total: u64,
+        largest_write: usize,
+    }
+    
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            use sha2::Digest;
+            self.hasher.update(buf);
+            self.total += buf.len() as u64;
+            self.largest_write = self.largest_write.max(buf.len());
+            Ok(buf.len())
+        }
+        
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    #[tokio::test]
+    async fn export_streams_one_chunk_at_a_time() {
+        use sha2::Digest;
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        service.storage.chunk_size = 4096;
+        let data: Vec<u8> = (0..256 * 1024u32).map(|i| (i * 31 % 251) as u8).collect();
+        let hash = upload(&mut service, "alice", &data).await;
+        
+        let mut writer = CountingWriter::default();
+        let written = service.export_to_writer(&hash, &mut writer).await.unwrap();
+        assert_eq!((written, writer.total), (data.len() as u64, data.len() as u64));
+        assert!(writer.largest_write <= 4096, "largest write {}", writer.largest_write);
+        assert_eq!(writer.hasher.finalize().to_vec(), hash.bytes);
+    }
+    
+    #[tokio::test]
+    async fn uploading_owned_content_again_returns_the_existing_file() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let first = service.upload_file(b"same bytes", "a.txt", "alice", Some("first"), None).await.unwrap();
+        let again = service.upload_file(b"same bytes", "b.txt", "alice", Some("second"), None).await.unwrap();
+        assert_eq!(again.hash, first.hash);
+        assert_eq!(again.path, first.path);
+        let files = service.get_user_files("alice").await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].filename, "a.txt");
+        assert_eq!(files[0].description.as_deref(), Some("first"));
+    }
+    
+    #[tokio::test]
+    async fn refresh_mode_takes_the_new_description_and_timestamp() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        service.duplicate_upload = DuplicateUpload::Refresh;
+        let first = service.upload_file(b"same bytes", "a.txt", "alice", Some("first"), None).await.unwrap();
+        let again = service.upload_file(b"same bytes", "a.txt", "alice", Some("second"), None).await.unwrap();
+        assert!(again.modified_at >= first.modified_at);
+        let files = service.get_user_files("alice").await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].description.as_deref(), Some("second"));
+        assert_eq!(files[0].modified_at, again.modified_at);
+    }
+    
+    #[tokio::test]
+    async fn self_test_passes_on_a_healthy_instance_without_touching_it() {
+        let (_dir, service) = open_service().await;
+        let report = service.self_test().await;
+        assert!(report.all_passed(), "{:?}", report.steps);
+        let names: Vec<&str> = report.steps.iter().map(|s| s.name).collect();
+        assert_eq!(names, [
+            "start scratch instance", "register users", "upload", "download and verify",
+            "share", "read as recipient", "clean up",
+        ]);
+        assert!(service.database.get_user_by_username("selftest-owner").await.unwrap().is_none());
+        assert_eq!(service.storage.dedup_stats.unique_files, 0);
+    }
+    
+    #[tokio::test]
+    async fn self_test_stops_at_the_first_failure_and_still_cleans_up() {
+        let (_dir, mut service) = open_service().await;
+        service.allowed_hash_algos = Some(vec![HashAlgo::Blake3]);
+        let report = service.self_test().await;
+        assert!(!report.all_passed());
+        let names: Vec<(&str, bool)> = report.steps.iter().map(|s| (s.name, s.passed)).collect();
+        assert_eq!(names, [
+            ("start scratch instance", true), ("register users", true), ("upload", false), ("clean up", true),
+        ]);
+        assert!(report.steps[2].detail.is_some());
+    }
+    
+    #[tokio::test]
+    async fn parallel_scan_reports_exactly_the_corrupt_file_in_order() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        service.storage.chunk_size = 4;
+        service.verify_concurrency = 3;
+        let mut hashes = Vec::new();
+        for i in 0..10 {
+            hashes.push(upload(&mut service, "alice", format!("file number {:02}", i).as_bytes()).await);
+        }
+        corrupt_chunk(&dir, &hashes[7], 2);
+        
+        let mut calls = Vec::new();
+        let failures = service.verify_all_with_progress(&CancellationToken::new(), |checked, total| calls.push((checked, total)))
+            .await.unwrap().into_inner();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, hashes[7]);
+        assert_eq!(calls, (1..=10).map(|i| (i, 10)).collect::<Vec<_>>());
+        
+        let quarantined = service.list_quarantined().await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].hash_value().unwrap(), hashes[7]);
+    }
+    
+    #[tokio::test]
+    async fn open_builds_a_working_service_from_one_config() {
+        let dir = TempDir::new().unwrap();
+        let mut service = FileSharingService::open(ServiceConfig {
+            data_dir: dir.path().to_path_buf(),
+            chunk_size: 5,
+            hash_algo: HashAlgo::Blake3,
+            duplicate_upload: DuplicateUpload::Refresh,
+            ..ServiceConfig::default()
+        }).await.unwrap();
+        service.storage.min_free_space = 0;
+        assert!(dir.path().join("secure_files.db").is_file());
+        assert!(dir.path().join("watch").is_dir());
+        assert_eq!(service.duplicate_upload, DuplicateUpload::Refresh);
+        
+        add_user(&service, "alice").await;
+        let metadata = service.upload_file(b"hello from open", "hello.txt", "alice", None, None).await.unwrap();
+        assert_eq!(metadata.hash.algo, HashAlgo::Blake3);
+        assert_eq!(metadata.chunks.len(), 3);
+        assert_eq!(chunk_files(&dir.path().join("storage")), 3);
+        assert_eq!(service.download_and_verify(&metadata.hash).await.unwrap(), b"hello from open");
+    }
+    
+    #[tokio::test]
+    async fn blank_passwords_are_rejected_on_register_and_change() {
+        let (_dir, mut service) = open_service().await;
+        for blank in ["", "   \t"] {
+            let err = service.register_user("alice", blank, None).await.unwrap_err();
+            assert!(matches!(err.downcast_ref(), Some(FileSharingError::WeakPassword(_))));
+        }
+        assert!(service.database.get_user_by_username("alice").await.unwrap().is_none());
+        
+        service.register_user("alice", "old secret", None).await.unwrap();
+        let err = service.change_password("alice", "old secret", "  ").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::WeakPassword(_))));
+        assert!(service.login("alice", "old secret").await.unwrap().is_some());
+    }
+    
+    #[tokio::test]
+    async fn chunk_archive_reimports_to_the_same_hash() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        service.storage.chunk_size = 4;
+        let hash = upload(&mut service, "alice", b"a file of several chunks").await;
+        let archive = service.download_chunks_archive(&hash).await.unwrap();
+        
+        let (_other_dir, mut other) = open_service().await;
+        add_user(&other, "bob").await;
+        let imported = other.import_chunks_archive(&archive, "copy.txt", "bob").await.unwrap();
+        assert_eq!(imported.hash, hash);
+        assert_eq!(other.download_and_verify(&hash).await.unwrap(), b"a file of several chunks");
+    }
+    
+    #[tokio::test]
+    async fn injected_read_corruption_quarantines_the_file_until_repaired() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "admin").await;
+        add_user(&service, "alice").await;
+        service.grant_admin("admin").await.unwrap();
+        service.storage.chunk_size = 4;
+        let hash = upload(&mut service, "alice", b"abcdefghijkl").await;
+        
+        service.storage.inject_read_corruption(2);
+        let failures = service.verify_all(&CancellationToken::new()).await.unwrap().into_inner();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, hash);
+        assert!(failures[0].1.contains("chunk 1"), "{}", failures[0].1);
+        assert_eq!(service.list_quarantined().await.unwrap().len(), 1);
+        
+        assert!(service.reupload_chunk("admin", &hash, 1, b"efgh").await.unwrap());
+        assert!(service.list_quarantined().await.unwrap().is_empty());
+        assert!(service.verify_all(&CancellationToken::new()).await.unwrap().into_inner().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn reshare_on_update_gives_every_recipient_the_new_version() {
+        let (_dir, mut service) = open_service().await;
+        let alice = add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        add_user(&service, "carol").await;
+        let old = upload(&mut service, "alice", b"version one").await;
+        let expiry = Utc::now() + Duration::days(3);
+        service.share_file(&old, "alice", "bob", SharePermission::Read).await.unwrap();
+        service.share_file_until(&old, "alice", "carol", SharePermission::Reshare, Some(expiry)).await.unwrap();
+        
+        let new = service.update_file_with_reshare(&old, b"version two", "alice", true).await.unwrap().hash;
+        let new_id = service.database.get_owned_file(&new, alice.id).await.unwrap().unwrap().id;
+        for (recipient, permission, expires_at) in [("bob", SharePermission::Read, None), ("carol", SharePermission::Reshare, Some(expiry))] {
+            let shares = service.get_shared_files(recipient).await.unwrap();
+            let share = shares.iter().find(|s| s.file_id == new_id).expect("no share of the new version");
+            assert_eq!(share.permission, permission);
+            assert_eq!(share.expires_at.map(|t| t.timestamp()), expires_at.map(|t| t.timestamp()));
+            assert!(service.verify_share_commitment(share, b"version two").await.unwrap());
+        }
+    }
+    
+    #[tokio::test]
+    async fn plain_update_leaves_recipients_on_the_old_version() {
+        let (_dir, mut service) = open_service().await;
+        let alice = add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        let old = upload(&mut service, "alice", b"version one").await;
+        service.share_file(&old, "alice", "bob", SharePermission::Read).await.unwrap();
+        
+        let new = service.update_file(&old, b"version two", "alice").await.unwrap().hash;
+        let new_id = service.database.get_owned_file(&new, alice.id).await.unwrap().unwrap().id;
+        let shares = service.get_shared_files("bob").await.unwrap();
+        assert!(shares.iter().all(|s| s.file_id != new_id));
+    }
+    
+    #[tokio::test]
+    async fn recompute_merkle_root_corrects_a_wrong_db_root() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "admin").await;
+        let alice = add_user(&service, "alice").await;
+        service.grant_admin("admin").await.unwrap();
+        service.storage.chunk_size = 4;
+        let hash = upload(&mut service, "alice", b"abcdefghijkl").await;
+        let expected = service.storage.metadata(&hash).unwrap().merkle_root.clone();
+        let wrong = HashValue::compute(b"not the root", expected.algo);
+        service.database.set_merkle_root(&hash, &wrong).await.unwrap();
+        
+        let err = service.recompute_merkle_root("alice", &hash).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        
+        assert!(service.recompute_merkle_root("admin", &hash).await.unwrap());
+        let record = service.database.get_owned_file(&hash, alice.id).await.unwrap().unwrap();
+        assert_eq!(record.merkle_root, expected.to_hex());
+        assert!(!service.recompute_merkle_root("admin", &hash).await.unwrap());
+    }
+    
+    // Keeps every notification sent, for assertions
+    #[derive(Default, Clone)]
+    struct RecordingNotifier {
+        sent: std::sync::Arc<std::sync::Mutex<Vec<(String, Notification)>>>,
+    }
+    
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn notify(&self, to: &str, notification: &Notification) {
+            self.sent.lock().unwrap().push((to.to_string(), notification.clone()));
+        }
+    }
+    
+    #[tokio::test]
+    async fn lockout_records_an_event_and_notifies_the_owner() {
+        let (_dir, mut service) = open_service().await;
+        let notifier = RecordingNotifier::default();
+        service.notifier = Box::new(notifier.clone());
+        service.lockout_threshold = 3;
+        let alice = service.register_user("alice", "right secret", Some("alice@example.com")).await.unwrap();
+        
+        for _ in 0..2 {
+            assert!(service.login("alice", "wrong").await.unwrap().is_none());
+        }
+        let err = service.login("alice", "wrong").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::AccountLocked { .. })));
+        let err = service.login("alice", "right secret").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::AccountLocked { .. })));
+        
+        let sent = notifier.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "alice@example.com");
+        assert!(matches!(&sent[0].1, Notification::AccountLocked { username, failed_attempts: 3, .. } if username == "alice"));
+        
+        let kinds: Vec<String> = service.database.security_events_since(alice.id, None).await.unwrap()
+            .into_iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, ["login_failed", "login_failed", "login_failed", "account_locked"]);
+    }
+    
+    #[tokio::test]
+    async fn next_login_after_a_lock_reports_what_happened() {
+        let (_dir, mut service) = open_service().await;
+        service.lockout_threshold = 2;
+        service.register_user("alice", "right secret", None).await.unwrap();
+        assert!(service.login("alice", "wrong").await.unwrap().is_none());
+        assert!(service.login("alice", "wrong").await.is_err());
+        
+        service.lockout_duration = Duration::zero();
+        assert!(service.login("alice", "right secret").await.unwrap().is_some());
+        assert_eq!(service.login_security_events.len(), 3);
+        assert_eq!(service.login_security_events[2].kind, "account_locked");
+        
+        service.logout();
+        assert!(service.login("alice", "right secret").await.unwrap().is_some());
+        assert!(service.login_security_events.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn a_corrupt_copy_only_blocks_its_own_owner_under_per_user_dedup() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["admin", "alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        service.grant_admin("admin").await.unwrap();
+        service.storage.dedup_scope = DedupScope::PerUser;
+        service.storage.chunk_size = 4;
+        let hash = upload(&mut service, "alice", b"abcdefghijkl").await;
+        assert_eq!(upload(&mut service, "bob", b"abcdefghijkl").await, hash);
+        service.share_file(&hash, "bob", "carol", SharePermission::Read).await.unwrap();
+        service.storage.inject_corrupt_copy_chunk(&hash, "alice", 1).unwrap();
+        
+        // Only alice's copy is bad, so only her reads fail and end up quarantined
+        assert!(service.download_as(&hash, Some("alice")).await.is_err());
+        let err = service.download_as(&hash, Some("alice")).await.unwrap_err();
+        assert!(err.to_string().contains("quarantined"), "{}", err);
+        assert_eq!(service.download_as(&hash, Some("bob")).await.unwrap(), b"abcdefghijkl");
+        assert_eq!(service.download_as(&hash, Some("carol")).await.unwrap(), b"abcdefghijkl");
+        assert_eq!(service.download_range(&hash, Some("carol"), 4, 8).await.unwrap(), b"efgh");
+        
+        // The scan checks every copy and finds just alice's
+        let failures = service.verify_all(&CancellationToken::new()).await.unwrap().into_inner();
+        assert_eq!(failures.len(), 1);
+        assert!(service.list_quarantined().await.unwrap()[0].copy_dir.is_some());
+        assert_eq!(service.download_as(&hash, Some("bob")).await.unwrap(), b"abcdefghijkl");
+        assert!(service.verify_user_files("bob").await.unwrap().corrupt.is_empty());
+        assert_eq!(service.verify_user_files("alice").await.unwrap().corrupt.len(), 1);
+        
+        assert!(service.reupload_chunk("admin", &hash, 1, b"efgh").await.unwrap());
+        assert_eq!(service.download_as(&hash, Some("alice")).await.unwrap(), b"abcdefghijkl");
+    }
+    
+    #[tokio::test]
+    async fn verify_user_files_reports_only_that_users_files() {
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        service.storage.chunk_size = 4;
+        upload(&mut service, "alice", b"good file").await;
+        let bad = upload(&mut service, "alice", b"bad file, corrupted").await;
+        let bobs = upload(&mut service, "bob", b"bob's corrupted file").await;
+        corrupt_chunk(&dir, &bad, 1);
+        corrupt_chunk(&dir, &bobs, 0);
+        
+        let report = service.verify_user_files("alice").await.unwrap();
+        assert_eq!((report.total, report.ok), (2, 1));
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].0, bad);
+        assert!(report.corrupt[0].1.contains("chunk 1"), "{}", report.corrupt[0].1);
+        
+        // Only alice's bad file is quarantined; bob's was never read
+        let quarantined = service.list_quarantined().await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].hash_value().unwrap(), bad);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn directory_with_a_non_utf8_name_uploads_nothing() {
+        use std::os::unix::ffi::OsStrExt;
+        let (dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        let source = dir.path().join("source");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"fine").unwrap();
+        std::fs::write(source.join(std::ffi::OsStr::from_bytes(b"b\xff.txt")), b"bad name").unwrap();
+        
+        let err = service.upload_directory(&source, "alice", &CancellationToken::new()).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidFilename(_))));
+        assert!(service.get_user_files("alice").await.unwrap().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn recipient_verifies_received_bytes_from_the_share_record_alone() {
+        let (_dir, mut service) = open_service().await;
+        add_user(&service, "alice").await;
+        add_user(&service, "bob").await;
+        add_user(&service, "carol").await;
+        service.storage.chunk_size = 4;
+        service.storage.merkle_arity = 3;
+        let data = b"shared in several chunks";
+        let hash = upload(&mut service, "alice", data).await;
+        service.share_file(&hash, "alice", "bob", SharePermission::Read).await.unwrap();
+        
+        let share = service.get_shared_files("bob").await.unwrap().remove(0);
+        let proof = share.proof.as_ref().unwrap();
+        assert_eq!((proof.size, proof.chunk_count, proof.merkle_arity), (24, 6, 3));
+        assert!(FileSharingService::verify_received(&share, data).unwrap());
+        
+        let mut tampered = data.to_vec();
+        tampered[10] ^= 1;
+        assert!(!FileSharingService::verify_received(&share, &tampered).unwrap());
+        assert!(!FileSharingService::verify_received(&share, &data[..20]).unwrap());
+        
+        // Without a proof on record the caller has to fall back to the commitment
+        service.share_proofs = false;
+        service.share_file(&hash, "alice", "carol", SharePermission::Read).await.unwrap();
+        let share = service.get_shared_files("carol").await.unwrap().remove(0);
+        assert!(share.proof.is_none());
+        assert!(FileSharingService::verify_received(&share, data).is_err());
+    }
+    
+    fn reset_tokens(notifier: &RecordingNotifier) -> Vec<String> {
+        notifier.sent.lock().unwrap().iter()
+            .filter_map(|(_, sent)| match sent {
+                Notification::PasswordReset { token, .. } => Some(token.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+    
+    #[tokio::test]
+    async fn password_reset_flow_replaces_the_password_and_ends_the_session() {
+        let (_dir, mut service) = open_service().await;
+        let notifier = RecordingNotifier::default();
+        service.notifier = Box::new(notifier.clone());
+        service.register_user("alice", "old secret", Some("alice@example.com")).await.unwrap();
+        service.login("alice", "old secret").await.unwrap().unwrap();
+        
+        service.request_password_reset("nobody").await.unwrap();
+        service.request_password_reset("alice").await.unwrap();
+        let tokens = reset_tokens(&notifier);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(notifier.sent.lock().unwrap()[0].0, "alice@example.com");
+        
+        // A rejected password leaves the token usable
+        let err = service.reset_password(&tokens[0], " ").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::WeakPassword(_))));
+        service.reset_password(&tokens[0], "new secret").await.unwrap();
+        assert!(service.current_user.is_none());
+        assert!(service.login("alice", "old secret").await.unwrap().is_none());
+        assert!(service.login("alice", "new secret").await.unwrap().is_some());
+        
+        let err = service.reset_password(&tokens[0], "another secret").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidResetToken)));
+    }
+    
+    #[tokio::test]
+    async fn expired_and_superseded_reset_tokens_are_rejected() {
+        let (_dir, mut service) = open_service().await;
+        let notifier = RecordingNotifier::default();
+        service.notifier = Box::new(notifier.clone());
+        service.register_user("alice", "old secret", Some("alice@example.com")).await.unwrap();
+        
+        service.reset_token_ttl = Duration::seconds(-1);
+        service.request_password_reset("alice").await.unwrap();
+        service.reset_token_ttl = Duration::hours(1);
+        service.request_password_reset("alice").await.unwrap();
+        service.request_password_reset("alice").await.unwrap();
+        let tokens = reset_tokens(&notifier);
+        
+        let err = service.reset_password(&tokens[0], "new secret").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidResetToken)));
+        service.reset_password(&tokens[1], "new secret").await.unwrap();
+        let err = service.reset_password(&tokens[2], "newer secret").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidResetToken)));
+        assert!(service.login("alice", "new secret").await.unwrap().is_some());
+    }
+    
+    #[tokio::test]
+    async fn share_strings_round_trip_and_carry_their_expiry() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob", "carol"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"handed over in person").await;
+        let share_string = service.share_token_string(&hash, "alice", "bob", Duration::hours(2)).await.unwrap();
+        
+        let raw = URL_SAFE_NO_PAD.decode(&share_string).unwrap();
+        let (body, _mac): (Vec<u8>, [u8; 32]) = bincode::deserialize(&raw).unwrap();
+        let ticket: ShareTicket = bincode::deserialize(&body).unwrap();
+        assert_eq!(ticket.hash, hash);
+        assert_eq!((ticket.owner.as_str(), ticket.target.as_str()), ("alice", "bob"));
+        assert!(ticket.proof.matches(b"handed over in person"));
+        
+        let err = service.redeem_share_string(&share_string, "carol").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::PermissionDenied(_))));
+        assert_eq!(service.redeem_share_string(&share_string, "bob").await.unwrap(), b"handed over in person");
+        let shares = service.get_shared_files("bob").await.unwrap();
+        assert_eq!(shares.len(), 1);
+        assert_eq!(shares[0].expires_at.map(|t| t.timestamp()), Some(ticket.expires_at.timestamp()));
+    }
+    
+    #[tokio::test]
+    async fn corrupted_and_expired_share_strings_are_rejected() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob"] {
+            add_user(&service, name).await;
+        }
+        let hash = upload(&mut service, "alice", b"handed over in person").await;
+        
+        let share_string = service.share_token_string(&hash, "alice", "bob", Duration::hours(2)).await.unwrap();
+        let mut tampered = share_string.into_bytes();
+        let mid = tampered.len() / 2;
+        tampered[mid] = if tampered[mid] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        let expired = service.share_token_string(&hash, "alice", "bob", Duration::seconds(-1)).await.unwrap();
+        
+        for bad in [tampered.as_str(), expired.as_str(), "not a share string"] {
+            let err = service.redeem_share_string(bad, "bob").await.unwrap_err();
+            assert!(matches!(err.downcast_ref(), Some(FileSharingError::InvalidShareString(_))), "{bad}");
+        }
+        assert!(service.get_shared_files("bob").await.unwrap().is_empty());
+    }
+    
+    // Just enough RFC 4180 to read export_listing_csv's output back
+    fn parse_csv(text: &str) -> Vec<Vec<String>> {
+        let mut records = Vec::new();
+        let (mut record, mut field) = (Vec::new(), String::new());
+        let mut chars = text.chars().peekable();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match (quoted, c) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => quoted = false,
+                (true, c) => field.push(c),
+                (false, '"') => quoted = true,
+                (false, ',') => record.push(std::mem::take(&mut field)),
+                (false, '\r') => {}
+                (false, '\n') => {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                (false, c) => field.push(c),
+            }
+        }
+        records
+    }
+    
+    #[tokio::test]
+    async fn listing_csv_escapes_fields_and_reads_back() {
+        let (_dir, mut service) = open_service().await;
+        for name in ["alice", "bob"] {
+            add_user(&service, name).await;
+        }
+        let plain = upload(&mut service, "alice", b"plain file").await;
+        let awkward = service.upload_file(b"awkward file", "report, final.txt", "alice", Some("the \"real\" one"), None)
+            .await.unwrap().hash;
+        service.share_file(&awkward, "alice", "bob", SharePermission::Reshare).await.unwrap();
+        
+        let mut buffer = Vec::new();
+        let rows = service.export_listing_csv("alice", false, &mut buffer).await.unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(parse_csv(&String::from_utf8(buffer).unwrap()).len(), 3);
+        
+        let mut buffer = Vec::new();
+        let rows = service.export_listing_csv("alice", true, &mut buffer).await.unwrap();
+        assert_eq!(rows, 3);
+        let records = parse_csv(&String::from_utf8(buffer).unwrap());
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0], LISTING_CSV_COLUMNS);
+        assert!(records.iter().all(|r| r.len() == LISTING_CSV_COLUMNS.len()));
+        
+        assert_eq!((records[1][0].as_str(), records[1][1].as_str()), ("file", "file.txt"));
+        assert_eq!(records[1][2], plain.to_hex());
+        assert_eq!(records[1][3], "10");
+        assert_eq!(records[2][1], "report, final.txt");
+        assert_eq!(records[2][7], "the \"real\" one");
+        assert_eq!(records[3][0], "share");
+        assert_eq!(records[3][1], "report, final.txt");
+        assert_eq!(&records[3][8..], ["bob", "reshare", "", "active"]);
+    }
+}
@@ -2,4 +2,6 @@
 // Service Module - Main Application Logic
 // ============================================================================
 
-pub mod file_sharing;
\ No newline at end of file
+pub mod file_sharing;
+pub mod scanner;
+pub mod notifier;
\ No newline at end of file
@@ -0,0 +1,35 @@
+// ============================================================================
+// Notifier Hook (email or other out-of-band messages to users)
+// ============================================================================
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    AccountLocked {
+        username: String,
+        failed_attempts: u32,
+        locked_until: DateTime<Utc>,
+    },
+    PasswordReset {
+        username: String,
+        token: String, // the only copy; the database keeps a hash
+        expires_at: DateTime<Utc>,
+    },
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    // `to` is the recipient's email address; delivery is best effort
+    async fn notify(&self, to: &str, notification: &Notification);
+}
+
+// Default notifier - drops everything
+#[derive(Debug, Default)]
+pub struct NoopNotifier;
+
+#[async_trait]
+impl Notifier for NoopNotifier {
+    async fn notify(&self, _to: &str, _notification: &Notification) {}
+}
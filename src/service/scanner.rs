@@ -0,0 +1,27 @@
+// ============================================================================
+// Content Scanner Hook (virus scanning before upload)
+// ============================================================================
+
+use async_trait::async_trait;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanResult {
+    Clean,
+    Infected(String), // reason / signature name
+}
+
+#[async_trait]
+pub trait ContentScanner: Send + Sync {
+    async fn scan(&self, data: &[u8]) -> ScanResult;
+}
+
+// Default scanner - accepts everything
+#[derive(Debug, Default)]
+pub struct NoopScanner;
+
+#[async_trait]
+impl ContentScanner for NoopScanner {
+    async fn scan(&self, _data: &[u8]) -> ScanResult {
+        ScanResult::Clean
+    }
+}
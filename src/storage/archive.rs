@@ -0,0 +1,169 @@
+// ============================================================================
+// Chunk Archive: a file as a tar of its verified chunks plus a manifest
+// ============================================================================
+
+use crate::core::file_metadata::FileMetadata;
+use crate::core::merkle_tree::MerkleTree;
+use crate::crypto::hash::HashValue;
+use crate::error::FileSharingError;
+use anyhow::{Context, Result};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::io::Read;
+
+pub const MANIFEST_ENTRY: &str = "manifest.json";
+
+// Everything needed to check the chunks without trusting the sender
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub hash: HashValue,
+    pub size: u64,
+    pub chunks: Vec<HashValue>,
+    pub merkle_root: HashValue,
+    pub merkle_arity: usize,
+}
+
+impl ChunkManifest {
+    pub fn from_metadata(metadata: &FileMetadata) -> Self {
+        Self {
+            hash: metadata.hash.clone(),
+            size: metadata.size,
+            chunks: metadata.chunks.clone(),
+            merkle_root: metadata.merkle_root.clone(),
+            merkle_arity: metadata.merkle_arity,
+        }
+    }
+}
+
+fn append_entry(builder: &mut tar::Builder<Vec<u8>>, name: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+// Tar with `manifest.json` first, then `chunk_0`, `chunk_1`, ... in order.
+// `chunks` must yield already-verified chunk bytes, e.g. from a verifying ChunkStream.
+pub fn build_chunk_archive(
+    metadata: &FileMetadata,
+    chunks: impl Iterator<Item = Result<Vec<u8>>>,
+) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    let manifest = serde_json::to_vec_pretty(&ChunkManifest::from_metadata(metadata))?;
+    append_entry(&mut builder, MANIFEST_ENTRY, &manifest)?;
+
+    for (i, chunk) in chunks.enumerate() {
+        append_entry(&mut builder, &format!("chunk_{}", i), &chunk?)?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+// Reassemble a file from an archive, checking every chunk against the manifest,
+// the chunk hashes against the Merkle root, and the result against the file hash
+pub fn read_chunk_archive(archive: &[u8]) -> Result<(ChunkManifest, Vec<u8>)> {
+    let mut manifest = None;
+    let mut chunks: HashMap<usize, Vec<u8>> = HashMap::new();
+
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        if name == MANIFEST_ENTRY {
+            manifest = Some(serde_json::from_slice::<ChunkManifest>(&data).context("invalid manifest")?);
+        } else if let Some(index) = name.strip_prefix("chunk_").and_then(|i| i.parse().ok()) {
+            chunks.insert(index, data);
+        } else {
+            anyhow::bail!("unexpected archive entry {}", name);
+        }
+    }
+    let manifest = manifest.context("archive has no manifest")?;
+
+    if chunks.len() != manifest.chunks.len() {
+        anyhow::bail!("archive has {} chunks, manifest lists {}", chunks.len(), manifest.chunks.len());
+    }
+    let root = MerkleTree::with_arity(&manifest.chunks, manifest.merkle_arity).root();
+    if root != manifest.merkle_root {
+        return Err(FileSharingError::MerkleRootMismatch {
+            hash: manifest.hash.to_string(),
+            expected: manifest.merkle_root.to_hex(),
+            actual: root.to_hex(),
+        }.into());
+    }
+
+    let mut data = Vec::with_capacity(manifest.size as usize);
+    for (index, expected) in manifest.chunks.iter().enumerate() {
+        let chunk = chunks.remove(&index).with_context(|| format!("archive is missing chunk {}", index))?;
+        if HashValue::compute(&chunk, expected.algo) != *expected {
+            return Err(FileSharingError::ChunkCorrupt { index }.into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    if HashValue::compute(&data, manifest.hash.algo) != manifest.hash {
+        return Err(FileSharingError::InvalidHash(
+            format!("reassembled content does not hash to {}", manifest.hash)
+        ).into());
+    }
+    Ok((manifest, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::engine::StorageEngine;
+    use tempfile::TempDir;
+
+    const DATA: &[u8] = b"chunked archive contents";
+
+    fn stored() -> (TempDir, FileMetadata) {
+        let dir = TempDir::new().unwrap();
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        engine.min_free_space = 0;
+        engine.chunk_size = 5;
+        let metadata = engine.store_file(DATA, "f.txt", "alice", None, None).unwrap();
+        (dir, metadata)
+    }
+
+    fn archive_of(metadata: &FileMetadata, chunks: Vec<Vec<u8>>) -> Vec<u8> {
+        build_chunk_archive(metadata, chunks.into_iter().map(Ok)).unwrap()
+    }
+
+    fn chunks() -> Vec<Vec<u8>> {
+        DATA.chunks(5).map(<[u8]>::to_vec).collect()
+    }
+
+    #[test]
+    fn archive_round_trips_through_the_manifest() {
+        let (_dir, metadata) = stored();
+        let (manifest, data) = read_chunk_archive(&archive_of(&metadata, chunks())).unwrap();
+        assert_eq!(data, DATA);
+        assert_eq!(manifest.hash, metadata.hash);
+        assert_eq!(manifest.chunks.len(), 5);
+    }
+
+    #[test]
+    fn tampered_or_missing_chunks_are_rejected() {
+        let (_dir, metadata) = stored();
+        let mut tampered = chunks();
+        tampered[3][0] ^= 0xff;
+        let err = read_chunk_archive(&archive_of(&metadata, tampered)).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::ChunkCorrupt { index: 3 })));
+
+        let mut short = chunks();
+        short.pop();
+        assert!(read_chunk_archive(&archive_of(&metadata, short)).is_err());
+    }
+
+    #[test]
+    fn manifest_with_a_forged_chunk_list_fails_the_merkle_check() {
+        let (_dir, mut metadata) = stored();
+        let mut forged = chunks();
+        forged[0] = b"EVIL!".to_vec();
+        metadata.chunks[0] = HashValue::compute(&forged[0], metadata.chunks[0].algo);
+        let err = read_chunk_archive(&archive_of(&metadata, forged)).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::MerkleRootMismatch { .. })));
+    }
+}
@@ -3,17 +3,82 @@
 // ============================================================================
 
 use crate::crypto::hash::{HashAlgo, HashValue};
-use crate::core::file_metadata::FileMetadata;
-use crate::core::merkle_tree::MerkleTree;
+use crate::core::file_metadata::{FileMetadata, METADATA_FORMAT_VERSION};
+use crate::core::merkle_tree::{MerkleTree, DEFAULT_ARITY};
+use crate::filter::bloom::BloomFilter;
+use crate::error::FileSharingError;
+use crate::storage::stream::{read_chunk, read_chunk_into, ChunkStream, DEFAULT_READ_AHEAD, DEFAULT_READ_BUFFER};
 use anyhow::{Result, Context};
-use serde_json;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Write;
+use rand::RngCore;
 use std::path::{Path, PathBuf};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Default)]
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+// Persisted copy of the content-hash bloom filter, readable without loading the index
+pub const CONTENT_BLOOM_FILE: &str = "content.bloom";
+
+// One stored copy's metadata and its (chunk file, expected hash) pairs
+type CopyChunks<'a> = (Cow<'a, FileMetadata>, Vec<(PathBuf, HashValue)>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetaFormat {
+    #[default]
+    Json,    // human-readable `.meta`
+    Bincode, // compact binary `.metab`, faster to load for large stores
+}
+
+impl MetaFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            MetaFormat::Json => "meta",
+            MetaFormat::Bincode => "metab",
+        }
+    }
+
+    fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "meta" => Some(MetaFormat::Json),
+            "metab" => Some(MetaFormat::Bincode),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, metadata: &FileMetadata) -> Result<Vec<u8>> {
+        Ok(match self {
+            MetaFormat::Json => serde_json::to_vec_pretty(metadata)?,
+            MetaFormat::Bincode => bincode::serialize(metadata)?,
+        })
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<FileMetadata> {
+        Ok(match self {
+            MetaFormat::Json => serde_json::from_slice(bytes)?,
+            MetaFormat::Bincode => FileMetadata::from_bincode(bytes)?,
+        })
+    }
+}
+
+// Whose uploads share stored content. Global dedup lets one user notice (by
+// timing or stats) that someone else already stored the same bytes; PerUser
+// closes that by giving each owner their own copy under storage_dir/<owner>/.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupScope {
+    #[default]
+    Global,
+    PerUser,
+    None, // every store writes its chunks; an owner's repeat lands on the same files
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DedupStats {
     pub total_files: usize,
     pub unique_files: usize,
@@ -21,48 +86,482 @@ pub struct DedupStats {
     pub saved_bytes: u64,
 }
 
+// What is on disk for one expected chunk, checked without reading its contents
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkStatus {
+    pub index: usize,
+    pub hash_prefix: String,
+    pub present: bool,
+    pub size_on_disk: Option<u64>,
+    pub expected_size: Option<u64>, // unknown for metadata written before sizes were recorded
+}
+
+// What `compact` removed from the storage directory
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CompactReport {
+    pub removed_files: usize,
+    pub reclaimed_bytes: u64,
+}
+
 pub struct StorageEngine {
     storage_dir: PathBuf,
     hash_to_path: HashMap<String, PathBuf>,     // hex hash -> file on disk
     hash_to_metadata: HashMap<String, FileMetadata>, // hex hash -> metadata
+    copies: HashMap<String, Vec<PathBuf>>,      // hex hash -> metadata file of every stored copy
     pub dedup_stats: DedupStats,  // Made public
+    content_bloom: BloomFilter,   // fast "definitely new" pre-check before the index lookup
+    // Skipping verification trusts whatever is on disk: a corrupted or
+    // tampered chunk is returned as-is. Only disable for trusted local reads.
+    pub verify_on_read: bool,
+    pub verify_on_write: bool,    // read each chunk back after writing to catch bad disks early
+    pub meta_format: MetaFormat,  // format for newly written metadata; both are read
+    // A final chunk smaller than this is merged into the previous one (0 = never merge)
+    pub merge_tail_threshold: usize,
+    pub read_ahead: usize,        // chunks prefetched by stream_file (0 = read inline)
+    pub read_buffer_size: usize,  // bytes per read when retrieve_file copies chunk data
+    pub hash_algo: HashAlgo,      // whole-file hash for new uploads (the content address)
+    pub chunk_algo: HashAlgo,     // per-chunk and Merkle hash for new uploads
+    pub min_free_space: u64,      // free bytes that must remain after an upload
+    pub merkle_arity: usize,      // children per Merkle node for new files
+    pub chunk_size: usize,        // bytes per chunk for new files
+    pub max_chunks: usize,        // uploads needing more chunks than this are refused
+    // Overwrite chunk bytes with random data before removing them. Best effort only:
+    // SSD wear levelling and copy-on-write filesystems may keep the old blocks.
+    pub secure_delete: bool,
+    // Put new files under storage_dir/<owner>/. With Global dedup_scope, content
+    // already stored stays in the directory of whoever stored it first.
+    pub owner_dirs: bool,
+    pub dedup_scope: DedupScope,  // non-Global scopes always use per-owner directories
+    hash_pool: Option<Arc<ThreadPool>>, // dedicated pool for chunk hashing; None = rayon's global pool
+    #[cfg(any(test, feature = "fault-injection"))]
+    writes_until_fault: std::sync::atomic::AtomicUsize, // chunk writes left before one fails (0 = off)
+    #[cfg(any(test, feature = "fault-injection"))]
+    reads_until_corrupt: std::sync::atomic::AtomicUsize, // chunk reads left before one finds its chunk damaged (0 = off)
+    #[cfg(any(test, feature = "fault-injection"))]
+    corrupt_writes: std::sync::atomic::AtomicUsize, // chunk writes left that land damaged on disk
 }
 
 impl StorageEngine {
     pub fn new(storage_dir: &Path) -> Result<Self> {
         std::fs::create_dir_all(storage_dir)?;
-        Ok(Self {
+        let mut engine = Self {
             storage_dir: storage_dir.to_path_buf(),
             hash_to_path: HashMap::new(),
             hash_to_metadata: HashMap::new(),
+            copies: HashMap::new(),
             dedup_stats: DedupStats::default(),
-        })
+            content_bloom: Self::load_content_bloom(storage_dir)
+                .unwrap_or_else(|| BloomFilter::new(100_000, 0.01)),
+            verify_on_read: true,
+            verify_on_write: true,
+            meta_format: MetaFormat::default(),
+            merge_tail_threshold: 0,
+            read_ahead: DEFAULT_READ_AHEAD,
+            read_buffer_size: DEFAULT_READ_BUFFER,
+            hash_algo: HashAlgo::Sha256,
+            chunk_algo: HashAlgo::Sha256,
+            min_free_space: 64 * 1024 * 1024,
+            merkle_arity: DEFAULT_ARITY,
+            chunk_size: CHUNK_SIZE,
+            max_chunks: 100_000,
+            secure_delete: false,
+            owner_dirs: false,
+            dedup_scope: DedupScope::default(),
+            hash_pool: None,
+            #[cfg(any(test, feature = "fault-injection"))]
+            writes_until_fault: std::sync::atomic::AtomicUsize::new(0),
+            #[cfg(any(test, feature = "fault-injection"))]
+            reads_until_corrupt: std::sync::atomic::AtomicUsize::new(0),
+            #[cfg(any(test, feature = "fault-injection"))]
+            corrupt_writes: std::sync::atomic::AtomicUsize::new(0),
+        };
+        engine.load_index()?;
+        engine.save_content_bloom();
+        Ok(engine)
+    }
+
+    // The filter saved by a previous run, if any. It only ever gains entries, so
+    // it can answer "definitely not stored" before (or without) loading the index.
+    pub fn load_content_bloom(storage_dir: &Path) -> Option<BloomFilter> {
+        let bytes = std::fs::read(storage_dir.join(CONTENT_BLOOM_FILE)).ok()?;
+        bincode::deserialize(&bytes).ok()
+    }
+
+    // Best effort: the filter is a hint, so a failed write never fails the caller
+    fn save_content_bloom(&self) {
+        let path = self.storage_dir.join(CONTENT_BLOOM_FILE);
+        let tmp_path = path.with_extension("bloom.tmp");
+        let result = bincode::serialize(&self.content_bloom)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(std::fs::write(&tmp_path, bytes)?))
+            .and_then(|_| Ok(std::fs::rename(&tmp_path, &path)?));
+        if let Err(e) = result {
+            println!("⚠️  could not save content bloom filter: {}", e);
+        }
+    }
+
+    // Probabilistic: false means the content is definitely not stored,
+    // true means it probably is (confirm with `contains`)
+    pub fn would_dedup(&self, hash: &HashValue) -> bool {
+        self.content_bloom.contains(hash.to_hex().as_bytes())
+    }
+
+    // Files directly in storage_dir and in its per-owner subdirectories
+    fn stored_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(&self.storage_dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                for inner in std::fs::read_dir(&path)? {
+                    paths.push(inner?.path());
+                }
+            } else {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    // Directory new files of `owner` go to: the hex SHA-256 of the username, so
+    // none can escape storage_dir and no two users share one. Usernames match
+    // case-insensitively, so are lowercased first
+    fn owner_dir(&self, owner: &str) -> PathBuf {
+        if !self.owner_dirs && self.dedup_scope == DedupScope::Global {
+            return self.storage_dir.clone();
+        }
+        let name = HashValue::compute(owner.to_ascii_lowercase().as_bytes(), HashAlgo::Sha256).to_hex();
+        self.storage_dir.join(name)
+    }
+
+    // Directory holding an indexed file's chunks: wherever its metadata lives
+    fn file_dir(&self, hex: &str) -> PathBuf {
+        self.hash_to_path.get(hex)
+            .and_then(|path| path.parent())
+            .map_or_else(|| self.storage_dir.clone(), Path::to_path_buf)
+    }
+
+    // Rebuild the in-memory index from the .meta/.metab files on disk.
+    // A corrupt or truncated file is renamed with a .corrupt suffix and skipped.
+    fn load_index(&mut self) -> Result<()> {
+        for path in self.stored_paths()? {
+            let format = match path.extension().and_then(|e| e.to_str()).and_then(MetaFormat::from_extension) {
+                Some(format) => format,
+                None => continue,
+            };
+
+            let parsed = std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| format.decode(&bytes));
+
+            match parsed {
+                Ok(metadata) => {
+                    let hex = metadata.hash.to_hex();
+                    self.dedup_stats.total_files += 1;
+                    self.dedup_stats.unique_files += 1;
+                    self.dedup_stats.total_bytes += metadata.size;
+                    self.index_metadata(hex, path, metadata);
+                }
+                Err(e) => {
+                    let quarantined = path.with_extension(format!("{}.corrupt", format.extension()));
+                    println!("⚠️  corrupt metadata {}: {} -> quarantined as {}",
+                        path.display(), e, quarantined.display());
+                    std::fs::rename(&path, &quarantined)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn split_chunks<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut parts: Vec<&[u8]> = data.chunks(self.chunk_size.max(1)).collect();
+        let tail_too_small = parts.last().is_some_and(|last| last.len() < self.merge_tail_threshold);
+        if parts.len() > 1 && tail_too_small {
+            // The last two chunks are adjacent in `data`, so they merge into one slice
+            let tail = parts.pop().unwrap().len();
+            let prev = parts.pop().unwrap().len();
+            parts.push(&data[data.len() - tail - prev..]);
+        }
+        parts
+    }
+
+    // Write a chunk and, if verify_on_write is set, read it back and check its hash.
+    // A mismatch is retried once before giving up.
+    fn write_chunk(&self, path: &Path, chunk: &[u8], expected: &HashValue) -> Result<()> {
+        #[cfg(any(test, feature = "fault-injection"))]
+        self.take_write_fault(path)?;
+
+        for attempt in 1..=2 {
+            let mut file = File::create(path)?;
+            file.write_all(chunk)?;
+            file.sync_all()?;
+            #[cfg(any(test, feature = "fault-injection"))]
+            self.take_write_corruption(path)?;
+
+            if !self.verify_on_write {
+                return Ok(());
+            }
+            let written = std::fs::read(path)?;
+            if HashValue::compute(&written, expected.algo) == *expected {
+                return Ok(());
+            }
+            println!("⚠️  chunk {} failed read-back verification (attempt {})", path.display(), attempt);
+        }
+        anyhow::bail!("chunk {} is corrupt after write", path.display())
+    }
+
+    fn write_metadata(&self, dir: &Path, hex: &str, metadata: &FileMetadata) -> Result<PathBuf> {
+        let meta_path = dir.join(format!("{}.{}", hex, self.meta_format.extension()));
+        std::fs::write(&meta_path, self.meta_format.encode(metadata)?)?;
+        Ok(meta_path)
+    }
+
+    fn read_metadata(path: &Path) -> Result<FileMetadata> {
+        let format = path.extension().and_then(|e| e.to_str()).and_then(MetaFormat::from_extension)
+            .with_context(|| format!("{} is not a metadata file", path.display()))?;
+        format.decode(&std::fs::read(path)?)
+    }
+
+    // The first copy indexed for a hash serves reads; later ones are only tracked
+    fn index_metadata(&mut self, hex: String, meta_path: PathBuf, metadata: FileMetadata) {
+        self.content_bloom.add(hex.as_bytes());
+        let copies = self.copies.entry(hex.clone()).or_default();
+        if !copies.contains(&meta_path) {
+            copies.push(meta_path.clone());
+        }
+        self.hash_to_path.entry(hex.clone()).or_insert(meta_path);
+        self.hash_to_metadata.entry(hex).or_insert(metadata);
+    }
+
+    // Metadata file of `owner`'s own copy of a hash, if they have one
+    fn owner_copy(&self, hex: &str, owner: &str) -> Option<PathBuf> {
+        let dir = self.owner_dir(owner);
+        self.copies.get(hex)?.iter()
+            .find(|path| path.parent() == Some(dir.as_path()))
+            .cloned()
+    }
+
+    // Metadata file of the copy reads on behalf of `owner` use: their own if they
+    // have one (per-user scopes), otherwise the first one indexed
+    fn copy_path(&self, hex: &str, owner: Option<&str>) -> Option<PathBuf> {
+        owner.and_then(|owner| self.owner_copy(hex, owner))
+            .or_else(|| self.hash_to_path.get(hex).cloned())
     }
 
-    pub fn store_file(&mut self, data: &[u8], filename: &str, owner: &str) -> Result<FileMetadata> {
-        let hash = HashValue::compute(data, HashAlgo::Sha256);
+    // Metadata files of every copy of a hash
+    fn copy_paths(&self, hex: &str) -> Vec<PathBuf> {
+        match self.copies.get(hex) {
+            Some(copies) if !copies.is_empty() => copies.clone(),
+            _ => self.hash_to_path.get(hex).cloned().into_iter().collect(),
+        }
+    }
+
+    // A copy's directory relative to storage_dir ("" for storage_dir itself)
+    fn copy_name(&self, meta_path: &Path) -> String {
+        meta_path.parent()
+            .and_then(|dir| dir.strip_prefix(&self.storage_dir).ok())
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    // Names the copy `owner` reads (see copy_path), for recording which copy
+    // of a hash failed verification
+    pub fn copy_id(&self, hash: &HashValue, owner: Option<&str>) -> Option<String> {
+        self.copy_path(&hash.to_hex(), owner).map(|path| self.copy_name(&path))
+    }
+
+    // Metadata and chunk files of one copy. Copies may have been chunked under
+    // different settings, so any but the first is read from its own metadata file.
+    fn copy_chunks(&self, hex: &str, meta_path: &Path) -> Result<CopyChunks<'_>> {
+        let metadata = match (self.hash_to_path.get(hex), self.hash_to_metadata.get(hex)) {
+            (Some(primary), Some(metadata)) if primary == meta_path => Cow::Borrowed(metadata),
+            _ => Cow::Owned(Self::read_metadata(meta_path)?),
+        };
+        let dir = meta_path.parent().unwrap_or(&self.storage_dir);
+        let paths = metadata.chunks.iter().enumerate()
+            .map(|(i, chunk_hash)| (dir.join(format!("{}_{}.chunk", hex, i)), chunk_hash.clone()))
+            .collect();
+        Ok((metadata, paths))
+    }
+
+    fn owner_chunks(&self, hash: &HashValue, owner: Option<&str>) -> Result<CopyChunks<'_>> {
+        let hex = hash.to_hex();
+        let meta_path = self.copy_path(&hex, owner).context("file not found")?;
+        self.copy_chunks(&hex, &meta_path)
+    }
+
+    // What a store of existing content may reuse under the dedup scope
+    fn dedup_target(&self, hex: &str, owner: &str) -> Option<FileMetadata> {
+        match self.dedup_scope {
+            DedupScope::Global => self.hash_to_metadata.get(hex).cloned(),
+            DedupScope::PerUser => {
+                let path = self.owner_copy(hex, owner)?;
+                Self::read_metadata(&path).ok()
+            }
+            DedupScope::None => None,
+        }
+    }
+
+    // Regenerate a missing or corrupt .meta from the chunk files on disk.
+    // Filename, owner and the Merkle root recorded at upload are not recoverable
+    // from chunks and must come from the caller (DB). The file may predate the
+    // current chunk_algo and merkle_arity, so the settings that reproduce
+    // `expected_root` are searched for; without a match nothing is written.
+    pub fn repair_metadata(&mut self, hash: &HashValue, filename: &str, owner: &str, expected_root: &str) -> Result<FileMetadata> {
+        let hex = hash.to_hex();
+        let dir = if self.hash_to_path.contains_key(&hex) {
+            self.file_dir(&hex)
+        } else {
+            [self.owner_dir(owner), self.storage_dir.clone()].into_iter()
+                .find(|dir| dir.join(format!("{}_0.chunk", hex)).exists())
+                .unwrap_or_else(|| self.storage_dir.clone())
+        };
+
+        let mut data = Vec::new();
+        let mut chunk_data = Vec::new();
+        let mut chunk_sizes = Vec::new();
+        for i in 0.. {
+            let chunk_path = dir.join(format!("{}_{}.chunk", hex, i));
+            if !chunk_path.exists() {
+                break;
+            }
+            let bytes = std::fs::read(&chunk_path)?;
+            chunk_sizes.push(bytes.len() as u64);
+            data.extend_from_slice(&bytes);
+            chunk_data.push(bytes);
+        }
+
+        let computed = HashValue::compute(&data, hash.algo);
+        if computed != *hash {
+            anyhow::bail!("cannot repair {}: chunks on disk do not match the file hash", hash.prefix(8));
+        }
+
+        // Current settings first; any arity from the chunk count up builds the same one-level tree
+        let algos = std::iter::once(self.chunk_algo).chain(HashAlgo::ALL.into_iter().filter(|a| *a != self.chunk_algo));
+        let arities: Vec<usize> = [self.merkle_arity, DEFAULT_ARITY].into_iter()
+            .chain(3..=16)
+            .chain([chunk_data.len()])
+            .collect();
+        let (chunks, merkle_tree) = algos
+            .map(|algo| chunk_data.iter().map(|c| HashValue::compute(c, algo)).collect::<Vec<_>>())
+            .find_map(|chunks| {
+                let tree = arities.iter()
+                    .map(|&arity| MerkleTree::with_arity(&chunks, arity))
+                    .find(|tree| tree.root().to_hex() == expected_root)?;
+                Some((chunks, tree))
+            })
+            .with_context(|| format!(
+                "cannot repair {}: no chunk algorithm and arity reproduce the recorded Merkle root",
+                hash.prefix(8),
+            ))?;
+
+        let now = Utc::now();
+        let metadata = FileMetadata {
+            path: PathBuf::from(filename),
+            size: data.len() as u64,
+            hash: hash.clone(),
+            merkle_root: merkle_tree.root(),
+            merkle_arity: merkle_tree.arity(),
+            chunks,
+            chunk_sizes,
+            created_at: now,
+            modified_at: now,
+            owner: owner.to_string(),
+            format_version: METADATA_FORMAT_VERSION,
+        };
+
+        let meta_path = self.write_metadata(&dir, &hex, &metadata)?;
+        if !self.hash_to_metadata.contains_key(&hex) {
+            self.dedup_stats.total_files += 1;
+            self.dedup_stats.unique_files += 1;
+            self.dedup_stats.total_bytes += metadata.size;
+        }
+        self.index_metadata(hex, meta_path, metadata.clone());
+        self.save_content_bloom();
+
+        println!("🔧 metadata repaired: {} ({} chunks)", filename, metadata.chunks.len());
+        Ok(metadata)
+    }
+
+    // Hash chunks on a dedicated pool of `threads` workers, built once here and
+    // reused, so a host application's own rayon work isn't contended. None
+    // goes back to the global pool.
+    pub fn set_hash_threads(&mut self, threads: Option<usize>) -> Result<()> {
+        self.hash_pool = match threads {
+            Some(n) => Some(Arc::new(ThreadPoolBuilder::new()
+                .num_threads(n.max(1))
+                .thread_name(|i| format!("sfs-hash-{}", i))
+                .build()?)),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn hash_chunks(&self, parts: &[&[u8]]) -> Vec<HashValue> {
+        let algo = self.chunk_algo;
+        let hash_all = || parts.par_iter().map(|chunk| HashValue::compute(chunk, algo)).collect();
+        match &self.hash_pool {
+            Some(pool) => pool.install(hash_all),
+            None => hash_all(),
+        }
+    }
+
+    // Timestamps default to now; imports pass the original ones. They only apply
+    // to newly stored content, a duplicate keeps the existing metadata.
+    pub fn store_file(
+        &mut self,
+        data: &[u8],
+        filename: &str,
+        owner: &str,
+        created_at: Option<DateTime<Utc>>,
+        modified_at: Option<DateTime<Utc>>,
+    ) -> Result<FileMetadata> {
+        let hash = HashValue::compute(data, self.hash_algo);
         let hex = hash.to_hex();
         
-        // Deduplication: if file exists, return metadata only
-        if let Some(existing) = self.hash_to_metadata.get(&hex) {
+        // Deduplication: if file exists, return metadata only.
+        // The bloom filter never gives false negatives, so a miss means definitely new;
+        // a hit still goes through the authoritative index.
+        let existing = if self.content_bloom.contains(hex.as_bytes()) {
+            self.dedup_target(&hex, owner)
+        } else {
+            None
+        };
+        if let Some(existing) = existing {
             self.dedup_stats.total_files += 1;
             self.dedup_stats.total_bytes += data.len() as u64;
             self.dedup_stats.saved_bytes += data.len() as u64;
             println!("♻️  duplicate detected: {} -> refers to existing file", filename);
-            return Ok(existing.clone());
+            return Ok(existing);
+        }
+
+        // Keep metadata and the Merkle tree bounded
+        let count = data.len().div_ceil(self.chunk_size.max(1));
+        if count > self.max_chunks {
+            return Err(FileSharingError::TooManyChunks { count, max: self.max_chunks }.into());
+        }
+
+        // Refuse up front rather than fail halfway through the chunks
+        let available = fs2::available_space(&self.storage_dir)?;
+        let needed = data.len() as u64 + self.min_free_space;
+        if available < needed {
+            return Err(FileSharingError::InsufficientSpace { needed, available }.into());
         }
 
-        // New file - split into 1MB chunks
-        let chunks: Vec<HashValue> = data.chunks(1024 * 1024).enumerate().map(|(i, chunk)| {
-            let chunk_hash = HashValue::compute(chunk, HashAlgo::Sha256);
-            let chunk_path = self.storage_dir.join(format!("{}_{}.chunk", hex, i));
-            let mut file = File::create(&chunk_path).unwrap();
-            file.write_all(chunk).unwrap();
-            chunk_hash
-        }).collect();
+        // New file - split into chunks
+        let dir = self.owner_dir(owner);
+        std::fs::create_dir_all(&dir)?;
+        let parts = self.split_chunks(data);
+        let chunk_sizes: Vec<u64> = parts.iter().map(|p| p.len() as u64).collect();
+        let chunk_hashes = self.hash_chunks(&parts);
+        let chunks: Vec<HashValue> = parts.into_iter().zip(chunk_hashes).enumerate().map(|(i, (chunk, chunk_hash))| {
+            let chunk_path = dir.join(format!("{}_{}.chunk", hex, i));
+            self.write_chunk(&chunk_path, chunk, &chunk_hash)
+                .with_context(|| format!("failed to write chunk {} of {}", i, filename))?;
+            Ok(chunk_hash)
+        }).collect::<Result<_>>()?;
 
         // Build Merkle Tree
-        let merkle_tree = MerkleTree::new(&chunks);
+        let merkle_tree = MerkleTree::with_arity(&chunks, self.merkle_arity);
         let merkle_root = merkle_tree.root();
 
         // Save metadata
@@ -71,22 +570,26 @@ impl StorageEngine {
             size: data.len() as u64,
             hash: hash.clone(),
             chunks: chunks.clone(),  // Clone here
+            chunk_sizes,
             merkle_root,
-            created_at: Utc::now(),
-            modified_at: Utc::now(),
+            merkle_arity: merkle_tree.arity(),
+            created_at: created_at.unwrap_or_else(Utc::now),
+            modified_at: modified_at.or(created_at).unwrap_or_else(Utc::now),
             owner: owner.to_string(),
+            format_version: METADATA_FORMAT_VERSION,
         };
 
-        let meta_path = self.storage_dir.join(format!("{}.meta", hex));
-        let meta_json = serde_json::to_string_pretty(&metadata)?;
-        std::fs::write(&meta_path, meta_json)?;
+        let meta_path = self.write_metadata(&dir, &hex, &metadata)?;
 
-        // Update state
-        self.hash_to_path.insert(hex.clone(), meta_path);
-        self.hash_to_metadata.insert(hex, metadata.clone());
+        // Update state; a DedupScope::None repeat by the same owner rewrote an existing copy
+        let new_copy = !self.copies.get(&hex).is_some_and(|copies| copies.contains(&meta_path));
+        self.index_metadata(hex, meta_path, metadata.clone());
+        self.save_content_bloom();
         
         self.dedup_stats.total_files += 1;
-        self.dedup_stats.unique_files += 1;
+        if new_copy {
+            self.dedup_stats.unique_files += 1;
+        }
         self.dedup_stats.total_bytes += data.len() as u64;
 
         println!(" new file stored: {} ({} bytes, {} chunks)", 
@@ -96,25 +599,451 @@ impl StorageEngine {
     }
 
     pub fn retrieve_file(&self, hash: &HashValue) -> Result<Vec<u8>> {
+        self.read_chunks(hash, None, self.verify_on_read)
+    }
+
+    // Always verifies chunk hashes, regardless of `verify_on_read`
+    pub fn retrieve_file_verified(&self, hash: &HashValue) -> Result<Vec<u8>> {
+        self.read_chunks(hash, None, true)
+    }
+
+    // retrieve_file_verified from the copy `owner` reads (see copy_path)
+    pub fn retrieve_copy_verified(&self, hash: &HashValue, owner: Option<&str>) -> Result<Vec<u8>> {
+        self.read_chunks(hash, owner, true)
+    }
+
+    // Metadata of the copy `owner` reads
+    pub fn copy_metadata(&self, hash: &HashValue, owner: Option<&str>) -> Option<FileMetadata> {
+        self.owner_chunks(hash, owner).ok().map(|(metadata, _)| metadata.into_owned())
+    }
+
+    // Verified bytes [start, end) of the copy `owner` reads, reading only the
+    // chunks that overlap the range. Files stored before chunk sizes were
+    // recorded are read whole.
+    pub fn read_range(&self, hash: &HashValue, owner: Option<&str>, start: u64, end: u64) -> Result<Vec<u8>> {
+        let (metadata, chunk_paths) = self.owner_chunks(hash, owner)?;
+        let end = end.min(metadata.size);
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        if metadata.chunk_sizes.len() != metadata.chunks.len() {
+            let data = self.retrieve_copy_verified(hash, owner)?;
+            return Ok(data[start as usize..end as usize].to_vec());
+        }
+
+        let mut out = Vec::with_capacity((end - start) as usize);
+        let mut offset = 0u64;
+        let mut chunk = Vec::new();
+        for (i, (chunk_path, chunk_hash)) in chunk_paths.iter().enumerate() {
+            let chunk_end = offset + metadata.chunk_sizes[i];
+            if chunk_end > start && offset < end {
+                #[cfg(any(test, feature = "fault-injection"))]
+                self.take_read_corruption(chunk_path)?;
+                chunk.clear();
+                read_chunk_into(chunk_path, chunk_hash, i, true, &mut chunk, self.read_buffer_size)?;
+                let from = start.saturating_sub(offset) as usize;
+                let to = (end.min(chunk_end) - offset) as usize;
+                out.extend_from_slice(&chunk[from..to]);
+            }
+            if chunk_end >= end {
+                break;
+            }
+            offset = chunk_end;
+        }
+        Ok(out)
+    }
+
+    // Chunks are read straight into one buffer sized from the metadata up front
+    fn read_chunks(&self, hash: &HashValue, owner: Option<&str>, verify: bool) -> Result<Vec<u8>> {
+        let (metadata, chunk_paths) = self.owner_chunks(hash, owner)?;
+        let mut full_data = Vec::with_capacity(metadata.size as usize);
+        for (i, (chunk_path, chunk_hash)) in chunk_paths.iter().enumerate() {
+            #[cfg(any(test, feature = "fault-injection"))]
+            self.take_read_corruption(chunk_path)?;
+            read_chunk_into(chunk_path, chunk_hash, i, verify, &mut full_data, self.read_buffer_size)?;
+        }
+        Ok(full_data)
+    }
+
+    // Overwrite one chunk with bytes that must hash to the recorded chunk hash,
+    // in every copy that records that hash for it. The new bytes go to a temp
+    // file first so a failed write leaves the old chunk alone.
+    pub fn replace_chunk(&self, hash: &HashValue, index: usize, data: &[u8]) -> Result<()> {
+        let hex = hash.to_hex();
+        let (_, expected) = self.chunk_paths(hash)?.into_iter().nth(index)
+            .with_context(|| format!("file {} has no chunk {}", hash.prefix(8), index))?;
+
+        let mut replaced = 0;
+        for meta_path in self.copy_paths(&hex) {
+            let (chunk_path, expected) = match self.copy_chunks(&hex, &meta_path)?.1.into_iter().nth(index) {
+                Some((path, chunk_hash)) if HashValue::compute(data, chunk_hash.algo) == chunk_hash => (path, chunk_hash),
+                _ => continue,
+            };
+            let tmp_path = chunk_path.with_extension("chunk.tmp");
+            self.write_chunk(&tmp_path, data, &expected)?;
+            std::fs::rename(&tmp_path, &chunk_path)?;
+            read_chunk(&chunk_path, &expected, index, true)?;
+            replaced += 1;
+        }
+        if replaced == 0 {
+            return Err(FileSharingError::ChunkMismatch { index, expected: expected.to_hex() }.into());
+        }
+
+        println!("🩹 chunk {} of {} replaced", index, hash.prefix(8));
+        Ok(())
+    }
+
+    // Chunk-by-chunk reader for large downloads; chunks are always verified
+    pub fn stream_file(&self, hash: &HashValue) -> Result<ChunkStream> {
+        Ok(ChunkStream::new(self.stream_paths(hash)?, true, self.read_ahead))
+    }
+
+    // Strictly lazy verified reader: nothing past the consumer's position is read,
+    // so a corrupt chunk is reported (as ChunkCorrupt) before any later one is touched
+    pub fn verify_stream(&self, hash: &HashValue) -> Result<ChunkStream> {
+        Ok(ChunkStream::new(self.stream_paths(hash)?, true, 0))
+    }
+
+    // Streams read on their own thread, so injected read faults are applied to
+    // each chunk as the stream is opened, in chunk order
+    fn stream_paths(&self, hash: &HashValue) -> Result<Vec<(PathBuf, HashValue)>> {
+        let paths = self.chunk_paths(hash)?;
+        #[cfg(any(test, feature = "fault-injection"))]
+        for (path, _) in &paths {
+            self.take_read_corruption(path)?;
+        }
+        Ok(paths)
+    }
+
+    // Chunk files of the first-indexed copy
+    fn chunk_paths(&self, hash: &HashValue) -> Result<Vec<(PathBuf, HashValue)>> {
+        Ok(self.owner_chunks(hash, None)?.1)
+    }
+
+    #[cfg(any(test, feature = "fault-injection"))]
+    fn take_write_fault(&self, path: &Path) -> Result<()> {
+        use std::sync::atomic::Ordering;
+        let previous = self.writes_until_fault
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .unwrap_or(0);
+        if previous == 1 {
+            anyhow::bail!("injected write failure for {}", path.display());
+        }
+        Ok(())
+    }
+
+    #[cfg(any(test, feature = "fault-injection"))]
+    fn take_read_corruption(&self, path: &Path) -> Result<()> {
+        use std::sync::atomic::Ordering;
+        let previous = self.reads_until_corrupt
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .unwrap_or(0);
+        if previous == 1 {
+            flip_first_byte(path)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(any(test, feature = "fault-injection"))]
+    fn take_write_corruption(&self, path: &Path) -> Result<()> {
+        use std::sync::atomic::Ordering;
+        let armed = self.corrupt_writes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if armed {
+            flip_first_byte(path)?;
+        }
+        Ok(())
+    }
+
+    // Test-only (cfg(test) or the fault-injection feature): flip the first byte
+    // of a stored chunk so it no longer matches its hash. These hooks give the
+    // integrity, repair and reconcile tests deterministic damage to work on.
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn inject_corrupt_chunk(&self, hash: &HashValue, index: usize) -> Result<()> {
+        let (path, _) = self.chunk_paths(hash)?.into_iter().nth(index)
+            .with_context(|| format!("file {} has no chunk {}", hash.prefix(8), index))?;
+        flip_first_byte(&path)
+    }
+
+    // inject_corrupt_chunk on the copy `owner` reads
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn inject_corrupt_copy_chunk(&self, hash: &HashValue, owner: &str, index: usize) -> Result<()> {
+        let (path, _) = self.owner_chunks(hash, Some(owner))?.1.into_iter().nth(index)
+            .with_context(|| format!("file {} has no chunk {}", hash.prefix(8), index))?;
+        flip_first_byte(&path)
+    }
+
+    // Remove a stored chunk file, leaving the metadata in place
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn inject_missing_chunk(&self, hash: &HashValue, index: usize) -> Result<()> {
+        let (path, _) = self.chunk_paths(hash)?.into_iter().nth(index)
+            .with_context(|| format!("file {} has no chunk {}", hash.prefix(8), index))?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    // Damage what the next `count` chunk writes put on disk, as a bad disk would
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn inject_write_corruption(&self, count: usize) {
+        self.corrupt_writes.store(count, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    // Make the `nth` chunk write from now on fail (1 = the next one, 0 = disarm)
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn inject_write_failure(&self, nth: usize) {
+        self.writes_until_fault.store(nth, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    // Damage the chunk behind the `nth` chunk read from now on (1 = the next
+    // one, 0 = disarm), as bit rot first noticed by a reader would be
+    #[cfg(any(test, feature = "fault-injection"))]
+    pub fn inject_read_corruption(&self, nth: usize) {
+        self.reads_until_corrupt.store(nth, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn chunk_status(&self, hash: &HashValue) -> Result<Vec<ChunkStatus>> {
+        let metadata = self.metadata(hash).context("file not found")?;
+
+        Ok(self.chunk_paths(hash)?.into_iter().enumerate()
+            .map(|(index, (path, chunk_hash))| {
+                let size_on_disk = std::fs::metadata(&path).ok().map(|m| m.len());
+                ChunkStatus {
+                    index,
+                    hash_prefix: chunk_hash.prefix(8),
+                    present: size_on_disk.is_some(),
+                    size_on_disk,
+                    expected_size: metadata.chunk_sizes.get(index).copied(),
+                }
+            })
+            .collect())
+    }
+
+    pub fn metadata(&self, hash: &HashValue) -> Option<&FileMetadata> {
+        self.hash_to_metadata.get(&hash.to_hex())
+    }
+
+    pub fn hashes(&self) -> Vec<HashValue> {
+        let mut hashes: Vec<HashValue> = self.hash_to_metadata.values()
+            .map(|m| m.hash.clone())
+            .collect();
+        hashes.sort_by_key(|h| h.to_hex());
+        hashes
+    }
+
+    // Root of roots: a single hash over every file's Merkle root, ordered by
+    // file hash, that changes whenever a file is added, removed or altered
+    pub fn store_merkle_root(&self) -> HashValue {
+        let roots: Vec<HashValue> = self.hashes().iter()
+            .filter_map(|hash| self.metadata(hash))
+            .map(|m| m.merkle_root.clone())
+            .collect();
+        MerkleTree::new(&roots).root()
+    }
+
+    // Re-verify every copy of every stored file; returns the failures with the reason
+    pub fn verify_all(&self) -> Vec<(HashValue, String)> {
+        let hashes = self.hashes();
+        let results = self.verify_files(&hashes, None);
+        hashes.into_iter().zip(results)
+            .flat_map(|(hash, failures)| failures.into_iter().map(move |(_, e)| (hash.clone(), e.to_string())))
+            .collect()
+    }
+
+    // Verify several files in parallel (on the hash pool, if one is set): every
+    // copy of each, or with `owner` only the copy they read. Returns each
+    // input's failures, by copy_id, in input order. Chunks are checked and
+    // dropped as they are read, so memory stays at a chunk per copy in flight.
+    pub fn verify_files(&self, hashes: &[HashValue], owner: Option<&str>) -> Vec<Vec<(String, anyhow::Error)>> {
+        let verify_copy = |hex: &str, meta_path: &Path| -> Result<()> {
+            let (_, paths) = self.copy_chunks(hex, meta_path)?;
+            #[cfg(any(test, feature = "fault-injection"))]
+            for (path, _) in &paths {
+                self.take_read_corruption(path)?;
+            }
+            for chunk in ChunkStream::new(paths, true, 0) {
+                chunk?;
+            }
+            Ok(())
+        };
+        let verify_one = |hash: &HashValue| -> Vec<(String, anyhow::Error)> {
+            let hex = hash.to_hex();
+            let copies = match owner {
+                Some(owner) => self.copy_path(&hex, Some(owner)).into_iter().collect(),
+                None => self.copy_paths(&hex),
+            };
+            if copies.is_empty() {
+                return vec![(String::new(), anyhow::anyhow!("file not found"))];
+            }
+            copies.iter()
+                .filter_map(|meta_path| verify_copy(&hex, meta_path).err().map(|e| (self.copy_name(meta_path), e)))
+                .collect()
+        };
+        let verify_all = || hashes.par_iter().map(verify_one).collect();
+        match &self.hash_pool {
+            Some(pool) => pool.install(verify_all),
+            None => verify_all(),
+        }
+    }
+
+    // Whether a file can be created in the storage directory right now
+    pub fn is_writable(&self) -> bool {
+        let probe = self.storage_dir.join(".write_probe");
+        let ok = std::fs::write(&probe, b"ok").is_ok();
+        let _ = std::fs::remove_file(&probe);
+        ok
+    }
+
+    pub fn free_space(&self) -> Result<u64> {
+        Ok(fs2::available_space(&self.storage_dir)?)
+    }
+
+    pub fn contains(&self, hash: &HashValue) -> bool {
+        self.hash_to_metadata.contains_key(&hash.to_hex())
+    }
+
+    fn overwrite_with_random(path: &Path) -> Result<()> {
+        let len = std::fs::metadata(path)?.len() as usize;
+        let mut noise = vec![0u8; len.min(CHUNK_SIZE)];
+        let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+        let mut written = 0;
+        while written < len {
+            let n = noise.len().min(len - written);
+            rand::thread_rng().fill_bytes(&mut noise[..n]);
+            file.write_all(&noise[..n])?;
+            written += n;
+        }
+        file.sync_all()?;
+        Ok(())
+    }
+
+    // Chunks and metadata file of one stored copy
+    fn remove_copy_files(&self, hex: &str, meta_path: &Path, chunk_count: usize) -> Result<()> {
+        let dir = meta_path.parent().unwrap_or(&self.storage_dir);
+        for i in 0..chunk_count {
+            let chunk_path = dir.join(format!("{}_{}.chunk", hex, i));
+            if chunk_path.exists() {
+                if self.secure_delete {
+                    Self::overwrite_with_random(&chunk_path)?;
+                }
+                std::fs::remove_file(&chunk_path)?;
+            }
+        }
+        if meta_path.exists() {
+            std::fs::remove_file(meta_path)?;
+        }
+        Ok(())
+    }
+
+    // Remove a file's chunks and metadata, every copy of them. Callers must ensure
+    // no other owner still references the content, since chunks are shared by dedup.
+    pub fn delete_file(&mut self, hash: &HashValue) -> Result<()> {
         let hex = hash.to_hex();
-        let metadata = self.hash_to_metadata.get(&hex)
+        let metadata = self.hash_to_metadata.remove(&hex)
             .context("file not found")?;
+        let primary = self.hash_to_path.remove(&hex);
+        let copies = self.copies.remove(&hex)
+            .unwrap_or_else(|| primary.into_iter().collect());
 
-        let mut full_data = Vec::new();
-        for (i, chunk_hash) in metadata.chunks.iter().enumerate() {
-            let chunk_path = self.storage_dir.join(format!("{}_{}.chunk", hex, i));
-            let mut file = File::open(chunk_path)?;
-            let mut chunk_data = Vec::new();
-            file.read_to_end(&mut chunk_data)?;
-            
-            // Verify chunk integrity
-            let computed = HashValue::compute(&chunk_data, HashAlgo::Sha256);
-            if computed != *chunk_hash {
-                anyhow::bail!("chunk {} integrity check failed", i);
+        for meta_path in &copies {
+            let chunk_count = Self::read_metadata(meta_path).map_or(metadata.chunks.len(), |m| m.chunks.len());
+            self.remove_copy_files(&hex, meta_path, chunk_count)?;
+        }
+
+        self.dedup_stats.total_files = self.dedup_stats.total_files.saturating_sub(copies.len());
+        self.dedup_stats.unique_files = self.dedup_stats.unique_files.saturating_sub(copies.len());
+        self.dedup_stats.total_bytes = self.dedup_stats.total_bytes.saturating_sub(metadata.size * copies.len() as u64);
+
+        println!("🗑️  file removed: {}", hash.prefix(8));
+        Ok(())
+    }
+
+    // Remove `owner`'s own copy while another copy of the content remains, e.g.
+    // once they no longer reference it under a per-user dedup scope. Returns
+    // false, leaving everything in place, when there is no such second copy.
+    pub fn remove_copy(&mut self, hash: &HashValue, owner: &str) -> Result<bool> {
+        let hex = hash.to_hex();
+        let meta_path = match self.owner_copy(&hex, owner) {
+            Some(path) if self.copies.get(&hex).is_some_and(|copies| copies.len() > 1) => path,
+            _ => return Ok(false),
+        };
+        let (chunk_count, size) = match self.hash_to_metadata.get(&hex) {
+            Some(metadata) => (metadata.chunks.len(), metadata.size),
+            None => return Ok(false),
+        };
+        let chunk_count = Self::read_metadata(&meta_path).map_or(chunk_count, |m| m.chunks.len());
+
+        self.remove_copy_files(&hex, &meta_path, chunk_count)?;
+        let copies = self.copies.entry(hex.clone()).or_default();
+        copies.retain(|path| *path != meta_path);
+        if self.hash_to_path.get(&hex) == Some(&meta_path) {
+            let next = copies[0].clone();
+            if let Ok(metadata) = Self::read_metadata(&next) {
+                self.hash_to_metadata.insert(hex.clone(), metadata);
             }
-            full_data.extend(chunk_data);
+            self.hash_to_path.insert(hex, next);
         }
-        Ok(full_data)
+
+        self.dedup_stats.total_files = self.dedup_stats.total_files.saturating_sub(1);
+        self.dedup_stats.unique_files = self.dedup_stats.unique_files.saturating_sub(1);
+        self.dedup_stats.total_bytes = self.dedup_stats.total_bytes.saturating_sub(size);
+
+        println!("🗑️  copy of {} removed for {}", hash.prefix(8), owner);
+        Ok(true)
+    }
+
+    // Whether `path` is a chunk (or leftover chunk temp file) that some indexed
+    // file still expects, in that file's directory. Chunks are named
+    // `<file hex>_<index>.chunk`.
+    fn is_referenced_chunk(&self, path: &Path) -> bool {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let stem = match name.strip_suffix(".chunk") {
+            Some(stem) => stem,
+            None => return false,
+        };
+        let (hex, index) = match stem.rsplit_once('_') {
+            Some(parts) => parts,
+            None => return false,
+        };
+        let index = match index.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => return false,
+        };
+        // Copies can differ in chunk count, so go by the one in this directory
+        self.copy_paths(hex).iter()
+            .find(|copy| copy.parent() == path.parent())
+            .and_then(|copy| self.copy_chunks(hex, copy).ok())
+            .is_some_and(|(_, paths)| index < paths.len())
+    }
+
+    // Remove chunk files no indexed metadata refers to, e.g. left behind by a
+    // failed upload or an interrupted delete. Takes `&mut self` so it can never
+    // run alongside a store_file on the same engine.
+    pub fn compact(&mut self) -> Result<CompactReport> {
+        let mut report = CompactReport::default();
+
+        for path in self.stored_paths()? {
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+            let is_chunk_file = name.ends_with(".chunk") || name.ends_with(".chunk.tmp");
+            if !is_chunk_file || self.is_referenced_chunk(&path) {
+                continue;
+            }
+
+            let size = std::fs::metadata(&path)?.len();
+            if self.secure_delete {
+                Self::overwrite_with_random(&path)?;
+            }
+            std::fs::remove_file(&path)?;
+            report.removed_files += 1;
+            report.reclaimed_bytes += size;
+        }
+
+        println!("🧹 compacted storage: {} file(s), {} bytes reclaimed",
+            report.removed_files, report.reclaimed_bytes);
+        Ok(report)
     }
 
     pub fn stats(&self) -> f64 {
@@ -124,4 +1053,590 @@ impl StorageEngine {
             (self.dedup_stats.saved_bytes as f64 / self.dedup_stats.total_bytes as f64) * 100.0
         }
     }
-}
\ No newline at end of file
+}
+
+// Damage that keeps the length, so only a hash check notices; an empty chunk gains a byte
+#[cfg(any(test, feature = "fault-injection"))]
+fn flip_first_byte(path: &Path) -> Result<()> {
+    let mut data = std::fs::read(path)?;
+    match data.first_mut() {
+        Some(byte) => *byte ^= 0xff,
+        None => data.push(0),
+    }
+    std::fs::write(path, data)?;
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn engine() -> (TempDir, StorageEngine) {
+        let dir = TempDir::new().unwrap();
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        engine.min_free_space = 0;
+        engine.chunk_size = 4;
+        (dir, engine)
+    }
+
+    // Same-length damage, the kind that only a hash check notices
+    fn flip_byte(engine: &StorageEngine, hash: &HashValue, index: usize) {
+        let (path, _) = engine.chunk_paths(hash).unwrap().swap_remove(index);
+        let mut data = std::fs::read(&path).unwrap();
+        data[0] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+    }
+
+    #[test]
+    fn unverified_read_returns_corrupt_chunk_as_is() {
+        let (_dir, mut engine) = engine();
+        let stored = engine.store_file(b"abcdefghij", "f.txt", "alice", None, None).unwrap();
+        flip_byte(&engine, &stored.hash, 1);
+
+        engine.verify_on_read = false;
+        let data = engine.retrieve_file(&stored.hash).unwrap();
+        assert_eq!(data.len(), 10);
+        assert_eq!(&data[..4], b"abcd");
+        assert_eq!(data[4], b'e' ^ 0xff);
+    }
+
+    #[test]
+    fn verified_read_catches_corruption() {
+        let (_dir, mut engine) = engine();
+        let stored = engine.store_file(b"abcdefghij", "f.txt", "alice", None, None).unwrap();
+        flip_byte(&engine, &stored.hash, 1);
+
+        assert!(engine.retrieve_file(&stored.hash).is_err());
+        engine.verify_on_read = false;
+        assert!(engine.retrieve_file_verified(&stored.hash).is_err());
+    }
+
+    #[test]
+    fn bloom_precheck_never_dedups_new_content() {
+        let (_dir, mut engine) = engine();
+        for i in 0..100u32 {
+            let data = format!("file number {}", i);
+            let stored = engine.store_file(data.as_bytes(), "f.txt", "alice", None, None).unwrap();
+            assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), data.as_bytes());
+        }
+        assert_eq!(engine.dedup_stats.unique_files, 100);
+        assert_eq!(engine.dedup_stats.saved_bytes, 0);
+    }
+
+    #[test]
+    fn bloom_precheck_still_dedups_repeats() {
+        let (_dir, mut engine) = engine();
+        let first = engine.store_file(b"same bytes", "a.txt", "alice", None, None).unwrap();
+        assert!(engine.would_dedup(&first.hash));
+        let again = engine.store_file(b"same bytes", "b.txt", "alice", None, None).unwrap();
+        assert_eq!(again.hash, first.hash);
+        assert_eq!(engine.dedup_stats.unique_files, 1);
+        assert_eq!(engine.dedup_stats.saved_bytes, 10);
+    }
+
+    #[test]
+    fn truncated_meta_is_quarantined_and_the_rest_loads() {
+        let (dir, mut engine) = engine();
+        let good = engine.store_file(b"valid content", "good.txt", "alice", None, None).unwrap();
+        let bad_path = dir.path().join(format!("{}.meta", "ab".repeat(32)));
+        std::fs::write(&bad_path, b"{\"path\": \"bad.txt\", \"si").unwrap();
+        drop(engine);
+
+        let engine = StorageEngine::new(dir.path()).unwrap();
+        assert_eq!(engine.hashes(), vec![good.hash.clone()]);
+        assert_eq!(engine.retrieve_file(&good.hash).unwrap(), b"valid content");
+        assert!(!bad_path.exists());
+        assert!(bad_path.with_extension("meta.corrupt").exists());
+    }
+
+    #[test]
+    fn repair_metadata_rebuilds_from_chunks() {
+        let (dir, mut engine) = engine();
+        let stored = engine.store_file(b"rebuild me please", "r.txt", "alice", None, None).unwrap();
+        std::fs::remove_file(dir.path().join(format!("{}.meta", stored.hash.to_hex()))).unwrap();
+        drop(engine);
+
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        engine.chunk_size = 4;
+        assert!(!engine.contains(&stored.hash));
+        let repaired = engine.repair_metadata(&stored.hash, "r.txt", "alice", &stored.merkle_root.to_hex()).unwrap();
+        assert_eq!(repaired.chunks, stored.chunks);
+        assert_eq!(repaired.merkle_root, stored.merkle_root);
+        assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), b"rebuild me please");
+    }
+
+    #[test]
+    fn repair_metadata_finds_the_settings_a_file_was_stored_with() {
+        let (dir, mut engine) = engine();
+        engine.chunk_size = 4;
+        engine.chunk_algo = HashAlgo::Blake3;
+        engine.merkle_arity = 4;
+        let stored = engine.store_file(b"stored under older settings", "r.txt", "alice", None, None).unwrap();
+        std::fs::remove_file(dir.path().join(format!("{}.meta", stored.hash.to_hex()))).unwrap();
+        drop(engine);
+
+        let mut engine = StorageEngine::new(dir.path()).unwrap();
+        let err = engine.repair_metadata(&stored.hash, "r.txt", "alice", &HashValue::compute(b"x", HashAlgo::Sha256).to_hex())
+            .unwrap_err();
+        assert!(err.to_string().contains("Merkle root"));
+        assert!(!engine.contains(&stored.hash));
+
+        let repaired = engine.repair_metadata(&stored.hash, "r.txt", "alice", &stored.merkle_root.to_hex()).unwrap();
+        assert_eq!((repaired.chunk_algo(), repaired.merkle_arity), (HashAlgo::Blake3, 4));
+        assert_eq!(repaired.chunks, stored.chunks);
+        assert_eq!(repaired.merkle_root, stored.merkle_root);
+        assert_eq!(engine.retrieve_file_verified(&stored.hash).unwrap(), b"stored under older settings");
+    }
+
+    #[test]
+    fn each_meta_format_round_trips() {
+        for format in [MetaFormat::Json, MetaFormat::Bincode] {
+            let (dir, mut engine) = engine();
+            engine.meta_format = format;
+            let stored = engine.store_file(b"formatted content", "f.txt", "alice", None, None).unwrap();
+            let meta_path = dir.path().join(format!("{}.{}", stored.hash.to_hex(), format.extension()));
+            assert!(meta_path.exists());
+            drop(engine);
+
+            let engine = StorageEngine::new(dir.path()).unwrap();
+            assert_eq!(engine.metadata(&stored.hash).unwrap().chunks, stored.chunks);
+            assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), b"formatted content");
+        }
+    }
+
+    #[test]
+    fn bincode_metadata_is_smaller() {
+        let (_dir, mut engine) = engine();
+        let stored = engine.store_file(&[7u8; 64], "f.bin", "alice", None, None).unwrap();
+        let json = MetaFormat::Json.encode(&stored).unwrap();
+        let bincode = MetaFormat::Bincode.encode(&stored).unwrap();
+        assert!(bincode.len() < json.len(), "{} >= {}", bincode.len(), json.len());
+    }
+
+    #[test]
+    fn write_verification_retries_a_corrupted_write() {
+        let (_dir, mut engine) = engine();
+        engine.inject_write_corruption(1);
+        let stored = engine.store_file(b"abcdefgh", "f.txt", "alice", None, None).unwrap();
+        assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), b"abcdefgh");
+    }
+
+    #[test]
+    fn write_verification_fails_store_on_repeated_corruption() {
+        let (_dir, mut engine) = engine();
+        engine.inject_write_corruption(2);
+        let err = engine.store_file(b"abcdefgh", "f.txt", "alice", None, None).unwrap_err();
+        assert!(format!("{:#}", err).contains("corrupt after write"));
+        assert!(engine.hashes().is_empty());
+    }
+
+    #[test]
+    fn without_write_verification_corruption_goes_unnoticed_until_read() {
+        let (_dir, mut engine) = engine();
+        engine.verify_on_write = false;
+        engine.inject_write_corruption(1);
+        let stored = engine.store_file(b"abcdefgh", "f.txt", "alice", None, None).unwrap();
+        assert!(engine.retrieve_file(&stored.hash).is_err());
+    }
+
+    #[test]
+    fn small_tail_merges_into_previous_chunk() {
+        let (_dir, mut engine) = engine();
+        engine.chunk_size = 8;
+        engine.merge_tail_threshold = 3;
+        for (len, expected_chunks) in [(8, 1), (9, 1), (10, 1), (11, 2), (16, 2), (17, 2), (25, 3), (28, 4)] {
+            let data: Vec<u8> = (0..len as u8).collect();
+            let stored = engine.store_file(&data, "f.bin", "alice", None, None).unwrap();
+            assert_eq!(stored.chunks.len(), expected_chunks, "{} bytes", len);
+            assert_eq!(stored.chunk_sizes.iter().sum::<u64>(), len as u64);
+            assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), data);
+            assert_eq!(engine.read_range(&stored.hash, None, 5, len as u64).unwrap(), data[5..]);
+        }
+    }
+
+    #[test]
+    fn merge_threshold_zero_keeps_tiny_tail() {
+        let (_dir, mut engine) = engine();
+        engine.chunk_size = 8;
+        let stored = engine.store_file(&[1u8; 9], "f.bin", "alice", None, None).unwrap();
+        assert_eq!(stored.chunk_sizes, vec![8, 1]);
+    }
+
+    #[test]
+    fn store_root_changes_with_content_and_ignores_insert_order() {
+        let files: [&[u8]; 3] = [b"first file", b"second file", b"third file"];
+        let (_a, mut forward) = engine();
+        let (_b, mut backward) = engine();
+        let empty = forward.store_merkle_root();
+
+        let mut roots = Vec::new();
+        for data in files {
+            forward.store_file(data, "f", "alice", None, None).unwrap();
+            roots.push(forward.store_merkle_root());
+        }
+        for data in files.iter().rev() {
+            backward.store_file(data, "f", "alice", None, None).unwrap();
+        }
+
+        assert_ne!(roots[0], empty);
+        assert_ne!(roots[1], roots[0]);
+        assert_ne!(roots[2], roots[1]);
+        assert_eq!(backward.store_merkle_root(), roots[2]);
+
+        let removed = forward.hashes()[0].clone();
+        forward.delete_file(&removed).unwrap();
+        assert_ne!(forward.store_merkle_root(), roots[2]);
+    }
+
+    #[test]
+    fn failed_chunk_write_is_an_error_not_a_panic() {
+        let (_dir, mut engine) = engine();
+        let data = b"abcdefghij";
+        let hex = HashValue::compute(data, engine.hash_algo).to_hex();
+        // A directory where the chunk file should go; unlike a read-only
+        // directory this also fails when the tests run as root
+        let owner_dir = engine.owner_dir("alice");
+        std::fs::create_dir_all(owner_dir.join(format!("{}_1.chunk", hex))).unwrap();
+
+        let err = engine.store_file(data, "f.txt", "alice", None, None).unwrap_err();
+        assert!(err.to_string().contains("failed to write chunk 1 of f.txt"), "{}", err);
+        assert!(!engine.contains(&HashValue::compute(data, engine.hash_algo)));
+    }
+
+    #[test]
+    fn blake3_chunks_under_a_sha256_file_hash() {
+        let (_dir, mut engine) = engine();
+        engine.hash_algo = HashAlgo::Sha256;
+        engine.chunk_algo = HashAlgo::Blake3;
+        let data = b"abcdefghijklmnop";
+        let stored = engine.store_file(data, "f.txt", "alice", None, None).unwrap();
+
+        assert_eq!(stored.hash, HashValue::compute(data, HashAlgo::Sha256));
+        assert_eq!(stored.chunk_algo(), HashAlgo::Blake3);
+        assert_eq!(stored.chunks[0], HashValue::compute(b"abcd", HashAlgo::Blake3));
+        let leaves: Vec<HashValue> = data.chunks(4).map(|c| HashValue::compute(c, HashAlgo::Blake3)).collect();
+        assert_eq!(stored.merkle_root, MerkleTree::with_arity(&leaves, stored.merkle_arity).root());
+        assert_eq!(engine.retrieve_file_verified(&stored.hash).unwrap(), data);
+
+        flip_byte(&engine, &stored.hash, 2);
+        assert!(engine.retrieve_file_verified(&stored.hash).is_err());
+    }
+
+    #[test]
+    fn too_many_chunks_is_refused_before_writing() {
+        let (dir, mut engine) = engine();
+        engine.chunk_size = 1;
+        engine.max_chunks = 1000;
+        let data = vec![7u8; 64 * 1024];
+
+        let err = engine.store_file(&data, "big.bin", "alice", None, None).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::TooManyChunks { count: 65536, max: 1000 })));
+        assert!(engine.hashes().is_empty());
+        assert!(!std::fs::read_dir(dir.path()).unwrap()
+            .any(|entry| entry.unwrap().path().extension().is_some_and(|ext| ext == "chunk")));
+
+        engine.chunk_size = 4096;
+        let stored = engine.store_file(&data, "big.bin", "alice", None, None).unwrap();
+        assert_eq!(stored.chunks.len(), 16);
+    }
+
+    #[test]
+    fn chunk_status_reports_a_deleted_chunk_file() {
+        let (_dir, mut engine) = engine();
+        let stored = engine.store_file(b"abcdefghij", "f.txt", "alice", None, None).unwrap();
+        let (path, _) = engine.chunk_paths(&stored.hash).unwrap().swap_remove(1);
+        std::fs::remove_file(path).unwrap();
+
+        let status = engine.chunk_status(&stored.hash).unwrap();
+        let present: Vec<bool> = status.iter().map(|c| c.present).collect();
+        assert_eq!(present, [true, false, true]);
+        assert_eq!(status[1].size_on_disk, None);
+        assert_eq!(status[0].size_on_disk, Some(4));
+        assert_eq!(status[2].expected_size, Some(2));
+        assert_eq!(status[1].hash_prefix, stored.chunks[1].prefix(8));
+    }
+
+    #[test]
+    fn secure_delete_removes_every_chunk_file() {
+        let (_dir, mut engine) = engine();
+        assert!(!engine.secure_delete);
+        engine.secure_delete = true;
+        let stored = engine.store_file(b"abcdefghij", "f.txt", "alice", None, None).unwrap();
+        let paths: Vec<PathBuf> = engine.chunk_paths(&stored.hash).unwrap().into_iter().map(|(path, _)| path).collect();
+
+        engine.delete_file(&stored.hash).unwrap();
+        for path in &paths {
+            assert!(std::fs::read(path).is_err(), "{}", path.display());
+        }
+        assert!(!engine.contains(&stored.hash));
+    }
+
+    #[test]
+    fn overwrite_keeps_the_length_and_replaces_the_bytes() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("secret.chunk");
+        std::fs::write(&path, [0u8; 64]).unwrap();
+
+        StorageEngine::overwrite_with_random(&path).unwrap();
+        let after = std::fs::read(&path).unwrap();
+        assert_eq!(after.len(), 64);
+        assert_ne!(after, [0u8; 64]);
+    }
+
+    #[test]
+    fn verify_stream_fails_at_the_first_bad_chunk_without_reading_on() {
+        let (_dir, mut engine) = engine();
+        let data: Vec<u8> = (0..400u32).map(|i| i as u8).collect();
+        let stored = engine.store_file(&data, "big.bin", "alice", None, None).unwrap();
+        assert_eq!(stored.chunks.len(), 100);
+        flip_byte(&engine, &stored.hash, 1);
+        // Anything read past chunk 1 would fail differently
+        for (path, _) in engine.chunk_paths(&stored.hash).unwrap().into_iter().skip(2) {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let results: Vec<_> = engine.verify_stream(&stored.hash).unwrap().collect();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap(), &data[..4]);
+        let err = results[1].as_ref().unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::ChunkCorrupt { index: 1 })));
+    }
+
+    #[test]
+    fn dedicated_hash_pool_produces_the_same_metadata() {
+        let data: Vec<u8> = (0..256 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let (_global_dir, mut global) = engine();
+        global.chunk_size = 4096;
+        let expected = global.store_file(&data, "big.bin", "alice", None, None).unwrap();
+
+        let (_dir, mut engine) = engine();
+        engine.chunk_size = 4096;
+        engine.set_hash_threads(Some(2)).unwrap();
+        assert_eq!(engine.hash_pool.as_ref().unwrap().current_num_threads(), 2);
+        let stored = engine.store_file(&data, "big.bin", "alice", None, None).unwrap();
+
+        assert_eq!(stored.chunks.len(), 64);
+        assert_eq!(stored.chunks, expected.chunks);
+        assert_eq!(stored.merkle_root, expected.merkle_root);
+        assert_eq!(engine.retrieve_file_verified(&stored.hash).unwrap(), data);
+    }
+
+    #[test]
+    fn compact_removes_only_orphan_chunks() {
+        let (dir, mut engine) = engine();
+        let shared = engine.store_file(b"abcdefghij", "a.txt", "alice", None, None).unwrap();
+        engine.store_file(b"abcdefghij", "b.txt", "bob", None, None).unwrap();
+        let other = engine.store_file(b"klmnop", "c.txt", "alice", None, None).unwrap();
+
+        let orphans = [
+            (format!("{}_0.chunk", "cd".repeat(32)), &b"unknown file"[..]),
+            (format!("{}_7.chunk", shared.hash.to_hex()), &b"past the end"[..]),
+            (format!("{}_0.chunk.tmp", other.hash.to_hex()), &b"tmp"[..]),
+        ];
+        for (name, data) in &orphans {
+            std::fs::write(dir.path().join(name), data).unwrap();
+        }
+
+        let report = engine.compact().unwrap();
+        assert_eq!(report.removed_files, 3);
+        assert_eq!(report.reclaimed_bytes, 12 + 12 + 3);
+        assert!(orphans.iter().all(|(name, _)| !dir.path().join(name).exists()));
+        assert_eq!(engine.retrieve_file_verified(&shared.hash).unwrap(), b"abcdefghij");
+        assert_eq!(engine.retrieve_file_verified(&other.hash).unwrap(), b"klmnop");
+        assert_eq!(engine.compact().unwrap().removed_files, 0);
+    }
+
+    #[test]
+    fn content_bloom_has_no_false_negatives_and_persists() {
+        let (dir, mut engine) = engine();
+        let stored: Vec<HashValue> = (0..200u32)
+            .map(|i| engine.store_file(format!("bloom {}", i).as_bytes(), "f.txt", "alice", None, None).unwrap().hash)
+            .collect();
+        assert!(stored.iter().all(|hash| engine.would_dedup(hash)));
+
+        // Sized for 100k entries at 1%, so a handful of false positives at most
+        let novel = (0..1000u32)
+            .filter(|i| engine.would_dedup(&HashValue::compute(format!("novel {}", i).as_bytes(), HashAlgo::Sha256)))
+            .count();
+        assert!(novel < 50, "{} of 1000 novel hashes looked stored", novel);
+
+        let reloaded = StorageEngine::load_content_bloom(dir.path()).unwrap();
+        assert!(stored.iter().all(|hash| reloaded.contains(hash.to_hex().as_bytes())));
+    }
+
+    #[test]
+    fn small_read_buffer_retrieves_large_chunks_intact() {
+        let (_dir, mut engine) = engine();
+        engine.chunk_size = 256 * 1024;
+        engine.read_buffer_size = 7;
+        let data: Vec<u8> = (0..600 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let stored = engine.store_file(&data, "big.bin", "alice", None, None).unwrap();
+        assert_eq!(stored.chunks.len(), 3);
+        assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), data);
+
+        flip_byte(&engine, &stored.hash, 2);
+        let err = engine.retrieve_file(&stored.hash).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(FileSharingError::ChunkCorrupt { index: 2 })));
+    }
+
+    #[test]
+    fn injected_read_corruption_is_reported_by_verify_all_and_repaired() {
+        let (_dir, mut engine) = engine();
+        let stored = engine.store_file(b"abcdefghijkl", "f.txt", "alice", None, None).unwrap();
+        engine.inject_read_corruption(2);
+
+        let failures = engine.verify_all();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, stored.hash);
+        assert!(failures[0].1.contains("chunk 1"), "{}", failures[0].1);
+
+        engine.replace_chunk(&stored.hash, 1, b"efgh").unwrap();
+        assert!(engine.verify_all().is_empty());
+        assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), b"abcdefghijkl");
+    }
+
+    #[test]
+    fn injected_missing_chunk_is_restored_by_replace_chunk() {
+        let (_dir, mut engine) = engine();
+        let stored = engine.store_file(b"abcdefghijkl", "f.txt", "alice", None, None).unwrap();
+        engine.inject_missing_chunk(&stored.hash, 2).unwrap();
+        assert!(!engine.chunk_status(&stored.hash).unwrap()[2].present);
+        assert_eq!(engine.verify_all().len(), 1);
+
+        engine.replace_chunk(&stored.hash, 2, b"ijkl").unwrap();
+        assert!(engine.verify_all().is_empty());
+    }
+
+    #[test]
+    fn injected_write_failure_fails_only_the_nth_write() {
+        let (_dir, mut engine) = engine();
+        engine.inject_write_failure(2);
+        let err = engine.store_file(b"abcdefgh", "f.txt", "alice", None, None).unwrap_err();
+        assert!(format!("{:#}", err).contains("injected write failure"));
+        assert!(engine.hashes().is_empty());
+
+        let stored = engine.store_file(b"abcdefgh", "f.txt", "alice", None, None).unwrap();
+        assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), b"abcdefgh");
+    }
+
+    fn chunk_count(dir: &Path) -> usize {
+        std::fs::read_dir(dir).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "chunk"))
+            .count()
+    }
+
+    #[test]
+    fn owner_dirs_put_each_owner_in_a_subdirectory_and_reload() {
+        let (dir, mut engine) = engine();
+        engine.owner_dirs = true;
+        let alice = engine.store_file(b"alice's file", "a.txt", "alice", None, None).unwrap();
+        let bob = engine.store_file(b"bob's file", "b.txt", "bob", None, None).unwrap();
+        assert_eq!(chunk_count(&engine.owner_dir("alice")), alice.chunks.len());
+        assert_eq!(chunk_count(&engine.owner_dir("bob")), bob.chunks.len());
+        assert_eq!(chunk_count(dir.path()), 0);
+
+        let reopened = StorageEngine::new(dir.path()).unwrap();
+        assert_eq!(reopened.retrieve_file(&alice.hash).unwrap(), b"alice's file");
+        assert_eq!(reopened.retrieve_file(&bob.hash).unwrap(), b"bob's file");
+    }
+
+    #[test]
+    fn owner_dirs_dedup_across_owners_in_place() {
+        let (_dir, mut engine) = engine();
+        engine.owner_dirs = true;
+        let first = engine.store_file(b"shared content", "a.txt", "alice", None, None).unwrap();
+        engine.store_file(b"shared content", "b.txt", "bob", None, None).unwrap();
+        assert_eq!(chunk_count(&engine.owner_dir("alice")), first.chunks.len());
+        assert!(!engine.owner_dir("bob").exists());
+        assert_eq!(engine.retrieve_file(&first.hash).unwrap(), b"shared content");
+    }
+
+    #[test]
+    fn owner_names_cannot_escape_the_storage_dir() {
+        let (dir, mut engine) = engine();
+        engine.owner_dirs = true;
+        let stored = engine.store_file(b"sneaky", "f.txt", "../evil", None, None).unwrap();
+        assert_eq!(chunk_count(&engine.owner_dir("../evil")), stored.chunks.len());
+        assert!(engine.owner_dir("../evil").starts_with(dir.path()));
+        assert_eq!(engine.retrieve_file(&stored.hash).unwrap(), b"sneaky");
+    }
+
+    #[test]
+    fn similar_owner_names_get_distinct_directories() {
+        let (_dir, mut engine) = engine();
+        engine.dedup_scope = DedupScope::PerUser;
+        for (one, other) in [("bob.smith", "bob_smith"), ("a b", "a_b"), ("", "_")] {
+            assert_ne!(engine.owner_dir(one), engine.owner_dir(other));
+            let data = format!("shared by {:?} and {:?}", one, other);
+            let first = engine.store_file(data.as_bytes(), "f.txt", one, None, None).unwrap();
+            engine.store_file(data.as_bytes(), "f.txt", other, None, None).unwrap();
+            assert_eq!(chunk_count(&engine.owner_dir(one)), first.chunks.len());
+            assert_eq!(chunk_count(&engine.owner_dir(other)), first.chunks.len());
+
+            // Dropping one owner's copy leaves the other's in place
+            assert!(engine.remove_copy(&first.hash, one).unwrap());
+            assert_eq!(chunk_count(&engine.owner_dir(other)), first.chunks.len());
+            assert!(!engine.remove_copy(&first.hash, other).unwrap());
+        }
+        // The same account under different casing is still one directory
+        assert_eq!(engine.owner_dir("Alice"), engine.owner_dir("alice"));
+    }
+
+    #[test]
+    fn per_user_scope_gives_each_owner_an_independent_copy() {
+        let (_dir, mut engine) = engine();
+        engine.dedup_scope = DedupScope::PerUser;
+        let alice = engine.store_file(b"identical bytes", "a.txt", "alice", None, None).unwrap();
+        let bob = engine.store_file(b"identical bytes", "b.txt", "bob", None, None).unwrap();
+        assert_eq!(alice.hash, bob.hash);
+        assert_eq!(chunk_count(&engine.owner_dir("alice")), alice.chunks.len());
+        assert_eq!(chunk_count(&engine.owner_dir("bob")), bob.chunks.len());
+        assert_eq!(engine.dedup_stats.unique_files, 2);
+        assert_eq!(engine.dedup_stats.saved_bytes, 0);
+
+        // An owner's own repeat still dedups
+        engine.store_file(b"identical bytes", "again.txt", "alice", None, None).unwrap();
+        assert_eq!(engine.dedup_stats.saved_bytes, 15);
+
+        // Dropping one copy leaves the other readable
+        assert!(engine.remove_copy(&alice.hash, "bob").unwrap());
+        assert!(!engine.owner_dir("bob").join(format!("{}_0.chunk", bob.hash.to_hex())).exists());
+        assert_eq!(engine.retrieve_file(&alice.hash).unwrap(), b"identical bytes");
+        assert!(!engine.remove_copy(&alice.hash, "alice").unwrap());
+    }
+
+    #[test]
+    fn global_scope_shares_one_chunk_set_across_owners() {
+        let (dir, mut engine) = engine();
+        let alice = engine.store_file(b"identical bytes", "a.txt", "alice", None, None).unwrap();
+        engine.store_file(b"identical bytes", "b.txt", "bob", None, None).unwrap();
+        assert_eq!(chunk_count(dir.path()), alice.chunks.len());
+        assert_eq!(engine.dedup_stats.unique_files, 1);
+        assert_eq!(engine.dedup_stats.saved_bytes, 15);
+    }
+
+    #[test]
+    fn each_owner_reads_and_verifies_their_own_copy() {
+        let (_dir, mut engine) = engine();
+        engine.dedup_scope = DedupScope::PerUser;
+        engine.chunk_size = 4;
+        let hash = engine.store_file(b"abcdefghijkl", "a.txt", "alice", None, None).unwrap().hash;
+        engine.store_file(b"abcdefghijkl", "b.txt", "bob", None, None).unwrap();
+        engine.inject_corrupt_copy_chunk(&hash, "alice", 1).unwrap();
+
+        assert!(engine.retrieve_copy_verified(&hash, Some("alice")).is_err());
+        assert_eq!(engine.retrieve_copy_verified(&hash, Some("bob")).unwrap(), b"abcdefghijkl");
+        assert!(engine.read_range(&hash, Some("alice"), 4, 8).is_err());
+        assert_eq!(engine.read_range(&hash, Some("bob"), 4, 8).unwrap(), b"efgh");
+
+        // Every copy is checked, and the failure names alice's
+        let failures = engine.verify_files(std::slice::from_ref(&hash), None).remove(0);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(Some(failures[0].0.clone()), engine.copy_id(&hash, Some("alice")));
+        assert_ne!(engine.copy_id(&hash, Some("alice")), engine.copy_id(&hash, Some("bob")));
+        assert!(engine.verify_files(std::slice::from_ref(&hash), Some("bob")).remove(0).is_empty());
+
+        engine.replace_chunk(&hash, 1, b"efgh").unwrap();
+        assert!(engine.verify_all().is_empty());
+    }
+}
@@ -2,4 +2,7 @@
 // Storage Module
 // ============================================================================
 
-pub mod engine;
\ No newline at end of file
+pub mod archive;
+pub mod engine;
+pub mod range;
+pub mod stream;
\ No newline at end of file
@@ -0,0 +1,65 @@
+// ============================================================================
+// Byte Ranges: `Range: bytes=...` requests against a stored file
+// ============================================================================
+
+// What a range request resolves to for a file of a given size. Maps onto HTTP
+// 200 (Full), 206 (Partial) and 416 (Unsatisfiable).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeRequest {
+    Full,
+    Partial { start: u64, end: u64 }, // inclusive, as in Content-Range
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    // Only single ranges are honoured; multi-range and malformed headers fall
+    // back to the full content, which the HTTP spec allows
+    pub fn parse(header: Option<&str>, size: u64) -> Self {
+        let spec = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return RangeRequest::Full,
+        };
+        let (first, last) = match spec.split_once('-') {
+            Some(parts) => parts,
+            None => return RangeRequest::Full,
+        };
+        let first = first.trim();
+        let last = last.trim();
+
+        // bytes=-N is the last N bytes
+        if first.is_empty() {
+            return match last.parse::<u64>() {
+                Ok(0) => RangeRequest::Unsatisfiable,
+                Ok(_) if size == 0 => RangeRequest::Unsatisfiable,
+                Ok(n) => RangeRequest::Partial { start: size.saturating_sub(n), end: size - 1 },
+                Err(_) => RangeRequest::Full,
+            };
+        }
+
+        let start = match first.parse::<u64>() {
+            Ok(start) => start,
+            Err(_) => return RangeRequest::Full,
+        };
+        let end = if last.is_empty() {
+            u64::MAX
+        } else {
+            match last.parse::<u64>() {
+                Ok(end) if end >= start => end,
+                _ => return RangeRequest::Full,
+            }
+        };
+        if start >= size {
+            return RangeRequest::Unsatisfiable;
+        }
+        RangeRequest::Partial { start, end: end.min(size - 1) }
+    }
+
+    // Value for the Content-Range header; None for full responses
+    pub fn content_range(&self, size: u64) -> Option<String> {
+        match self {
+            RangeRequest::Full => None,
+            RangeRequest::Partial { start, end } => Some(format!("bytes {}-{}/{}", start, end, size)),
+            RangeRequest::Unsatisfiable => Some(format!("bytes */{}", size)),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// ============================================================================
+// Streaming Chunk Reader with optional Read-Ahead
+// ============================================================================
+
+use crate::crypto::hash::HashValue;
+use crate::error::FileSharingError;
+use anyhow::Result;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
+
+pub const DEFAULT_READ_AHEAD: usize = 2;
+pub const DEFAULT_READ_BUFFER: usize = 64 * 1024;
+
+// Read one chunk from disk, optionally checking it against its recorded hash
+pub fn read_chunk(path: &Path, expected: &HashValue, index: usize, verify: bool) -> Result<Vec<u8>> {
+    let data = std::fs::read(path)?;
+    if verify && HashValue::compute(&data, expected.algo) != *expected {
+        return Err(FileSharingError::ChunkCorrupt { index }.into());
+    }
+    Ok(data)
+}
+
+// Append one chunk to `out`, reading `buffer_size` bytes at a time so a large
+// chunk never needs a second full-size allocation of its own
+pub fn read_chunk_into(
+    path: &Path,
+    expected: &HashValue,
+    index: usize,
+    verify: bool,
+    out: &mut Vec<u8>,
+    buffer_size: usize,
+) -> Result<()> {
+    let start = out.len();
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; buffer_size.max(1)];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        out.extend_from_slice(&buffer[..n]);
+    }
+    if verify && HashValue::compute(&out[start..], expected.algo) != *expected {
+        out.truncate(start);
+        return Err(FileSharingError::ChunkCorrupt { index }.into());
+    }
+    Ok(())
+}
+
+enum Source {
+    Inline {
+        chunks: std::vec::IntoIter<(PathBuf, HashValue)>,
+        index: usize,
+    },
+    // A reader thread keeps up to N chunks buffered ahead of the consumer
+    Prefetch(Receiver<Result<Vec<u8>>>),
+}
+
+// Yields a file's chunks in order; stops after the first error
+pub struct ChunkStream {
+    source: Source,
+    verify: bool,
+    failed: bool,
+}
+
+impl ChunkStream {
+    pub fn new(chunks: Vec<(PathBuf, HashValue)>, verify: bool, read_ahead: usize) -> Self {
+        if read_ahead == 0 {
+            return Self {
+                source: Source::Inline { chunks: chunks.into_iter(), index: 0 },
+                verify,
+                failed: false,
+            };
+        }
+
+        let (tx, rx) = sync_channel(read_ahead);
+        thread::spawn(move || {
+            for (index, (path, expected)) in chunks.iter().enumerate() {
+                let result = read_chunk(path, expected, index, verify);
+                let stop = result.is_err();
+                // A dropped receiver means the consumer is gone
+                if tx.send(result).is_err() || stop {
+                    break;
+                }
+            }
+        });
+
+        Self { source: Source::Prefetch(rx), verify, failed: false }
+    }
+}
+
+impl Iterator for ChunkStream {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let item = match &mut self.source {
+            Source::Inline { chunks, index } => {
+                let (path, expected) = chunks.next()?;
+                let result = read_chunk(&path, &expected, *index, self.verify);
+                *index += 1;
+                result
+            }
+            Source::Prefetch(rx) => rx.recv().ok()?,
+        };
+        self.failed = item.is_err();
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::HashAlgo;
+    use tempfile::TempDir;
+
+    fn write_chunks(dir: &TempDir, count: usize) -> Vec<(PathBuf, HashValue)> {
+        (0..count).map(|i| {
+            let data: Vec<u8> = (0..100).map(|b| (b * 7 + i) as u8).collect();
+            let path = dir.path().join(format!("{}.chunk", i));
+            std::fs::write(&path, &data).unwrap();
+            (path, HashValue::compute(&data, HashAlgo::Sha256))
+        }).collect()
+    }
+
+    fn collect(stream: ChunkStream) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for chunk in stream {
+            out.extend(chunk?);
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn read_ahead_yields_the_same_bytes() {
+        let dir = TempDir::new().unwrap();
+        let chunks = write_chunks(&dir, 10);
+        let inline = collect(ChunkStream::new(chunks.clone(), true, 0)).unwrap();
+        assert_eq!(inline.len(), 1000);
+        for read_ahead in [1, 3, 16] {
+            assert_eq!(collect(ChunkStream::new(chunks.clone(), true, read_ahead)).unwrap(), inline);
+        }
+    }
+
+    #[test]
+    fn read_ahead_still_verifies_and_stops_at_the_bad_chunk() {
+        let dir = TempDir::new().unwrap();
+        let chunks = write_chunks(&dir, 5);
+        std::fs::write(&chunks[2].0, b"tampered").unwrap();
+
+        for read_ahead in [0, 2] {
+            let results: Vec<_> = ChunkStream::new(chunks.clone(), true, read_ahead).collect();
+            assert_eq!(results.len(), 3);
+            assert!(results[..2].iter().all(|r| r.is_ok()));
+            let err = results[2].as_ref().unwrap_err();
+            assert!(matches!(err.downcast_ref(), Some(FileSharingError::ChunkCorrupt { index: 2 })));
+        }
+    }
+}